@@ -0,0 +1,131 @@
+//! Command-line argument parsing for the sandbox front-end
+
+pub const USAGE: &str = "Usage: phantom_sandbox <ROM_PATH> [--scale <N>] [--region <ntsc|pal>] \
+                         [--fullscreen] [--mute] [--trace]";
+
+const DEFAULT_SCALE: u32 = 3;
+
+#[derive(Debug, PartialEq)]
+pub enum Region {
+    Ntsc,
+    Pal,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Options {
+    pub rom_path: String,
+    pub scale: u32,
+    /// Overrides the TV system from the ROM header
+    pub region: Option<Region>,
+    pub fullscreen: bool,
+    pub mute: bool,
+    pub trace: bool,
+}
+
+/// Parses the sandbox arguments (without the program name).
+pub fn parse_args<I>(args: I) -> Result<Options, String>
+where
+    I: IntoIterator<Item = String>,
+{
+    let mut rom_path = None;
+    let mut scale = DEFAULT_SCALE;
+    let mut region = None;
+    let mut fullscreen = false;
+    let mut mute = false;
+    let mut trace = false;
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--scale" => {
+                let value = args.next().ok_or("Missing value for --scale")?;
+                scale = match value.parse::<u32>() {
+                    Ok(scale) if scale > 0 => scale,
+                    _ => return Err(format!("Invalid scale '{}'", value)),
+                };
+            }
+            "--region" => {
+                let value = args.next().ok_or("Missing value for --region")?;
+                region = match value.to_lowercase().as_str() {
+                    "ntsc" => Some(Region::Ntsc),
+                    "pal" => Some(Region::Pal),
+                    _ => return Err(format!("Invalid region '{}'", value)),
+                };
+            }
+            "--fullscreen" => fullscreen = true,
+            "--mute" => mute = true,
+            "--trace" => trace = true,
+            _ if arg.starts_with("--") => return Err(format!("Unknown option '{}'", arg)),
+            _ if rom_path.is_none() => rom_path = Some(arg),
+            _ => return Err(format!("Unexpected argument '{}'", arg)),
+        }
+    }
+
+    Ok(Options {
+        rom_path: rom_path.ok_or("Missing ROM path")?,
+        scale,
+        region,
+        fullscreen,
+        mute,
+        trace,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(raw: &[&str]) -> Vec<String> {
+        raw.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn test_parse_args_defaults() {
+        let options = parse_args(args(&["game.nes"])).unwrap();
+        assert_eq!(
+            options,
+            Options {
+                rom_path: "game.nes".to_string(),
+                scale: 3,
+                region: None,
+                fullscreen: false,
+                mute: false,
+                trace: false,
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_args_all_options() {
+        let options = parse_args(args(&[
+            "--scale",
+            "2",
+            "game.nes",
+            "--region",
+            "PAL",
+            "--fullscreen",
+            "--mute",
+            "--trace",
+        ]))
+        .unwrap();
+        assert_eq!(options.rom_path, "game.nes");
+        assert_eq!(options.scale, 2);
+        assert_eq!(options.region, Some(Region::Pal));
+        assert!(options.fullscreen);
+        assert!(options.mute);
+        assert!(options.trace);
+    }
+
+    #[test]
+    fn test_parse_args_rom_path_required() {
+        assert!(parse_args(args(&[])).is_err());
+        assert!(parse_args(args(&["--mute"])).is_err());
+    }
+
+    #[test]
+    fn test_parse_args_invalid_scale() {
+        assert!(parse_args(args(&["game.nes", "--scale", "0"])).is_err());
+        assert!(parse_args(args(&["game.nes", "--scale", "big"])).is_err());
+        assert!(parse_args(args(&["game.nes", "--scale"])).is_err());
+    }
+}
@@ -1,44 +1,82 @@
+mod args;
+mod tracing;
+
+use phantom::nes::apu::audio_buffer::AudioBuffer;
+use phantom::nes::apu::DEFAULT_SAMPLE_RATE;
 use phantom::nes::bus::Bus;
-use phantom::nes::cartridge::Rom;
+use phantom::nes::cartridge::{Rom, TvSystem};
 use phantom::nes::cpu::Cpu;
-use phantom::nes::render::frame::Frame;
 use phantom::nes::ppu::Ppu;
-use phantom::nes::render;
 use phantom::nes::joypad;
 
+use sdl2::audio::{AudioCallback, AudioSpecDesired};
 use sdl2::event::Event;
 use sdl2::keyboard::Keycode;
 use sdl2::pixels::PixelFormatEnum;
 use sdl2::EventPump;
 
-use std::collections::HashMap;
+struct ApuAudio {
+    buffer: AudioBuffer,
+}
+
+impl AudioCallback for ApuAudio {
+    type Channel = f32;
+
+    fn callback(&mut self, out: &mut [f32]) {
+        self.buffer.pull(out);
+    }
+}
 
 fn main() {
+    let options = args::parse_args(std::env::args().skip(1)).unwrap_or_else(|err| {
+        eprintln!("{}\n{}", err, args::USAGE);
+        std::process::exit(1);
+    });
+
+    // Load game
+    let raw_rom = std::fs::read(&options.rom_path).unwrap();
+    let mut rom = Rom::new(&raw_rom).unwrap();
+    if let Some(region) = &options.region {
+        rom.tv_system = match region {
+            args::Region::Ntsc => TvSystem::Ntsc,
+            args::Region::Pal => TvSystem::Pal,
+        };
+    }
+    if let Err(err) = rom.check_mapper() {
+        eprintln!("Warning: {}, running the game as mapper 0", err);
+    }
+
+    println!(
+        "Phantom NES: {} ({:?}, {}x{})",
+        options.rom_path,
+        rom.tv_system,
+        options.scale,
+        if options.mute { ", muted" } else { "" }
+    );
+
     // init sdl2
     let sdl_context = sdl2::init().unwrap();
     let video_subsystem = sdl_context.video().unwrap();
-    let window = video_subsystem
-        .window("Phantom NES", (256.0 * 3.0) as u32, (240.0 * 3.0) as u32)
-        .position_centered()
-        .build()
-        .unwrap();
+    let mut window_builder =
+        video_subsystem.window("Phantom NES", 256 * options.scale, 240 * options.scale);
+    window_builder.position_centered();
+    if options.fullscreen {
+        window_builder.fullscreen_desktop();
+    }
+    let window = window_builder.build().unwrap();
 
     let mut canvas = window.into_canvas().present_vsync().build().unwrap();
     let mut event_pump = sdl_context.event_pump().unwrap();
-    canvas.set_scale(3.0, 3.0).unwrap();
+    canvas
+        .set_scale(options.scale as f32, options.scale as f32)
+        .unwrap();
 
     let creator = canvas.texture_creator();
     let mut texture = creator
         .create_texture_target(PixelFormatEnum::RGB24, 256, 240)
         .unwrap();
 
-    // Load game
-    let raw_rom = std::fs::read("pacman.nes").unwrap();
-    let rom = Rom::new(&raw_rom).unwrap();
-
-    let mut frame = Frame::new();
-
-    let mut keymap = HashMap::new();
+    let mut keymap = joypad::JoypadConfig::new();
     keymap.insert(Keycode::Down, joypad::JoypadButton::DOWN);
     keymap.insert(Keycode::Up, joypad::JoypadButton::UP);
     keymap.insert(Keycode::Right, joypad::JoypadButton::RIGHT);
@@ -48,40 +86,75 @@ fn main() {
     keymap.insert(Keycode::A, joypad::JoypadButton::BUTTON_A);
     keymap.insert(Keycode::S, joypad::JoypadButton::BUTTON_B);
 
+    // Second player
+    let mut keymap2 = joypad::JoypadConfig::new();
+    keymap2.insert(Keycode::K, joypad::JoypadButton::DOWN);
+    keymap2.insert(Keycode::I, joypad::JoypadButton::UP);
+    keymap2.insert(Keycode::L, joypad::JoypadButton::RIGHT);
+    keymap2.insert(Keycode::J, joypad::JoypadButton::LEFT);
+    keymap2.insert(Keycode::U, joypad::JoypadButton::SELECT);
+    keymap2.insert(Keycode::O, joypad::JoypadButton::START);
+    keymap2.insert(Keycode::N, joypad::JoypadButton::BUTTON_A);
+    keymap2.insert(Keycode::M, joypad::JoypadButton::BUTTON_B);
+    let keymaps = [keymap, keymap2];
+
     // Game cycle logic
-    let bus = Bus::new(rom, move |ppu: &Ppu, joypad: &mut joypad::Joypad| {
-        render::render(ppu, &mut frame);
-        texture.update(None, &frame.data(), 256 * 3).unwrap();
+    let mut bus = Bus::with_two_joypads(
+        rom,
+        move |ppu: &Ppu, joypad1: &mut joypad::Joypad, joypad2: &mut joypad::Joypad| {
+            texture.update(None, ppu.frame().data(), 256 * 3).unwrap();
 
-        canvas.copy(&texture, None, None).unwrap();
-        canvas.present();
+            canvas.copy(&texture, None, None).unwrap();
+            canvas.present();
 
-        handle_user_input(joypad, &keymap, &mut event_pump);
-    });
+            handle_user_input([joypad1, joypad2], &keymaps, &mut event_pump);
+        },
+    );
+
+    // Keep the device alive for the whole run, dropping it stops the playback
+    let _audio_device = if options.mute {
+        None
+    } else {
+        let audio_subsystem = sdl_context.audio().unwrap();
+        let desired_spec = AudioSpecDesired {
+            freq: Some(DEFAULT_SAMPLE_RATE as i32),
+            channels: Some(1),
+            samples: Some(1024),
+        };
+        let buffer = bus.apu().audio_output();
+        let device = audio_subsystem
+            .open_playback(None, &desired_spec, |_spec| ApuAudio { buffer })
+            .unwrap();
+        bus.apu_mut().set_sample_rate(device.spec().freq as u32);
+        device.resume();
+        Some(device)
+    };
 
     let mut cpu = Cpu::new(bus);
 
     cpu.reset();
-    cpu.run();
+    if options.trace {
+        cpu.run_with_callback(|cpu| println!("{}", tracing::trace(cpu)));
+    } else {
+        cpu.run();
+    }
 }
 
-fn handle_user_input(joypad: &mut joypad::Joypad, keymap: &HashMap<Keycode, joypad::JoypadButton>, event_pump: &mut EventPump) {
-    event_pump.poll_iter().for_each(|event| {
-        match event {
+fn handle_user_input(
+    mut joypads: [&mut joypad::Joypad; 2],
+    keymaps: &[joypad::JoypadConfig<Keycode>; 2],
+    event_pump: &mut EventPump,
+) {
+    for event in event_pump.poll_iter() {
+        let (keycode, pressed) = match event {
             Event::Quit { .. }
             | Event::KeyDown { keycode: Some(Keycode::Escape), .. } => std::process::exit(0),
-            Event::KeyDown { keycode, .. } => {
-                if let Some(joypad_button) = keymap.get(&keycode.unwrap_or(Keycode::Ampersand)) {
-                    joypad.set_button_status(*joypad_button, true);
-                }
-            }
-            Event::KeyUp { keycode, .. } => {
-                if let Some(joypad_button) = keymap.get(&keycode.unwrap_or(Keycode::Ampersand)) {
-                    joypad.set_button_status(*joypad_button, false);
-                }
-            }
-            _ => { /* Do Nothing */ }
+            Event::KeyDown { keycode: Some(keycode), .. } => (keycode, true),
+            Event::KeyUp { keycode: Some(keycode), .. } => (keycode, false),
+            _ => continue,
+        };
+        for (joypad, keymap) in joypads.iter_mut().zip(keymaps.iter()) {
+            keymap.apply(joypad, &keycode, pressed);
         }
-    });
+    }
 }
-
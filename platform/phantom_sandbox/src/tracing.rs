@@ -0,0 +1,19 @@
+//! Instruction tracing in the nestest log format, e.g. to diff against a reference log
+use phantom::nes::cpu::Cpu;
+use phantom::nes::debug::disassemble_instruction;
+
+/// Formats the instruction about to run along with the register state:
+/// `C000  JMP $C5F5                       A:00 X:00 Y:00 P:24 SP:FD`
+pub fn trace(cpu: &Cpu) -> String {
+    let (instruction, _) = disassemble_instruction(cpu, cpu.program_counter());
+    format!(
+        "{:04X}  {:<32}  A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X}",
+        cpu.program_counter(),
+        instruction,
+        cpu.register_a(),
+        cpu.register_x(),
+        cpu.register_y(),
+        cpu.status().bits(),
+        cpu.stack_pointer()
+    )
+}
@@ -0,0 +1,112 @@
+//! Thread-safe sample queue between the emulation and the audio device
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+pub const DEFAULT_CAPACITY: usize = 4096;
+
+struct SampleQueue {
+    samples: VecDeque<f32>,
+    capacity: usize,
+    last_sample: f32,
+}
+
+/// Ring buffer of audio samples. The emulation pushes samples while the audio thread
+/// pulls them; clones share the same queue so each side can own one.
+/// On overrun the oldest samples are dropped to keep latency bounded,
+/// and on underrun the last pulled sample is repeated to avoid pops.
+#[derive(Clone)]
+pub struct AudioBuffer {
+    queue: Arc<Mutex<SampleQueue>>,
+}
+
+impl AudioBuffer {
+    pub fn new(capacity: usize) -> Self {
+        AudioBuffer {
+            queue: Arc::new(Mutex::new(SampleQueue {
+                samples: VecDeque::with_capacity(capacity),
+                capacity,
+                last_sample: 0.0,
+            })),
+        }
+    }
+
+    pub fn push(&self, sample: f32) {
+        let mut queue = self.queue.lock().unwrap();
+        if queue.samples.len() == queue.capacity {
+            queue.samples.pop_front();
+        }
+        queue.samples.push_back(sample);
+    }
+
+    /// Fills `output` with the queued samples, repeating the last one if there are not enough
+    pub fn pull(&self, output: &mut [f32]) {
+        let mut queue = self.queue.lock().unwrap();
+        for sample in output.iter_mut() {
+            if let Some(next) = queue.samples.pop_front() {
+                queue.last_sample = next;
+            }
+            *sample = queue.last_sample;
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.queue.lock().unwrap().samples.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Default for AudioBuffer {
+    fn default() -> Self {
+        AudioBuffer::new(DEFAULT_CAPACITY)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_audio_buffer_push_and_pull_in_order() {
+        let buffer = AudioBuffer::new(8);
+        let producer = buffer.clone();
+        for i in 0..5 {
+            producer.push(i as f32 * 0.1);
+        }
+        assert_eq!(buffer.len(), 5);
+
+        let mut output = [0.0; 5];
+        buffer.pull(&mut output);
+        assert_eq!(output, [0.0, 0.1, 0.2, 0.3, 0.4]);
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn test_audio_buffer_underrun_repeats_last_sample() {
+        let buffer = AudioBuffer::new(8);
+        let mut output = [1.0; 2];
+        buffer.pull(&mut output);
+        assert_eq!(output, [0.0, 0.0]);
+
+        buffer.push(0.5);
+        buffer.push(0.25);
+        let mut output = [0.0; 4];
+        buffer.pull(&mut output);
+        assert_eq!(output, [0.5, 0.25, 0.25, 0.25]);
+    }
+
+    #[test]
+    fn test_audio_buffer_overrun_drops_oldest() {
+        let buffer = AudioBuffer::new(3);
+        for i in 0..5 {
+            buffer.push(i as f32);
+        }
+        assert_eq!(buffer.len(), 3);
+
+        let mut output = [0.0; 3];
+        buffer.pull(&mut output);
+        assert_eq!(output, [2.0, 3.0, 4.0]);
+    }
+}
@@ -0,0 +1,240 @@
+const IRQ_ENABLED_FLAG: u8 = 0b10000000;
+const LOOP_FLAG: u8 = 0b01000000;
+
+// Timer periods in CPU cycles (NTSC)
+const DMC_PERIODS: [u16; 16] = [
+    428, 380, 340, 320, 286, 254, 226, 214, 190, 160, 142, 128, 106, 84, 72, 54,
+];
+
+/// Delta modulation channel, plays 1-bit delta encoded samples that it fetches
+/// from PRG space through DMA, stalling the CPU.
+/// RAM addresses: 0x4010-0x4013.
+pub struct Dmc {
+    irq_enabled: bool,
+    irq_pending: bool,
+    loop_flag: bool,
+    timer_period: u16,
+    timer: u16,
+    // 7-bit output level
+    output_level: u8,
+
+    // Memory reader
+    sample_address: u16,
+    sample_length: u16,
+    current_address: u16,
+    bytes_remaining: u16,
+    sample_buffer: Option<u8>,
+
+    // Output unit
+    shift_register: u8,
+    bits_remaining: u8,
+    silence: bool,
+}
+
+impl Dmc {
+    pub fn new() -> Self {
+        Dmc {
+            irq_enabled: false,
+            irq_pending: false,
+            loop_flag: false,
+            timer_period: DMC_PERIODS[0],
+            timer: 0,
+            output_level: 0,
+            sample_address: 0xC000,
+            sample_length: 1,
+            current_address: 0xC000,
+            bytes_remaining: 0,
+            sample_buffer: None,
+            shift_register: 0,
+            bits_remaining: 8,
+            silence: true,
+        }
+    }
+
+    /// IRQ enabled (I), loop (L), rate index (RRRR). Bits: IL-- RRRR.
+    pub fn write_control(&mut self, data: u8) {
+        self.irq_enabled = data & IRQ_ENABLED_FLAG != 0;
+        if !self.irq_enabled {
+            self.irq_pending = false;
+        }
+        self.loop_flag = data & LOOP_FLAG != 0;
+        self.timer_period = DMC_PERIODS[(data & 0b1111) as usize];
+    }
+
+    /// Direct load of the output level (DDDDDDD). Bits: -DDD DDDD.
+    pub fn write_direct_load(&mut self, data: u8) {
+        self.output_level = data & 0b0111_1111;
+    }
+
+    /// Sample address, %11AAAAAA.AA000000 (0xC000-0xFFC0)
+    pub fn write_sample_address(&mut self, data: u8) {
+        self.sample_address = 0xC000 | (data as u16) << 6;
+    }
+
+    /// Sample length, %LLLL.LLLL0001 bytes
+    pub fn write_sample_length(&mut self, data: u8) {
+        self.sample_length = (data as u16) << 4 | 1;
+    }
+
+    /// Disabling stops the sample, enabling restarts it only if it had finished.
+    /// Either way the DMC interrupt is acknowledged.
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.irq_pending = false;
+        if !enabled {
+            self.bytes_remaining = 0;
+        } else if self.bytes_remaining == 0 {
+            self.restart();
+        }
+    }
+
+    /// Whether a sample is still being fetched, as reported by 0x4015
+    pub fn is_active(&self) -> bool {
+        self.bytes_remaining > 0
+    }
+
+    pub fn irq_pending(&self) -> bool {
+        self.irq_pending
+    }
+
+    /// Address of the next sample byte when the sample buffer needs to be refilled through DMA
+    pub fn dma_address(&self) -> Option<u16> {
+        if self.sample_buffer.is_none() && self.bytes_remaining > 0 {
+            Some(self.current_address)
+        } else {
+            None
+        }
+    }
+
+    /// Fills the sample buffer with the byte fetched from `dma_address`
+    pub fn load_sample_byte(&mut self, data: u8) {
+        self.sample_buffer = Some(data);
+        // The address wraps around to 0x8000, not 0x0000
+        self.current_address = self.current_address.checked_add(1).unwrap_or(0x8000);
+        self.bytes_remaining -= 1;
+        if self.bytes_remaining == 0 {
+            if self.loop_flag {
+                self.restart();
+            } else if self.irq_enabled {
+                self.irq_pending = true;
+            }
+        }
+    }
+
+    /// Clocked every CPU cycle
+    pub fn clock_timer(&mut self) {
+        if self.timer > 0 {
+            self.timer -= 1;
+            return;
+        }
+        self.timer = self.timer_period - 1;
+
+        if !self.silence {
+            // Bit set: +2, bit clear: -2, as long as the level stays in 0-127
+            if self.shift_register & 1 == 1 {
+                if self.output_level <= 125 {
+                    self.output_level += 2;
+                }
+            } else if self.output_level >= 2 {
+                self.output_level -= 2;
+            }
+        }
+        self.shift_register >>= 1;
+
+        self.bits_remaining -= 1;
+        if self.bits_remaining == 0 {
+            self.bits_remaining = 8;
+            match self.sample_buffer.take() {
+                Some(sample) => {
+                    self.silence = false;
+                    self.shift_register = sample;
+                }
+                None => self.silence = true,
+            }
+        }
+    }
+
+    /// Current output level, 0-127
+    pub fn output(&self) -> u8 {
+        self.output_level
+    }
+
+    fn restart(&mut self) {
+        self.current_address = self.sample_address;
+        self.bytes_remaining = self.sample_length;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dmc_sample_address_and_length() {
+        let mut dmc = Dmc::new();
+        dmc.write_sample_address(0xFF);
+        dmc.write_sample_length(0x01);
+        dmc.set_enabled(true);
+        assert_eq!(dmc.dma_address(), Some(0xFFC0));
+        assert!(dmc.is_active());
+
+        for _ in 0..16 {
+            dmc.load_sample_byte(0);
+            dmc.sample_buffer = None;
+        }
+        assert_eq!(dmc.dma_address(), Some(0xFFD0));
+        dmc.load_sample_byte(0);
+        assert!(!dmc.is_active());
+        assert_eq!(dmc.dma_address(), None);
+    }
+
+    #[test]
+    fn test_dmc_address_wraps_to_8000() {
+        let mut dmc = Dmc::new();
+        dmc.current_address = 0xFFFF;
+        dmc.bytes_remaining = 2;
+        dmc.load_sample_byte(0);
+        dmc.sample_buffer = None;
+        assert_eq!(dmc.dma_address(), Some(0x8000));
+    }
+
+    #[test]
+    fn test_dmc_output_level_follows_sample_bits() {
+        let mut dmc = Dmc::new();
+        dmc.write_control(0x0F); // fastest rate, 54 cycles per bit
+        dmc.write_direct_load(64);
+        dmc.set_enabled(true);
+        dmc.load_sample_byte(0b0000_0011);
+
+        // The output cycle that was already running is silent
+        while dmc.sample_buffer.is_some() {
+            dmc.clock_timer();
+        }
+        assert_eq!(dmc.output(), 64);
+        (0..54).for_each(|_| dmc.clock_timer());
+        assert_eq!(dmc.output(), 66);
+        (0..54).for_each(|_| dmc.clock_timer());
+        assert_eq!(dmc.output(), 68);
+        (0..54).for_each(|_| dmc.clock_timer());
+        assert_eq!(dmc.output(), 66);
+    }
+
+    #[test]
+    fn test_dmc_irq_at_sample_end() {
+        let mut dmc = Dmc::new();
+        dmc.write_control(IRQ_ENABLED_FLAG);
+        dmc.set_enabled(true);
+        dmc.load_sample_byte(0);
+        assert!(dmc.irq_pending());
+
+        dmc.set_enabled(true);
+        assert!(!dmc.irq_pending());
+
+        // No IRQ when looping, the sample restarts instead
+        let mut dmc = Dmc::new();
+        dmc.write_control(IRQ_ENABLED_FLAG | LOOP_FLAG);
+        dmc.set_enabled(true);
+        dmc.load_sample_byte(0);
+        assert!(!dmc.irq_pending());
+        assert!(dmc.is_active());
+    }
+}
@@ -0,0 +1,92 @@
+const LOOP_FLAG: u8 = 0b00100000;
+const CONSTANT_VOLUME_FLAG: u8 = 0b00010000;
+const VOLUME_MASK: u8 = 0b00001111;
+
+/// Volume envelope of the pulse and noise channels: either a constant volume
+/// or a sawtooth decaying from 15, clocked by the quarter frame signal.
+pub struct Envelope {
+    start: bool,
+    looping: bool,
+    constant_volume: bool,
+    volume: u8,
+    divider: u8,
+    decay_level: u8,
+}
+
+impl Envelope {
+    pub fn new() -> Self {
+        Envelope {
+            start: false,
+            looping: false,
+            constant_volume: false,
+            volume: 0,
+            divider: 0,
+            decay_level: 0,
+        }
+    }
+
+    /// Loop (L), constant volume (C), volume/envelope period (VVVV). Bits: --LC VVVV.
+    pub fn write(&mut self, data: u8) {
+        self.looping = data & LOOP_FLAG != 0;
+        self.constant_volume = data & CONSTANT_VOLUME_FLAG != 0;
+        self.volume = data & VOLUME_MASK;
+    }
+
+    /// Restarts the decay on the next clock, done when the length counter is loaded
+    pub fn restart(&mut self) {
+        self.start = true;
+    }
+
+    pub fn clock(&mut self) {
+        if self.start {
+            self.start = false;
+            self.decay_level = 15;
+            self.divider = self.volume;
+        } else if self.divider == 0 {
+            self.divider = self.volume;
+            if self.decay_level > 0 {
+                self.decay_level -= 1;
+            } else if self.looping {
+                self.decay_level = 15;
+            }
+        } else {
+            self.divider -= 1;
+        }
+    }
+
+    pub fn output(&self) -> u8 {
+        if self.constant_volume {
+            self.volume
+        } else {
+            self.decay_level
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_envelope_decay() {
+        let mut envelope = Envelope::new();
+        envelope.write(0b0000_0001); // decay every other clock, no loop
+        envelope.restart();
+        envelope.clock();
+        assert_eq!(envelope.output(), 15);
+
+        (0..2).for_each(|_| envelope.clock());
+        assert_eq!(envelope.output(), 14);
+        (0..28).for_each(|_| envelope.clock());
+        assert_eq!(envelope.output(), 0);
+        (0..2).for_each(|_| envelope.clock());
+        assert_eq!(envelope.output(), 0);
+
+        envelope.write(0b0010_0001); // loop
+        (0..2).for_each(|_| envelope.clock());
+        assert_eq!(envelope.output(), 15);
+
+        envelope.write(0b0001_0111); // constant volume
+        assert_eq!(envelope.output(), 7);
+    }
+}
@@ -0,0 +1,238 @@
+// https://wiki.nesdev.org/w/index.php/APU_Frame_Counter
+// Steps are expressed in CPU cycles since the sequence started (NTSC)
+const FOUR_STEP_SEQUENCE: [usize; 4] = [7457, 14913, 22371, 29829];
+const FIVE_STEP_SEQUENCE: [usize; 5] = [7457, 14913, 22371, 29829, 37281];
+
+const MODE_FLAG: u8 = 0b10000000;
+const IRQ_INHIBIT_FLAG: u8 = 0b01000000;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum FrameCounterMode {
+    FourStep,
+    FiveStep,
+}
+
+/// Clock produced by a frame counter step. A half frame clock also clocks the quarter frame units.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum FrameClock {
+    Quarter,
+    Half,
+}
+
+/// APU frame counter (frame sequencer). Generates the quarter and half frame clocks
+/// used by the channels' units and, in 4-step mode, the frame interrupt.
+/// RAM address: 0x4017 - Bits: MI-- ----.
+pub struct FrameCounter {
+    mode: FrameCounterMode,
+    irq_inhibit: bool,
+    irq_pending: bool,
+    cycles: usize,
+    step: usize,
+    odd_cycle: bool,
+    reset_delay: Option<u8>,
+}
+
+impl FrameCounter {
+    pub fn new() -> Self {
+        FrameCounter {
+            mode: FrameCounterMode::FourStep,
+            irq_inhibit: false,
+            irq_pending: false,
+            cycles: 0,
+            step: 0,
+            odd_cycle: false,
+            reset_delay: None,
+        }
+    }
+
+    pub fn write(&mut self, data: u8) {
+        self.mode = if data & MODE_FLAG == 0 {
+            FrameCounterMode::FourStep
+        } else {
+            FrameCounterMode::FiveStep
+        };
+
+        self.irq_inhibit = data & IRQ_INHIBIT_FLAG != 0;
+        if self.irq_inhibit {
+            self.irq_pending = false;
+        }
+
+        // The sequencer restarts 3 CPU cycles after a write landing on an APU cycle
+        // and 4 CPU cycles after a write landing in between APU cycles
+        self.reset_delay = Some(if self.odd_cycle { 4 } else { 3 });
+    }
+
+    /// Advances the sequencer by one CPU cycle
+    pub fn tick(&mut self) -> Option<FrameClock> {
+        self.odd_cycle = !self.odd_cycle;
+
+        if let Some(delay) = self.reset_delay {
+            if delay > 1 {
+                self.reset_delay = Some(delay - 1);
+            } else {
+                self.reset_delay = None;
+                self.cycles = 0;
+                self.step = 0;
+
+                // In 5-step mode all the units are clocked as soon as the sequence restarts
+                return match self.mode {
+                    FrameCounterMode::FourStep => None,
+                    FrameCounterMode::FiveStep => Some(FrameClock::Half),
+                };
+            }
+        }
+
+        self.cycles += 1;
+
+        let sequence: &[usize] = match self.mode {
+            FrameCounterMode::FourStep => &FOUR_STEP_SEQUENCE,
+            FrameCounterMode::FiveStep => &FIVE_STEP_SEQUENCE,
+        };
+
+        if self.cycles != sequence[self.step] {
+            return None;
+        }
+
+        let clock = match (self.mode, self.step) {
+            (FrameCounterMode::FourStep, 1) | (FrameCounterMode::FourStep, 3) => FrameClock::Half,
+            (FrameCounterMode::FiveStep, 1) | (FrameCounterMode::FiveStep, 4) => FrameClock::Half,
+            _ => FrameClock::Quarter,
+        };
+
+        if self.mode == FrameCounterMode::FourStep && self.step == 3 && !self.irq_inhibit {
+            self.irq_pending = true;
+        }
+
+        self.step += 1;
+        if self.step == sequence.len() {
+            self.step = 0;
+            self.cycles = 0;
+        }
+
+        Some(clock)
+    }
+
+    pub fn mode(&self) -> FrameCounterMode {
+        self.mode
+    }
+
+    pub fn step(&self) -> usize {
+        self.step
+    }
+
+    pub fn irq_pending(&self) -> bool {
+        self.irq_pending
+    }
+
+    /// Reading the APU status register (0x4015) acknowledges the frame interrupt
+    pub fn acknowledge_irq(&mut self) {
+        self.irq_pending = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tick_until_clock(frame_counter: &mut FrameCounter) -> (usize, FrameClock) {
+        let mut cycles = 0;
+        loop {
+            cycles += 1;
+            if let Some(clock) = frame_counter.tick() {
+                return (cycles, clock);
+            }
+        }
+    }
+
+    #[test]
+    fn test_frame_counter_four_step_sequence() {
+        let mut frame_counter = FrameCounter::new();
+        assert_eq!(
+            tick_until_clock(&mut frame_counter),
+            (7457, FrameClock::Quarter)
+        );
+        assert_eq!(
+            tick_until_clock(&mut frame_counter),
+            (7456, FrameClock::Half)
+        );
+        assert_eq!(
+            tick_until_clock(&mut frame_counter),
+            (7458, FrameClock::Quarter)
+        );
+        assert!(!frame_counter.irq_pending());
+        assert_eq!(
+            tick_until_clock(&mut frame_counter),
+            (7458, FrameClock::Half)
+        );
+        assert!(frame_counter.irq_pending());
+        assert_eq!(frame_counter.step(), 0);
+    }
+
+    #[test]
+    fn test_frame_counter_five_step_sequence() {
+        let mut frame_counter = FrameCounter::new();
+        frame_counter.write(0b10000000);
+        assert_eq!(frame_counter.mode(), FrameCounterMode::FiveStep);
+
+        assert_eq!(tick_until_clock(&mut frame_counter), (3, FrameClock::Half));
+        assert_eq!(
+            tick_until_clock(&mut frame_counter),
+            (7457, FrameClock::Quarter)
+        );
+        assert_eq!(
+            tick_until_clock(&mut frame_counter),
+            (7456, FrameClock::Half)
+        );
+        assert_eq!(
+            tick_until_clock(&mut frame_counter),
+            (7458, FrameClock::Quarter)
+        );
+        assert_eq!(
+            tick_until_clock(&mut frame_counter),
+            (7458, FrameClock::Quarter)
+        );
+        assert_eq!(
+            tick_until_clock(&mut frame_counter),
+            (7452, FrameClock::Half)
+        );
+        assert!(!frame_counter.irq_pending());
+    }
+
+    #[test]
+    fn test_frame_counter_irq_inhibit() {
+        let mut frame_counter = FrameCounter::new();
+        (0..29829).for_each(|_| {
+            frame_counter.tick();
+        });
+        assert!(frame_counter.irq_pending());
+
+        frame_counter.write(0b01000000);
+        assert!(!frame_counter.irq_pending());
+        (0..29833).for_each(|_| {
+            frame_counter.tick();
+        });
+        assert!(!frame_counter.irq_pending());
+    }
+
+    #[test]
+    fn test_frame_counter_reset_delay() {
+        let mut frame_counter = FrameCounter::new();
+        frame_counter.write(0b00000000); // write on an even cycle
+        assert_eq!(frame_counter.tick(), None);
+        assert_eq!(frame_counter.tick(), None);
+        assert_eq!(frame_counter.tick(), None); // 4-step mode restarts without clocking
+        assert_eq!(
+            tick_until_clock(&mut frame_counter),
+            (7457, FrameClock::Quarter)
+        );
+
+        let mut frame_counter = FrameCounter::new();
+        frame_counter.tick();
+        frame_counter.write(0b10000000); // write on an odd cycle
+        assert_eq!(frame_counter.tick(), None);
+        assert_eq!(frame_counter.tick(), None);
+        assert_eq!(frame_counter.tick(), None);
+        assert_eq!(frame_counter.tick(), Some(FrameClock::Half));
+        assert_eq!(frame_counter.step(), 0);
+    }
+}
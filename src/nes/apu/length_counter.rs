@@ -0,0 +1,78 @@
+// https://wiki.nesdev.org/w/index.php/APU_Length_Counter
+#[rustfmt::skip]
+const LENGTH_TABLE: [u8; 32] = [
+    10, 254, 20,  2, 40,  4, 80,  6, 160,  8, 60, 10, 14, 12, 26, 14,
+    12,  16, 24, 18, 48, 20, 96, 22, 192, 24, 72, 26, 16, 28, 32, 30,
+];
+
+/// Counter that silences a channel once it reaches zero.
+/// It is clocked by the half frame signal of the frame counter unless halted.
+pub struct LengthCounter {
+    value: u8,
+    halted: bool,
+    enabled: bool,
+}
+
+impl LengthCounter {
+    pub fn new() -> Self {
+        LengthCounter {
+            value: 0,
+            halted: false,
+            enabled: false,
+        }
+    }
+
+    /// Loads the counter from the 5-bit index written to the channel's length register
+    pub fn load(&mut self, index: u8) {
+        if self.enabled {
+            self.value = LENGTH_TABLE[(index & 0b11111) as usize];
+        }
+    }
+
+    pub fn set_halted(&mut self, halted: bool) {
+        self.halted = halted;
+    }
+
+    /// Disabling a channel through 0x4015 clears its length counter right away
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.value = 0;
+        }
+    }
+
+    pub fn clock(&mut self) {
+        if !self.halted && self.value > 0 {
+            self.value -= 1;
+        }
+    }
+
+    pub fn value(&self) -> u8 {
+        self.value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_length_counter_load_and_clock() {
+        let mut counter = LengthCounter::new();
+        counter.load(0b00001);
+        assert_eq!(counter.value(), 0); // disabled channels ignore loads
+
+        counter.set_enabled(true);
+        counter.load(0b00001);
+        assert_eq!(counter.value(), 254);
+        counter.clock();
+        assert_eq!(counter.value(), 253);
+
+        counter.set_halted(true);
+        counter.clock();
+        assert_eq!(counter.value(), 253);
+
+        counter.set_enabled(false);
+        assert_eq!(counter.value(), 0);
+    }
+}
@@ -0,0 +1,391 @@
+//! Implementation of the NES' APU (audio processing unit)
+pub mod audio_buffer;
+mod dmc;
+mod envelope;
+mod frame_counter;
+mod length_counter;
+mod noise;
+mod pulse;
+mod resampler;
+mod triangle;
+
+use crate::nes::apu::audio_buffer::AudioBuffer;
+use crate::nes::apu::dmc::Dmc;
+use crate::nes::apu::frame_counter::{FrameClock, FrameCounter, FrameCounterMode};
+use crate::nes::apu::noise::Noise;
+use crate::nes::apu::pulse::{Pulse, PulseChannel};
+use crate::nes::apu::resampler::Resampler;
+use crate::nes::apu::triangle::Triangle;
+use crate::nes::cartridge::TvSystem;
+
+pub const DEFAULT_SAMPLE_RATE: u32 = 44100;
+// Fast-forwarding past this speed outputs silence instead of chipmunk audio
+const FAST_FORWARD_MUTE_SPEED: f64 = 4.0;
+
+pub struct Apu {
+    pulse1: Pulse,
+    pulse2: Pulse,
+    triangle: Triangle,
+    noise: Noise,
+    dmc: Dmc,
+    frame_counter: FrameCounter,
+    // Pulse timers are clocked on every other CPU cycle
+    odd_cycle: bool,
+
+    resampler: Resampler,
+    output: AudioBuffer,
+}
+
+impl Apu {
+    /// Creates an NTSC APU
+    pub fn new() -> Self {
+        Apu::with_tv_system(TvSystem::Ntsc)
+    }
+
+    /// Creates an APU that outputs samples at the CPU clock of the given TV system
+    pub fn with_tv_system(tv_system: TvSystem) -> Self {
+        Apu {
+            pulse1: Pulse::new(PulseChannel::One),
+            pulse2: Pulse::new(PulseChannel::Two),
+            triangle: Triangle::new(),
+            noise: Noise::new(),
+            dmc: Dmc::new(),
+            frame_counter: FrameCounter::new(),
+            odd_cycle: false,
+            resampler: Resampler::new(DEFAULT_SAMPLE_RATE, tv_system.cpu_clock_rate()),
+            output: AudioBuffer::default(),
+        }
+    }
+
+    /// Advances the APU by the given amount of CPU cycles
+    pub fn tick(&mut self, cycles: u8) {
+        for _ in 0..cycles {
+            match self.frame_counter.tick() {
+                Some(FrameClock::Quarter) => self.clock_quarter_frame(),
+                Some(FrameClock::Half) => {
+                    self.clock_quarter_frame();
+                    self.clock_half_frame();
+                }
+                None => {}
+            }
+
+            self.triangle.clock_timer();
+            self.noise.clock_timer();
+            self.dmc.clock_timer();
+            if self.odd_cycle {
+                self.pulse1.clock_timer();
+                self.pulse2.clock_timer();
+            }
+            self.odd_cycle = !self.odd_cycle;
+
+            if self.resampler.tick() {
+                let sample = if self.resampler.speed() >= FAST_FORWARD_MUTE_SPEED {
+                    0.0
+                } else {
+                    self.read_sample()
+                };
+                self.output.push(sample);
+            }
+        }
+    }
+
+    /// Queue the samples are pushed to; the audio thread pulls from a clone of it
+    pub fn audio_output(&self) -> AudioBuffer {
+        self.output.clone()
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.resampler.set_sample_rate(sample_rate);
+    }
+
+    /// Scales the output rate with the emulation speed (e.g. 2.0 when fast-forwarding)
+    /// so the audio queue neither overflows nor starves. Speeds that aren't positive and
+    /// finite are ignored.
+    pub fn set_speed(&mut self, speed: f64) {
+        self.resampler.set_speed(speed);
+    }
+
+    pub fn write_to_channel_register(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x4000 => self.pulse1.write_control(data),
+            0x4001 => self.pulse1.write_sweep(data),
+            0x4002 => self.pulse1.write_timer_low(data),
+            0x4003 => self.pulse1.write_length_counter_load(data),
+            0x4004 => self.pulse2.write_control(data),
+            0x4005 => self.pulse2.write_sweep(data),
+            0x4006 => self.pulse2.write_timer_low(data),
+            0x4007 => self.pulse2.write_length_counter_load(data),
+            0x4008 => self.triangle.write_control(data),
+            0x400A => self.triangle.write_timer_low(data),
+            0x400B => self.triangle.write_length_counter_load(data),
+            0x400C => self.noise.write_control(data),
+            0x400E => self.noise.write_period(data),
+            0x400F => self.noise.write_length_counter_load(data),
+            0x4010 => self.dmc.write_control(data),
+            0x4011 => self.dmc.write_direct_load(data),
+            0x4012 => self.dmc.write_sample_address(data),
+            0x4013 => self.dmc.write_sample_length(data),
+            _ => { /* Not emulated yet */ }
+        }
+    }
+
+    /// Enables (1) or disables (0) each channel. Bits: ---D NT21.
+    pub fn write_to_status_register(&mut self, data: u8) {
+        self.pulse1.set_enabled(data & 0b0001 != 0);
+        self.pulse2.set_enabled(data & 0b0010 != 0);
+        self.triangle.set_enabled(data & 0b0100 != 0);
+        self.noise.set_enabled(data & 0b1000 != 0);
+        self.dmc.set_enabled(data & 0b10000 != 0);
+    }
+
+    /// Channel and interrupt status. Bits: IF-D NT21, where D is whether the DMC is still
+    /// playing, NT21 whether each length counter is nonzero and I and F the DMC and frame
+    /// interrupts. Reading it acknowledges the frame interrupt, but not the DMC one.
+    pub fn read_status_register(&mut self) -> u8 {
+        let mut status = 0;
+        for (i, length_counter) in self.length_counters().iter().enumerate() {
+            if *length_counter > 0 {
+                status |= 1 << i;
+            }
+        }
+        if self.dmc.is_active() {
+            status |= 0b0001_0000;
+        }
+        if self.frame_counter.irq_pending() {
+            status |= 0b0100_0000;
+        }
+        if self.dmc.irq_pending() {
+            status |= 0b1000_0000;
+        }
+        self.frame_counter.acknowledge_irq();
+        status
+    }
+
+    pub fn write_to_frame_counter(&mut self, data: u8) {
+        self.frame_counter.write(data);
+    }
+
+    /// Frame counter sequence length: 4 or 5 steps
+    pub fn frame_counter_mode(&self) -> u8 {
+        match self.frame_counter.mode() {
+            FrameCounterMode::FourStep => 4,
+            FrameCounterMode::FiveStep => 5,
+        }
+    }
+
+    /// Index of the next step the frame counter is going to clock
+    pub fn frame_counter_step(&self) -> u8 {
+        self.frame_counter.step() as u8
+    }
+
+    pub fn frame_irq_pending(&self) -> bool {
+        self.frame_counter.irq_pending()
+    }
+
+    pub fn dmc_irq_pending(&self) -> bool {
+        self.dmc.irq_pending()
+    }
+
+    /// Whether the DMC still has sample bytes to fetch
+    pub fn dmc_active(&self) -> bool {
+        self.dmc.is_active()
+    }
+
+    /// PRG address the DMC needs to read next, if its sample buffer is empty.
+    /// The bus answers with `load_dmc_sample` and stalls the CPU meanwhile.
+    pub fn dmc_dma_address(&self) -> Option<u16> {
+        self.dmc.dma_address()
+    }
+
+    pub fn load_dmc_sample(&mut self, data: u8) {
+        self.dmc.load_sample_byte(data);
+    }
+
+    /// Current length counter values: [pulse 1, pulse 2, triangle, noise]
+    pub fn length_counters(&self) -> [u8; 4] {
+        [
+            self.pulse1.length_counter(),
+            self.pulse2.length_counter(),
+            self.triangle.length_counter(),
+            self.noise.length_counter(),
+        ]
+    }
+
+    /// Mixes the current output of the channels into a sample between 0.0 and 1.0
+    /// https://wiki.nesdev.org/w/index.php/APU_Mixer
+    pub fn read_sample(&self) -> f32 {
+        let pulse = (self.pulse1.output() + self.pulse2.output()) as f32;
+        let pulse_out = if pulse == 0.0 {
+            0.0
+        } else {
+            95.88 / (8128.0 / pulse + 100.0)
+        };
+
+        let tnd = self.triangle.output() as f32 / 8227.0
+            + self.noise.output() as f32 / 12241.0
+            + self.dmc.output() as f32 / 22638.0;
+        let tnd_out = if tnd == 0.0 {
+            0.0
+        } else {
+            159.79 / (1.0 / tnd + 100.0)
+        };
+
+        pulse_out + tnd_out
+    }
+
+    fn clock_quarter_frame(&mut self) {
+        self.pulse1.clock_quarter_frame();
+        self.pulse2.clock_quarter_frame();
+        self.triangle.clock_quarter_frame();
+        self.noise.clock_quarter_frame();
+    }
+
+    fn clock_half_frame(&mut self) {
+        self.pulse1.clock_half_frame();
+        self.pulse2.clock_half_frame();
+        self.triangle.clock_half_frame();
+        self.noise.clock_half_frame();
+    }
+}
+
+impl Default for Apu {
+    fn default() -> Self {
+        Apu::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_apu_frame_counter_diagnostics() {
+        let mut apu = Apu::new();
+        assert_eq!(apu.frame_counter_mode(), 4);
+        assert_eq!(apu.frame_counter_step(), 0);
+
+        (0..30).for_each(|_| apu.tick(255)); // 7650 cycles, past the first step
+        assert_eq!(apu.frame_counter_step(), 1);
+        assert!(!apu.frame_irq_pending());
+
+        apu.write_to_frame_counter(0b10000000);
+        assert_eq!(apu.frame_counter_mode(), 5);
+        apu.tick(4); // sequencer restarts after the write delay
+        assert_eq!(apu.frame_counter_step(), 0);
+    }
+
+    fn samples_per_frame(apu: &mut Apu) -> usize {
+        let output = apu.audio_output();
+        output.pull(&mut vec![0.0; output.len()]);
+        (0..29781).for_each(|_| apu.tick(1));
+        output.len()
+    }
+
+    #[test]
+    fn test_apu_samples_scale_with_speed() {
+        // 44100Hz at ~60.1 frames per second
+        let expected_per_frame = 44100.0 * 29781.0 / 1_789_773.0;
+        let mut apu = Apu::new();
+        for speed in [1.0, 2.0, 0.5].iter() {
+            apu.set_speed(*speed);
+            let samples = samples_per_frame(&mut apu) as f64;
+            assert!((samples - expected_per_frame / speed).abs() <= 1.0);
+        }
+
+        apu.set_speed(1.0);
+        for speed in [0.0, -1.0, f64::NAN, f64::INFINITY].iter() {
+            apu.set_speed(*speed);
+            assert_eq!(apu.resampler.speed(), 1.0);
+        }
+        let samples = samples_per_frame(&mut apu) as f64;
+        assert!((samples - expected_per_frame).abs() <= 1.0);
+    }
+
+    #[test]
+    fn test_apu_samples_at_the_pal_cpu_clock() {
+        // 44100Hz at ~50 frames per second, with a PAL frame's worth of CPU cycles
+        let expected_per_frame = 44100.0 * 33248.0 / 1_662_607.0;
+        let mut apu = Apu::with_tv_system(TvSystem::Pal);
+        let output = apu.audio_output();
+        (0..33248).for_each(|_| apu.tick(1));
+        assert!((output.len() as f64 - expected_per_frame).abs() <= 1.0);
+    }
+
+    #[test]
+    fn test_apu_pulse_output() {
+        let mut apu = Apu::new();
+        // The idle triangle holds the first level of its sequence
+        let silence = apu.read_sample();
+
+        apu.write_to_status_register(0b0001);
+        apu.write_to_channel_register(0x4000, 0b1001_1111); // 50% duty, constant volume 15
+        apu.write_to_channel_register(0x4002, 0xFD); // ~440Hz
+        apu.write_to_channel_register(0x4003, 0b0000_1000);
+
+        let mut levels = Vec::new();
+        for _ in 0..(254 * 2 * 8) {
+            apu.tick(1);
+            levels.push(apu.read_sample());
+        }
+        let max = levels.iter().cloned().fold(0.0, f32::max);
+        assert!((max - silence - 95.88 / (8128.0 / 15.0 + 100.0)).abs() < 1e-6);
+        assert!(levels.contains(&silence));
+
+        let output = apu.audio_output();
+        let mut samples = vec![0.0; output.len()];
+        output.pull(&mut samples);
+        assert!(samples.iter().any(|sample| *sample > silence));
+    }
+
+    #[test]
+    fn test_apu_length_counters() {
+        let mut apu = Apu::new();
+        apu.write_to_status_register(0b1111);
+        apu.write_to_channel_register(0x4003, 1 << 3); // 254
+        apu.write_to_channel_register(0x4007, 0 << 3); // 10
+        apu.write_to_channel_register(0x400B, 2 << 3); // 20
+        apu.write_to_channel_register(0x400F, 31 << 3); // 30
+        assert_eq!(apu.length_counters(), [254, 10, 20, 30]);
+
+        // First half frame clock happens on the second frame counter step
+        (0..58).for_each(|_| apu.tick(255));
+        assert_eq!(apu.length_counters(), [254, 10, 20, 30]);
+        apu.tick(255);
+        assert_eq!(apu.length_counters(), [253, 9, 19, 29]);
+    }
+
+    #[test]
+    fn test_apu_status_register_read() {
+        let mut apu = Apu::new();
+        apu.write_to_status_register(0b0101);
+        apu.write_to_channel_register(0x4003, 1 << 3);
+        apu.write_to_channel_register(0x400B, 2 << 3);
+        apu.write_to_channel_register(0x400F, 31 << 3); // noise is disabled, its counter stays at 0
+        assert_eq!(apu.read_status_register(), 0b0000_0101);
+
+        (0..30000).for_each(|_| apu.tick(1)); // past the last step of the 4-step sequence
+        assert!(apu.frame_irq_pending());
+        assert_eq!(apu.read_status_register(), 0b0100_0101);
+        assert!(!apu.frame_irq_pending());
+        assert_eq!(apu.read_status_register(), 0b0000_0101);
+    }
+
+    #[test]
+    fn test_apu_frame_counter_write_clocks_length_in_five_step_mode() {
+        let mut apu = Apu::new();
+        apu.write_to_status_register(0b0001);
+        apu.write_to_channel_register(0x4003, 1 << 3); // 254
+
+        apu.write_to_frame_counter(0x80);
+        apu.tick(2);
+        assert_eq!(apu.length_counters()[0], 254);
+        apu.tick(1);
+        assert_eq!(apu.length_counters()[0], 253);
+        assert_eq!(apu.frame_counter_step(), 0);
+
+        // 4-step mode only restarts the sequence
+        apu.write_to_frame_counter(0x00);
+        apu.tick(4);
+        assert_eq!(apu.length_counters()[0], 253);
+    }
+}
@@ -0,0 +1,119 @@
+use crate::nes::apu::envelope::Envelope;
+use crate::nes::apu::length_counter::LengthCounter;
+
+const LENGTH_COUNTER_HALT_FLAG: u8 = 0b00100000;
+const MODE_FLAG: u8 = 0b10000000;
+
+// Timer periods in CPU cycles (NTSC)
+const NOISE_PERIODS: [u16; 16] = [
+    4, 8, 16, 32, 64, 96, 128, 160, 202, 254, 380, 508, 762, 1016, 2034, 4068,
+];
+
+/// Noise channel (pseudo-random bit generator).
+/// RAM addresses: 0x400C-0x400F.
+pub struct Noise {
+    length_counter: LengthCounter,
+    envelope: Envelope,
+
+    // 15-bit linear feedback shift register, its bit 0 silences the output when set
+    shift_register: u16,
+    short_mode: bool,
+    timer_period: u16,
+    timer: u16,
+}
+
+impl Noise {
+    pub fn new() -> Self {
+        Noise {
+            length_counter: LengthCounter::new(),
+            envelope: Envelope::new(),
+            shift_register: 1,
+            short_mode: false,
+            timer_period: NOISE_PERIODS[0],
+            timer: 0,
+        }
+    }
+
+    /// Length counter halt (L), constant volume (C), volume/envelope (VVVV). Bits: --LC VVVV.
+    pub fn write_control(&mut self, data: u8) {
+        self.length_counter
+            .set_halted(data & LENGTH_COUNTER_HALT_FLAG != 0);
+        self.envelope.write(data);
+    }
+
+    /// Mode (M), period (PPPP). Bits: M--- PPPP.
+    pub fn write_period(&mut self, data: u8) {
+        self.short_mode = data & MODE_FLAG != 0;
+        self.timer_period = NOISE_PERIODS[(data & 0b1111) as usize];
+    }
+
+    /// Length counter load (LLLLL). Bits: LLLL L---.
+    pub fn write_length_counter_load(&mut self, data: u8) {
+        self.length_counter.load(data >> 3);
+        self.envelope.restart();
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.length_counter.set_enabled(enabled);
+    }
+
+    /// Clocked every CPU cycle, unlike the pulse timers, since the periods are in CPU cycles
+    pub fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.timer_period - 1;
+            // Short mode feeds back bit 6 instead of bit 1, for a metallic 93-step sequence
+            let tap = if self.short_mode { 6 } else { 1 };
+            let feedback = (self.shift_register ^ (self.shift_register >> tap)) & 1;
+            self.shift_register = (self.shift_register >> 1) | (feedback << 14);
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    pub fn clock_quarter_frame(&mut self) {
+        self.envelope.clock();
+    }
+
+    pub fn clock_half_frame(&mut self) {
+        self.length_counter.clock();
+    }
+
+    pub fn length_counter(&self) -> u8 {
+        self.length_counter.value()
+    }
+
+    /// Current volume, 0-15
+    pub fn output(&self) -> u8 {
+        if self.length_counter.value() == 0 || self.shift_register & 1 == 1 {
+            return 0;
+        }
+        self.envelope.output()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // CPU cycles until the shift register next changes
+    fn clock_until_shift(noise: &mut Noise) -> u16 {
+        let shift_register = noise.shift_register;
+        let mut cycles = 0;
+        while noise.shift_register == shift_register {
+            noise.clock_timer();
+            cycles += 1;
+        }
+        cycles
+    }
+
+    #[test]
+    fn test_noise_timer_period() {
+        let mut noise = Noise::new();
+        for (period, cpu_cycles) in [(0x00, 4), (0x0F, 4068)].iter() {
+            noise.write_period(*period);
+            clock_until_shift(&mut noise); // reloads the timer with the new period
+            assert_eq!(clock_until_shift(&mut noise), *cpu_cycles);
+            assert_eq!(clock_until_shift(&mut noise), *cpu_cycles);
+        }
+    }
+}
@@ -0,0 +1,208 @@
+use crate::nes::apu::envelope::Envelope;
+use crate::nes::apu::length_counter::LengthCounter;
+
+const LENGTH_COUNTER_HALT_FLAG: u8 = 0b00100000;
+
+const SWEEP_ENABLED_FLAG: u8 = 0b10000000;
+const SWEEP_NEGATE_FLAG: u8 = 0b00001000;
+const MAX_TIMER_PERIOD: u16 = 0x7FF;
+
+// https://wiki.nesdev.org/w/index.php/APU_Pulse
+const DUTY_SEQUENCES: [[u8; 8]; 4] = [
+    [0, 1, 0, 0, 0, 0, 0, 0], // 12.5%
+    [0, 1, 1, 0, 0, 0, 0, 0], // 25%
+    [0, 1, 1, 1, 1, 0, 0, 0], // 50%
+    [1, 0, 0, 1, 1, 1, 1, 1], // 25% negated
+];
+
+/// The sweep units differ in how they negate the period change:
+/// pulse 1 uses one's complement and pulse 2 two's complement
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum PulseChannel {
+    One,
+    Two,
+}
+
+/// Pulse (square wave) channel.
+/// RAM addresses: 0x4000-0x4003 (pulse 1) and 0x4004-0x4007 (pulse 2).
+pub struct Pulse {
+    channel: PulseChannel,
+    length_counter: LengthCounter,
+    envelope: Envelope,
+
+    duty: usize,
+    sequence_step: usize,
+    timer_period: u16,
+    timer: u16,
+
+    sweep_enabled: bool,
+    sweep_period: u8,
+    sweep_negate: bool,
+    sweep_shift: u8,
+    sweep_divider: u8,
+    sweep_reload: bool,
+}
+
+impl Pulse {
+    pub fn new(channel: PulseChannel) -> Self {
+        Pulse {
+            channel,
+            length_counter: LengthCounter::new(),
+            envelope: Envelope::new(),
+            duty: 0,
+            sequence_step: 0,
+            timer_period: 0,
+            timer: 0,
+            sweep_enabled: false,
+            sweep_period: 0,
+            sweep_negate: false,
+            sweep_shift: 0,
+            sweep_divider: 0,
+            sweep_reload: false,
+        }
+    }
+
+    /// Duty (DD), length counter halt (L), constant volume (C), volume/envelope (VVVV).
+    /// Bits: DDLC VVVV.
+    pub fn write_control(&mut self, data: u8) {
+        self.duty = (data >> 6) as usize;
+        self.length_counter
+            .set_halted(data & LENGTH_COUNTER_HALT_FLAG != 0);
+        self.envelope.write(data);
+    }
+
+    /// Enabled (E), period (PPP), negate (N), shift (SSS). Bits: EPPP NSSS.
+    pub fn write_sweep(&mut self, data: u8) {
+        self.sweep_enabled = data & SWEEP_ENABLED_FLAG != 0;
+        self.sweep_period = (data >> 4) & 0b111;
+        self.sweep_negate = data & SWEEP_NEGATE_FLAG != 0;
+        self.sweep_shift = data & 0b111;
+        self.sweep_reload = true;
+    }
+
+    /// Timer low (TTTTTTTT). Bits: TTTT TTTT.
+    pub fn write_timer_low(&mut self, data: u8) {
+        self.timer_period = (self.timer_period & 0xFF00) | data as u16;
+    }
+
+    /// Length counter load (LLLLL), timer high (TTT). Bits: LLLL LTTT.
+    pub fn write_length_counter_load(&mut self, data: u8) {
+        self.length_counter.load(data >> 3);
+        self.timer_period = (self.timer_period & 0x00FF) | ((data & 0b111) as u16) << 8;
+        self.sequence_step = 0;
+        self.envelope.restart();
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.length_counter.set_enabled(enabled);
+    }
+
+    /// Clocked every APU cycle (every other CPU cycle)
+    pub fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.timer_period;
+            self.sequence_step = (self.sequence_step + 1) % 8;
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    pub fn clock_quarter_frame(&mut self) {
+        self.envelope.clock();
+    }
+
+    pub fn clock_half_frame(&mut self) {
+        self.length_counter.clock();
+
+        if self.sweep_divider == 0 && self.sweep_enabled && self.sweep_shift > 0 && !self.muted() {
+            self.timer_period = self.sweep_target_period();
+        }
+        if self.sweep_divider == 0 || self.sweep_reload {
+            self.sweep_divider = self.sweep_period;
+            self.sweep_reload = false;
+        } else {
+            self.sweep_divider -= 1;
+        }
+    }
+
+    pub fn length_counter(&self) -> u8 {
+        self.length_counter.value()
+    }
+
+    /// Current volume, 0-15
+    pub fn output(&self) -> u8 {
+        if self.length_counter.value() == 0
+            || self.muted()
+            || DUTY_SEQUENCES[self.duty][self.sequence_step] == 0
+        {
+            return 0;
+        }
+        self.envelope.output()
+    }
+
+    fn sweep_target_period(&self) -> u16 {
+        let change = self.timer_period >> self.sweep_shift;
+        if !self.sweep_negate {
+            return self.timer_period + change;
+        }
+        match self.channel {
+            PulseChannel::One => self.timer_period.saturating_sub(change + 1),
+            PulseChannel::Two => self.timer_period.saturating_sub(change),
+        }
+    }
+
+    // The sweep unit mutes the channel even when it is disabled
+    fn muted(&self) -> bool {
+        self.timer_period < 8 || self.sweep_target_period() > MAX_TIMER_PERIOD
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_playing_pulse(channel: PulseChannel) -> Pulse {
+        let mut pulse = Pulse::new(channel);
+        pulse.set_enabled(true);
+        pulse.write_control(0b1001_1111); // 50% duty, constant volume 15
+        pulse.write_timer_low(0x10);
+        pulse.write_length_counter_load(0b0000_1000);
+        pulse
+    }
+
+    #[test]
+    fn test_pulse_duty_waveform() {
+        let mut pulse = new_playing_pulse(PulseChannel::One);
+        let mut waveform = Vec::new();
+        for _ in 0..8 {
+            waveform.push(pulse.output());
+            (0..=0x10).for_each(|_| pulse.clock_timer());
+        }
+        assert_eq!(waveform, vec![0, 15, 15, 15, 15, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_pulse_sweep() {
+        let mut pulse = new_playing_pulse(PulseChannel::One);
+        pulse.write_sweep(0b1000_1001); // enabled, period 0, negate, shift 1
+        pulse.clock_half_frame();
+        assert_eq!(pulse.timer_period, 0x10 - 8 - 1);
+
+        let mut pulse = new_playing_pulse(PulseChannel::Two);
+        pulse.write_sweep(0b1000_1001);
+        pulse.clock_half_frame();
+        assert_eq!(pulse.timer_period, 0x10 - 8);
+    }
+
+    #[test]
+    fn test_pulse_muted_by_sweep_target_overflow() {
+        let mut pulse = new_playing_pulse(PulseChannel::One);
+        pulse.write_timer_low(0xFF);
+        pulse.write_length_counter_load(0b0000_1111); // period 0x7FF
+        pulse.clock_timer(); // step 1, high in every duty
+        assert_eq!(pulse.output(), 0);
+
+        pulse.write_sweep(0b0000_1000); // negated target doesn't overflow
+        assert_eq!(pulse.output(), 15);
+    }
+}
@@ -0,0 +1,59 @@
+//! Decides on which CPU cycles an output sample is due, for a given sample rate
+//! and emulation speed
+
+pub struct Resampler {
+    sample_rate: u32,
+    // CPU cycles per second, which differs between NTSC and PAL
+    cpu_clock_rate: f64,
+    speed: f64,
+    samples_per_cycle: f64,
+    accumulator: f64,
+}
+
+impl Resampler {
+    pub fn new(sample_rate: u32, cpu_clock_rate: f64) -> Self {
+        let mut resampler = Resampler {
+            sample_rate,
+            cpu_clock_rate,
+            speed: 1.0,
+            samples_per_cycle: 0.0,
+            accumulator: 0.0,
+        };
+        resampler.update_samples_per_cycle();
+        resampler
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.sample_rate = sample_rate;
+        self.update_samples_per_cycle();
+    }
+
+    /// Emulating at twice the speed means half the samples per emulated cycle,
+    /// so the output rate still matches the audio device. Speeds that aren't positive
+    /// and finite are ignored, they would output a sample every cycle or none at all.
+    pub fn set_speed(&mut self, speed: f64) {
+        if !speed.is_finite() || speed <= 0.0 {
+            return;
+        }
+        self.speed = speed;
+        self.update_samples_per_cycle();
+    }
+
+    pub fn speed(&self) -> f64 {
+        self.speed
+    }
+
+    /// Advances one CPU cycle, returning whether a sample has to be output
+    pub fn tick(&mut self) -> bool {
+        self.accumulator += self.samples_per_cycle;
+        if self.accumulator >= 1.0 {
+            self.accumulator -= 1.0;
+            return true;
+        }
+        false
+    }
+
+    fn update_samples_per_cycle(&mut self) {
+        self.samples_per_cycle = self.sample_rate as f64 / (self.cpu_clock_rate * self.speed);
+    }
+}
@@ -0,0 +1,128 @@
+use crate::nes::apu::length_counter::LengthCounter;
+
+const LENGTH_COUNTER_HALT_FLAG: u8 = 0b10000000;
+
+#[rustfmt::skip]
+const TRIANGLE_SEQUENCE: [u8; 32] = [
+    15, 14, 13, 12, 11, 10,  9,  8,  7,  6,  5,  4,  3,  2,  1,  0,
+     0,  1,  2,  3,  4,  5,  6,  7,  8,  9, 10, 11, 12, 13, 14, 15,
+];
+
+/// Triangle wave channel.
+/// RAM addresses: 0x4008-0x400B.
+pub struct Triangle {
+    length_counter: LengthCounter,
+
+    // The control flag doubles as the length counter halt flag
+    linear_counter_control: bool,
+    linear_counter_reload_value: u8,
+    linear_counter: u8,
+    linear_counter_reload: bool,
+
+    sequence_step: usize,
+    timer_period: u16,
+    timer: u16,
+}
+
+impl Triangle {
+    pub fn new() -> Self {
+        Triangle {
+            length_counter: LengthCounter::new(),
+            linear_counter_control: false,
+            linear_counter_reload_value: 0,
+            linear_counter: 0,
+            linear_counter_reload: false,
+            sequence_step: 0,
+            timer_period: 0,
+            timer: 0,
+        }
+    }
+
+    /// Length counter halt / linear counter control (C), linear counter load (RRRRRRR).
+    /// Bits: CRRR RRRR.
+    pub fn write_control(&mut self, data: u8) {
+        self.linear_counter_control = data & LENGTH_COUNTER_HALT_FLAG != 0;
+        self.linear_counter_reload_value = data & 0b0111_1111;
+        self.length_counter
+            .set_halted(data & LENGTH_COUNTER_HALT_FLAG != 0);
+    }
+
+    /// Timer low (TTTTTTTT). Bits: TTTT TTTT.
+    pub fn write_timer_low(&mut self, data: u8) {
+        self.timer_period = (self.timer_period & 0xFF00) | data as u16;
+    }
+
+    /// Length counter load (LLLLL), timer high (TTT). Bits: LLLL LTTT.
+    pub fn write_length_counter_load(&mut self, data: u8) {
+        self.length_counter.load(data >> 3);
+        self.timer_period = (self.timer_period & 0x00FF) | ((data & 0b111) as u16) << 8;
+        self.linear_counter_reload = true;
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.length_counter.set_enabled(enabled);
+    }
+
+    /// Clocked every CPU cycle. The sequence only advances while both counters are non-zero,
+    /// so a silenced triangle holds its last level instead of dropping to 0.
+    pub fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.timer_period;
+            if self.length_counter.value() > 0 && self.linear_counter > 0 {
+                self.sequence_step = (self.sequence_step + 1) % 32;
+            }
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    pub fn clock_quarter_frame(&mut self) {
+        if self.linear_counter_reload {
+            self.linear_counter = self.linear_counter_reload_value;
+        } else if self.linear_counter > 0 {
+            self.linear_counter -= 1;
+        }
+        if !self.linear_counter_control {
+            self.linear_counter_reload = false;
+        }
+    }
+
+    pub fn clock_half_frame(&mut self) {
+        self.length_counter.clock();
+    }
+
+    pub fn length_counter(&self) -> u8 {
+        self.length_counter.value()
+    }
+
+    /// Current level, 0-15
+    pub fn output(&self) -> u8 {
+        TRIANGLE_SEQUENCE[self.sequence_step]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_triangle_sequence_needs_both_counters() {
+        let mut triangle = Triangle::new();
+        triangle.set_enabled(true);
+        triangle.write_control(0b0000_0010); // linear counter reload value 2
+        triangle.write_timer_low(0);
+        triangle.write_length_counter_load(0b0000_1000);
+
+        triangle.clock_timer();
+        assert_eq!(triangle.output(), 15); // linear counter not reloaded yet
+
+        triangle.clock_quarter_frame();
+        triangle.clock_timer();
+        triangle.clock_timer();
+        assert_eq!(triangle.output(), 13);
+
+        (0..2).for_each(|_| triangle.clock_quarter_frame()); // linear counter runs out
+        triangle.clock_timer();
+        assert_eq!(triangle.output(), 13);
+    }
+}
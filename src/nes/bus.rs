@@ -1,8 +1,14 @@
 /// Implementation of the NES' Bus that connects the CPU, PPU and memory together
-use crate::nes::cartridge::Rom;
-use crate::nes::joypad::Joypad;
+use crate::nes::apu::Apu;
+use crate::nes::cartridge::{Rom, TvSystem};
+use crate::nes::joypad::{Joypad, JoypadButton};
+use crate::nes::mapper;
+use crate::nes::mapper::SharedMapper;
 use crate::nes::memory::Memory;
 use crate::nes::ppu::Ppu;
+use crate::nes::render::frame::Frame;
+use crate::nes::state::{StateError, StateReader, StateWriter};
+use std::fmt;
 
 const RAM_START_ADDR: u16 = 0x0000;
 const RAM_MIRRORS_END_ADDR: u16 = 0x1FFF;
@@ -17,27 +23,81 @@ const PPU_ADDR_REGISTER: u16 = 0x2006;
 const PPU_DATA_REGISTER: u16 = 0x2007;
 const PPU_OAM_DMA_REGISTER: u16 = 0x4014;
 
+const APU_CHANNELS_START_ADDR: u16 = 0x4000;
+const APU_CHANNELS_END_ADDR: u16 = 0x4013;
+const APU_STATUS_REGISTER: u16 = 0x4015;
+const APU_FRAME_COUNTER_REGISTER: u16 = 0x4017;
+
 const PPU_REGISTERS_MIRRORS_START_ADDR: u16 = 0x2008;
 const PPU_REGISTERS_MIRRORS_END_ADDR: u16 = 0x3FFF;
 
 const RAM_MIRROR_MASK: u16 = 0b00000111_11111111;
 const PPU_MIRROR_MASK: u16 = 0b00100000_00000111;
 
-const PRG_ROM_START_ADDR: u16 = 0x8000;
+const PRG_RAM_START_ADDR: u16 = 0x6000;
 const PRG_ROM_END_ADDR: u16 = 0xFFFF;
 
+const OAM_DMA_CYCLES: u16 = 513; // +1 when the transfer starts on an odd cycle
+                                 // Usually 4, it can be 1 to 3 depending on what the CPU was doing, which isn't emulated
+const DMC_DMA_CYCLES: u8 = 4;
+
 const JOYPAD1_ADDR: u16 = 0x4016;
 const JOYPAD2_ADDR: u16 = 0x4017;
 
+/// Contents of the CPU RAM at power on. On hardware they are unpredictable, a few games
+/// (and test ROMs) behave differently when they are not all 0s.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub enum RamInit {
+    #[default]
+    Zeroed,
+    Filled(u8),
+    /// Pseudo-random bytes, the same ones for the same seed
+    Seeded(u64),
+}
+
+impl RamInit {
+    fn fill(&self, ram: &mut [u8]) {
+        match *self {
+            RamInit::Zeroed => ram.iter_mut().for_each(|byte| *byte = 0),
+            RamInit::Filled(value) => ram.iter_mut().for_each(|byte| *byte = value),
+            RamInit::Seeded(seed) => {
+                // SplitMix64, which gives well mixed values for any seed, 0 included
+                let mut state = seed;
+                for chunk in ram.chunks_mut(8) {
+                    state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+                    let mut z = state;
+                    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+                    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+                    z ^= z >> 31;
+                    chunk.copy_from_slice(&z.to_le_bytes()[..chunk.len()]);
+                }
+            }
+        }
+    }
+}
+
+type GameLoopCallback<'call> = Box<dyn FnMut(&Ppu, &mut Joypad, &mut Joypad) + 'call>;
+type LogCallback<'call> = Box<dyn FnMut(&str) + 'call>;
+
 pub struct Bus<'call> {
     cpu_ram: [u8; 2048],
-    prg_rom: Vec<u8>,
+    mapper: SharedMapper,
     ppu: Ppu,
+    apu: Apu,
 
     cycles: usize,
+    // CPU cycles towards the extra PPU dot that PAL gets every 5 CPU cycles
+    pal_cycle_remainder: u8,
+    frame_ready: bool,
 
-    game_loop_callback: Box<dyn FnMut(&Ppu, &mut Joypad) + 'call>,
+    game_loop_callback: GameLoopCallback<'call>,
+    log_callback: Option<LogCallback<'call>>,
     joypad1: Joypad,
+    joypad2: Joypad,
+
+    // First joypad state at every frame boundary, see `start_recording`
+    input_recording: Option<Vec<u8>>,
+    input_playback: Option<std::vec::IntoIter<u8>>,
 }
 
 impl Memory for Bus<'_> {
@@ -48,17 +108,20 @@ impl Memory for Bus<'_> {
                 self.cpu_ram[mirrored_addr as usize]
             }
             JOYPAD1_ADDR => self.joypad1.read(),
+            JOYPAD2_ADDR => self.joypad2.read(),
             PPU_CTRL_REGISTER
             | PPU_MASK_REGISTER
             | PPU_OAM_ADDR_REGISTER
             | PPU_SCROLL_REGISTER
-            | PPU_ADDR_REGISTER
-            | PPU_OAM_DMA_REGISTER => {
-                panic!(
-                    "Bus: Attempted to read from write-only PPU address {:#X}",
+            | PPU_ADDR_REGISTER => {
+                // Real hardware returns whatever is left on the PPU data bus
+                self.log(format_args!(
+                    "Bus: Read from write-only PPU address {:#X} (Returning open bus)",
                     addr
-                );
+                ));
+                self.ppu.open_bus()
             }
+            APU_STATUS_REGISTER => self.apu.read_status_register(),
             PPU_STATUS_REGISTER => self.ppu.read_status_register(),
             PPU_OAM_DATA_REGISTER => self.ppu.read_oam_data_register(),
             PPU_DATA_REGISTER => self.ppu.read_data_register(),
@@ -66,12 +129,12 @@ impl Memory for Bus<'_> {
                 let mirrored_addr = addr & PPU_MIRROR_MASK;
                 self.mem_read(mirrored_addr)
             }
-            PRG_ROM_START_ADDR..=PRG_ROM_END_ADDR => self.read_prg_rom(addr),
+            PRG_RAM_START_ADDR..=PRG_ROM_END_ADDR => self.read_prg_rom(addr),
             _ => {
-                println!(
+                self.log(format_args!(
                     "Bus: Memory read at address {:#X} ignored (Returning 0)",
                     addr
-                );
+                ));
                 0
             }
         }
@@ -83,7 +146,11 @@ impl Memory for Bus<'_> {
                 let mirrored_addr = addr & RAM_MIRROR_MASK;
                 self.cpu_ram[mirrored_addr as usize] = data;
             }
-            JOYPAD1_ADDR => self.joypad1.write(data),
+            JOYPAD1_ADDR => {
+                // The strobe is shared, it latches both controllers
+                self.joypad1.write(data);
+                self.joypad2.write(data);
+            }
             PPU_CTRL_REGISTER => {
                 self.ppu.write_to_control_register(data);
             }
@@ -115,60 +182,324 @@ impl Memory for Bus<'_> {
             PPU_OAM_DMA_REGISTER => {
                 let mut buffer: [u8; 256] = [0; 256];
                 let hi = (data as u16) << 8;
-                for i in 0..256u16 {
-                    buffer[i as usize] = self.mem_read(hi + i);
+                if Self::is_ram_or_rom(hi) {
+                    for i in 0..256u16 {
+                        buffer[i as usize] = self.mem_peek(hi + i);
+                    }
+                } else {
+                    // Other pages are DMAed as 0s, to not trigger I/O register side effects
+                    self.log(format_args!(
+                        "Bus: OAM DMA from non RAM/ROM page {:#04X}",
+                        data
+                    ));
                 }
 
-                self.ppu.write_to_oam_dma_register(&buffer);
+                self.dma_oam_from(&buffer);
+            }
+            APU_CHANNELS_START_ADDR..=APU_CHANNELS_END_ADDR => {
+                self.apu.write_to_channel_register(addr, data);
+            }
+            APU_STATUS_REGISTER => {
+                self.apu.write_to_status_register(data);
             }
-            PRG_ROM_START_ADDR..=PRG_ROM_END_ADDR => {
-                panic!("Bus: Attempted to write to PRG_ROM address {:#X}", addr);
+            APU_FRAME_COUNTER_REGISTER => {
+                self.apu.write_to_frame_counter(data);
+            }
+            PRG_RAM_START_ADDR..=PRG_ROM_END_ADDR => {
+                self.mapper.borrow_mut().cpu_write(addr, data);
             }
             _ => {
-                println!(
+                self.log(format_args!(
                     "Bus: Memory write of byte {:#X} at address {:#X} ignored",
                     data, addr
-                );
+                ));
             }
         }
     }
 }
 
 impl<'a> Bus<'a> {
-    pub fn new<'call, F>(rom: Rom, game_loop_callback: F) -> Bus<'call>
+    pub fn new<'call, F>(rom: Rom, mut game_loop_callback: F) -> Bus<'call>
         where
             F: FnMut(&Ppu, &mut Joypad) + 'call
     {
+        Bus::with_two_joypads(
+            rom,
+            move |ppu: &Ppu, joypad1: &mut Joypad, _joypad2: &mut Joypad| {
+                game_loop_callback(ppu, joypad1)
+            },
+        )
+    }
+
+    /// Same as `new`, but the game loop callback also gets the second controller (0x4017)
+    pub fn with_two_joypads<'call, F>(rom: Rom, game_loop_callback: F) -> Bus<'call>
+    where
+        F: FnMut(&Ppu, &mut Joypad, &mut Joypad) + 'call,
+    {
+        let tv_system = rom.tv_system;
+        let mapper = mapper::share(rom.into_mapper());
+        let ppu = Ppu::with_mapper(mapper.clone(), tv_system);
+        Bus::from_parts(mapper, ppu, Box::from(game_loop_callback))
+    }
+
+    /// Same as `new`, but the CPU RAM starts with the given contents instead of all 0s
+    pub fn with_ram_init<'call, F>(rom: Rom, ram_init: RamInit, game_loop_callback: F) -> Bus<'call>
+    where
+        F: FnMut(&Ppu, &mut Joypad) + 'call,
+    {
+        let mut bus = Bus::new(rom, game_loop_callback);
+        ram_init.fill(&mut bus.cpu_ram);
+        bus
+    }
+
+    /// Same as `new`, but uses an already set up PPU (e.g. with preset VRAM, OAM and palette)
+    /// so tests can start from a specific PPU state. The PPU gets connected to the ROM's
+    /// mapper, so pattern tables and mirroring come from the ROM.
+    pub fn new_with_ppu<'call, F>(rom: Rom, mut ppu: Ppu, mut game_loop_callback: F) -> Bus<'call>
+    where
+        F: FnMut(&Ppu, &mut Joypad) + 'call,
+    {
+        let mapper = mapper::share(rom.into_mapper());
+        ppu.connect_mapper(mapper.clone());
+        Bus::from_parts(
+            mapper,
+            ppu,
+            Box::new(
+                move |ppu: &Ppu, joypad1: &mut Joypad, _joypad2: &mut Joypad| {
+                    game_loop_callback(ppu, joypad1)
+                },
+            ),
+        )
+    }
+
+    fn from_parts(
+        mapper: SharedMapper,
+        ppu: Ppu,
+        game_loop_callback: GameLoopCallback<'a>,
+    ) -> Bus<'a> {
+        let apu = Apu::with_tv_system(ppu.tv_system());
         Bus {
             cpu_ram: [0; 2048],
-            prg_rom: rom.prg_rom,
-            ppu: Ppu::new(rom.chr_rom, rom.screen_mirroring),
+            mapper,
+            ppu,
+            apu,
             cycles: 0,
-            game_loop_callback: Box::from(game_loop_callback),
+            pal_cycle_remainder: 0,
+            frame_ready: false,
+            game_loop_callback,
+            log_callback: None,
             joypad1: Joypad::new(),
+            joypad2: Joypad::new(),
+            input_recording: None,
+            input_playback: None,
+        }
+    }
+
+    /// Sets the callback that receives diagnostic warnings (e.g. a bad reset vector or
+    /// accesses to unmapped addresses). Warnings are silently dropped when there is none.
+    pub fn set_log_callback<F>(&mut self, log_callback: F)
+    where
+        F: FnMut(&str) + 'a,
+    {
+        self.log_callback = Some(Box::from(log_callback));
+    }
+
+    /// Sends a warning to the log callback. The message is only formatted when there is
+    /// one, since warnings come from hot paths like reads of unmapped addresses.
+    pub fn log(&mut self, message: fmt::Arguments) {
+        if let Some(log_callback) = self.log_callback.as_mut() {
+            log_callback(&message.to_string());
         }
     }
 
     pub fn tick(&mut self, cycles: u8) {
         // https://wiki.nesdev.com/w/index.php/Catch-up
-        // ppu clock is three times faster than cpu's
+        // ppu clock is three times faster than cpu's on NTSC, 3.2 times on PAL
         self.cycles += cycles as usize;
-        let generate_new_frame = self.ppu.tick(cycles * 3);
+        self.apu.tick(cycles);
+        let mut ppu_dots = cycles * 3;
+        if self.ppu.tv_system() == TvSystem::Pal {
+            self.pal_cycle_remainder += cycles;
+            ppu_dots += self.pal_cycle_remainder / 5;
+            self.pal_cycle_remainder %= 5;
+        }
+        let generate_new_frame = self.ppu.tick(ppu_dots);
         if generate_new_frame {
-            (self.game_loop_callback)(&self.ppu, &mut self.joypad1);
+            self.frame_ready = true;
+            (self.game_loop_callback)(&self.ppu, &mut self.joypad1, &mut self.joypad2);
+            self.update_recorded_input();
         }
+
+        if let Some(addr) = self.apu.dmc_dma_address() {
+            let sample = self.read_prg_rom(addr);
+            self.apu.load_dmc_sample(sample);
+            // The CPU is stalled during the fetch, while everything else keeps running
+            self.tick(DMC_DMA_CYCLES);
+        }
+    }
+
+    /// Soft reset: restarts the cycle count and puts the PPU and the mapper back in their
+    /// reset state. RAM is kept, like on real hardware.
+    pub fn reset(&mut self) {
+        self.cycles = 0;
+        self.pal_cycle_remainder = 0;
+        self.frame_ready = false;
+        self.ppu.reset();
+        self.mapper.borrow_mut().reset();
+    }
+
+    /// Saves the RAM, the cycle count, the PPU and the mapper, see `MachineState`
+    pub(crate) fn save_state(&self, state: &mut StateWriter) {
+        state.write_bytes(&self.cpu_ram);
+        state.write_u64(self.cycles as u64);
+        state.write_u8(self.pal_cycle_remainder);
+        state.write_bool(self.frame_ready);
+        self.ppu.save_state(state);
+        self.mapper.borrow().save_state(state);
+    }
+
+    /// Restores what `save_state` saved
+    pub(crate) fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        state.read_into(&mut self.cpu_ram)?;
+        self.cycles = state.read_u64()? as usize;
+        self.pal_cycle_remainder = state.read_u8()?;
+        self.frame_ready = state.read_bool()?;
+        self.ppu.load_state(state)?;
+        self.mapper.borrow_mut().load_state(state)
+    }
+
+    /// Starts recording the first joypad's state on every frame boundary.
+    /// Start right after a reset for `play` to reproduce the same run.
+    pub fn start_recording(&mut self) {
+        self.input_recording = Some(Vec::new());
+    }
+
+    /// Stops recording and returns one joypad byte per recorded frame
+    pub fn stop_recording(&mut self) -> Vec<u8> {
+        self.input_recording.take().unwrap_or_default()
+    }
+
+    /// Replays a recording from `stop_recording`, one byte per frame, ignoring the live
+    /// input of the first joypad until the recording runs out
+    pub fn play(&mut self, recording: Vec<u8>) {
+        self.input_playback = Some(recording.into_iter());
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.input_playback.is_some()
+    }
+
+    fn update_recorded_input(&mut self) {
+        if let Some(playback) = self.input_playback.as_mut() {
+            match playback.next() {
+                Some(buttons) => self
+                    .joypad1
+                    .set_pressed_buttons(JoypadButton::from_bits_truncate(buttons)),
+                None => self.input_playback = None,
+            }
+        }
+        if let Some(recording) = self.input_recording.as_mut() {
+            recording.push(self.joypad1.pressed_buttons().bits());
+        }
+    }
+
+    /// Copies the given page straight into OAM, as a write to 0x4014 would,
+    /// and charges the cycles the CPU would be stalled for.
+    pub fn dma_oam_from(&mut self, data: &[u8; 256]) {
+        self.ppu.write_to_oam_dma_register(data);
+
+        let stall_cycles = OAM_DMA_CYCLES + (self.cycles % 2) as u16;
+        for _ in 0..stall_cycles {
+            self.tick(1);
+        }
+    }
+
+    /// Whether a frame was completed since the last `take_frame`. Hosts that pace
+    /// themselves can run until it flips, take the frame and sleep for the rest of
+    /// `TvSystem::frame_duration`, or not sleep at all to fast-forward.
+    pub fn frame_ready(&self) -> bool {
+        self.frame_ready
+    }
+
+    /// Returns the frame completed since the last call, if any, for hosts that
+    /// prefer polling over the game loop callback. See `Ppu::frame`.
+    pub fn take_frame(&mut self) -> Option<&Frame> {
+        if !self.frame_ready {
+            return None;
+        }
+        self.frame_ready = false;
+        Some(self.ppu.frame())
+    }
+
+    /// Battery-backed save RAM for the front-end to write to a `.sav` file,
+    /// `None` for carts without a battery
+    pub fn save_sram(&self) -> Option<Vec<u8>> {
+        self.mapper.borrow().save_ram()
+    }
+
+    /// Restores save RAM previously returned by `save_sram`
+    pub fn load_sram(&mut self, data: &[u8]) {
+        self.mapper.borrow_mut().load_ram(data);
+    }
+
+    /// CPU cycles elapsed since the last reset
+    pub fn cycles(&self) -> usize {
+        self.cycles
+    }
+
+    pub fn ppu(&self) -> &Ppu {
+        &self.ppu
+    }
+
+    /// The first controller, for hosts that set the buttons directly instead of
+    /// in the game loop callback
+    pub fn joypad1_mut(&mut self) -> &mut Joypad {
+        &mut self.joypad1
+    }
+
+    pub fn apu(&self) -> &Apu {
+        &self.apu
+    }
+
+    pub fn apu_mut(&mut self) -> &mut Apu {
+        &mut self.apu
     }
 
     pub fn poll_nmi_status(&mut self) -> Option<u8> {
         self.ppu.poll_nmi_interrupt()
     }
 
-    fn read_prg_rom(&self, mut addr: u16) -> u8 {
-        addr -= 0x8000; // set addr relative to 0
-        if self.prg_rom.len() == 0x4000 && addr >= 0x4000 {
-            addr = addr % 0x4000; // Mirror if needed
+    /// Whether the IRQ line is asserted. Unlike NMI it stays asserted until acknowledged.
+    pub fn poll_irq_status(&self) -> bool {
+        self.apu.frame_irq_pending() || self.apu.dmc_irq_pending()
+    }
+
+    /// Reads memory without side effects, e.g. for a debugger's memory viewer.
+    /// PPU registers read as a snapshot of their state (0 for the write-only ones
+    /// that keep none), and the joypads and APU read as 0.
+    pub fn mem_peek(&self, addr: u16) -> u8 {
+        match addr {
+            RAM_START_ADDR..=RAM_MIRRORS_END_ADDR => {
+                self.cpu_ram[(addr & RAM_MIRROR_MASK) as usize]
+            }
+            PPU_CTRL_REGISTER..=PPU_REGISTERS_MIRRORS_END_ADDR => {
+                self.ppu.peek_register(addr & PPU_MIRROR_MASK)
+            }
+            PRG_RAM_START_ADDR..=PRG_ROM_END_ADDR => self.read_prg_rom(addr),
+            _ => 0,
         }
-        self.prg_rom[addr as usize]
+    }
+
+    // The cart's PRG RAM (0x6000-0x7FFF) counts as RAM too
+    fn is_ram_or_rom(addr: u16) -> bool {
+        matches!(
+            addr,
+            RAM_START_ADDR..=RAM_MIRRORS_END_ADDR | PRG_RAM_START_ADDR..=PRG_ROM_END_ADDR
+        )
+    }
+
+    fn read_prg_rom(&self, addr: u16) -> u8 {
+        self.mapper.borrow().cpu_read(addr)
     }
 }
 
@@ -176,6 +507,9 @@ impl<'a> Bus<'a> {
 mod tests {
     use super::*;
     use crate::nes::cartridge::tests;
+    use crate::nes::cartridge::{MirroringMode, RomBuilder};
+    use crate::nes::joypad::JoypadButton;
+    use crate::nes::render::palette::SYSTEM_PALETTE;
 
     #[test]
     fn test_bus_mem_read_ram() {
@@ -191,6 +525,31 @@ mod tests {
         assert_eq!(bus.mem_read(0x00), 0xFF);
     }
 
+    #[test]
+    fn test_bus_ram_init() {
+        let rom = tests::create_simple_test_rom();
+        let mut bus = Bus::with_ram_init(
+            rom,
+            RamInit::Filled(0xFF),
+            |_ppu: &Ppu, _joypad: &mut Joypad| {},
+        );
+        assert!((0..0x0800).all(|addr| bus.mem_read(addr) == 0xFF));
+
+        let seeded = |seed| {
+            let rom = tests::create_simple_test_rom();
+            let bus = Bus::with_ram_init(
+                rom,
+                RamInit::Seeded(seed),
+                |_ppu: &Ppu, _joypad: &mut Joypad| {},
+            );
+            bus.cpu_ram
+        };
+        assert_eq!(seeded(1)[..], seeded(1)[..]);
+        assert_ne!(seeded(1)[..], seeded(2)[..]);
+        let ram = seeded(0);
+        assert!(ram.iter().any(|byte| *byte != ram[0]));
+    }
+
     #[test]
     fn test_bus_ram_mirroring() {
         // 0x0800 is mirrored into 0x00, 0x1000 and 0x1800
@@ -200,4 +559,399 @@ mod tests {
         assert_eq!(bus.mem_read(0x1000), 0xFF);
         assert_eq!(bus.mem_read(0x1800), 0xFF);
     }
+
+    #[test]
+    fn test_bus_dma_oam_from() {
+        let mut bus = Bus::new(
+            tests::create_simple_test_rom(),
+            |_ppu: &Ppu, _joypad: &mut Joypad| {},
+        );
+        let mut data = [0; 256];
+        for (i, byte) in data.iter_mut().enumerate() {
+            *byte = i as u8;
+        }
+
+        bus.dma_oam_from(&data);
+        for i in 0..256 {
+            assert_eq!(bus.ppu.read_oam_data_at(i), i as u8);
+        }
+        assert_eq!(bus.cycles, 513);
+
+        bus.dma_oam_from(&data); // starts on an odd cycle
+        assert_eq!(bus.cycles, 513 + 514);
+    }
+
+    #[test]
+    fn test_bus_oam_dma_from_ram() {
+        let mut bus = Bus::new(
+            tests::create_simple_test_rom(),
+            |_ppu: &Ppu, _joypad: &mut Joypad| {},
+        );
+        bus.cpu_ram[0x0200] = 0xAB;
+        bus.cpu_ram[0x02FF] = 0xCD;
+        bus.mem_write(0x4014, 0x02);
+        assert_eq!(bus.ppu.read_oam_data_at(0), 0xAB);
+        assert_eq!(bus.ppu.read_oam_data_at(255), 0xCD);
+    }
+
+    #[test]
+    fn test_bus_oam_dma_from_prg_ram() {
+        let mut warnings = Vec::new();
+        let mut bus = Bus::new(
+            tests::create_simple_test_rom(),
+            |_ppu: &Ppu, _joypad: &mut Joypad| {},
+        );
+        bus.set_log_callback(|message| warnings.push(message.to_string()));
+        bus.mem_write(0x6000, 0xAB);
+        bus.mem_write(0x60FF, 0xCD);
+        bus.mem_write(0x4014, 0x60);
+        assert_eq!(bus.ppu.read_oam_data_at(0), 0xAB);
+        assert_eq!(bus.ppu.read_oam_data_at(255), 0xCD);
+        drop(bus);
+        assert!(warnings.is_empty());
+    }
+
+    #[test]
+    fn test_bus_oam_dma_write_stalls_the_cpu() {
+        let mut bus = Bus::new(
+            tests::create_simple_test_rom(),
+            |_ppu: &Ppu, _joypad: &mut Joypad| {},
+        );
+        bus.mem_write(0x4014, 0x02);
+        assert_eq!(bus.cycles(), 513);
+        bus.mem_write(0x4014, 0x02); // starts on an odd cycle
+        assert_eq!(bus.cycles(), 513 + 514);
+    }
+
+    #[test]
+    fn test_bus_oam_dma_from_io_page() {
+        let mut warnings = Vec::new();
+        let mut bus = Bus::new(
+            tests::create_simple_test_rom(),
+            |_ppu: &Ppu, _joypad: &mut Joypad| {},
+        );
+        bus.set_log_callback(|message| warnings.push(message.to_string()));
+        while bus.ppu.status_register_bits() & 0b10000000 == 0 {
+            bus.tick(1);
+        }
+        bus.mem_write(0x4014, 0x20);
+
+        assert_eq!(bus.ppu.status_register_bits() & 0b10000000, 0b10000000); // vblank kept
+        assert_eq!(bus.ppu.read_oam_data_at(2), 0);
+        drop(bus);
+        assert_eq!(warnings, vec!["Bus: OAM DMA from non RAM/ROM page 0x20"]);
+    }
+
+    #[test]
+    fn test_bus_write_only_ppu_registers_read_open_bus() {
+        let mut warnings = Vec::new();
+        let mut bus = Bus::new(
+            tests::create_simple_test_rom(),
+            |_ppu: &Ppu, _joypad: &mut Joypad| {},
+        );
+        bus.set_log_callback(|message| warnings.push(message.to_string()));
+        bus.mem_write(0x2000, 0x80);
+        assert_eq!(bus.mem_read(0x2000), 0x80);
+        bus.mem_write(0x2005, 0x12);
+        assert_eq!(bus.mem_read(0x2001), 0x12);
+        assert_eq!(bus.mem_read(0x2003), 0x12);
+        assert_eq!(bus.mem_read(0x200E), 0x12); // mirror of 0x2006
+        assert_eq!(bus.mem_read(0x4014), 0);
+        drop(bus);
+        assert_eq!(warnings.len(), 5);
+        assert_eq!(
+            warnings[0],
+            "Bus: Read from write-only PPU address 0x2000 (Returning open bus)"
+        );
+        assert_eq!(
+            warnings[4],
+            "Bus: Memory read at address 0x4014 ignored (Returning 0)"
+        );
+    }
+
+    #[test]
+    fn test_bus_take_frame() {
+        let mut bus = Bus::new(
+            tests::create_simple_test_rom(),
+            |_ppu: &Ppu, _joypad: &mut Joypad| {},
+        );
+        assert!(bus.take_frame().is_none());
+
+        // A frame takes 262 scanlines of 341 PPU cycles, 3 PPU cycles per CPU cycle
+        while bus.cycles < 262 * 341 / 3 + 1 {
+            assert!(!bus.frame_ready());
+            bus.tick(1);
+        }
+        assert!(bus.frame_ready());
+        assert!(bus.take_frame().is_some());
+        assert!(!bus.frame_ready());
+        assert!(bus.take_frame().is_none());
+    }
+
+    #[test]
+    fn test_bus_pal_frame_timing() {
+        let mut rom = tests::create_simple_test_rom();
+        rom.tv_system = TvSystem::Pal;
+        let mut bus = Bus::new(rom, |_ppu: &Ppu, _joypad: &mut Joypad| {});
+
+        // 312 scanlines of 341 PPU cycles, 3.2 PPU cycles per CPU cycle
+        let mut last_scanline = 0;
+        while !bus.frame_ready {
+            last_scanline = last_scanline.max(bus.ppu.scanline());
+            bus.tick(1);
+        }
+        assert_eq!(last_scanline, 311);
+        assert_eq!(bus.cycles, 33248);
+    }
+
+    #[test]
+    fn test_bus_new_with_ppu() {
+        let mut ppu = Ppu::new(vec![0; 0x2000], MirroringMode::Horizontal);
+        ppu.write_to_address_register(0x3F);
+        ppu.write_to_address_register(0x00);
+        for color in [0x0F, 0x21, 0x22, 0x23].iter() {
+            ppu.write_to_data_register(*color);
+        }
+        ppu.write_to_mask_register(0b00001010); // show background
+
+        let mut bus = Bus::new_with_ppu(
+            tests::create_simple_test_rom(),
+            ppu,
+            |_ppu: &Ppu, _joypad: &mut Joypad| {},
+        );
+        let frame = loop {
+            bus.tick(1);
+            if let Some(frame) = bus.take_frame() {
+                break frame;
+            }
+        };
+        // The test ROM's CHR rows are all 0x02: only the 7th pixel of every tile row is opaque
+        assert_eq!(frame.get_pixel(0, 0), SYSTEM_PALETTE[0x0F]);
+        assert_eq!(frame.get_pixel(6, 0), SYSTEM_PALETTE[0x23]);
+    }
+
+    #[test]
+    fn test_bus_mem_peek_has_no_side_effects() {
+        let mut bus = Bus::new(
+            tests::create_simple_test_rom(),
+            |_ppu: &Ppu, _joypad: &mut Joypad| {},
+        );
+        bus.mem_write(0x0001, 0xAB);
+        bus.mem_write(0x6000, 0xCD);
+        assert_eq!(bus.mem_peek(0x0801), 0xAB);
+        assert_eq!(bus.mem_peek(0x6000), 0xCD);
+        assert_eq!(bus.mem_peek(0x8000), 0x01);
+
+        while bus.ppu.status_register_bits() & 0b10000000 == 0 {
+            bus.tick(1);
+        }
+        assert_eq!(bus.mem_peek(0x2002) & 0b10000000, 0b10000000);
+        assert_eq!(bus.mem_peek(0x3FFA) & 0b10000000, 0b10000000); // mirror of 0x2002
+        assert_eq!(bus.mem_read(0x2002) & 0b10000000, 0b10000000); // vblank wasn't cleared
+
+        // Buffered reads from 0x2007 don't move the address
+        bus.mem_write(0x2006, 0x20);
+        bus.mem_write(0x2006, 0x00);
+        bus.mem_write(0x2007, 0x12);
+        bus.mem_write(0x2006, 0x20);
+        bus.mem_write(0x2006, 0x00);
+        bus.mem_read(0x2007);
+        assert_eq!(bus.mem_peek(0x2007), 0x12);
+        assert_eq!(bus.mem_peek(0x2007), 0x12);
+        assert_eq!(bus.mem_read(0x2007), 0x12);
+
+        // Write-only registers don't panic
+        bus.mem_write(0x2000, 0x80);
+        assert_eq!(bus.mem_peek(0x2000), 0x80);
+        assert_eq!(bus.mem_peek(0x2005), 0);
+        assert_eq!(bus.mem_peek(0x2006), 0);
+        assert_eq!(bus.mem_peek(0x4014), 0);
+    }
+
+    #[test]
+    fn test_bus_battery_backed_sram() {
+        let mut rom = tests::create_simple_test_rom();
+        rom.has_battery = true;
+        let mut bus = Bus::new(rom, |_ppu: &Ppu, _joypad: &mut Joypad| {});
+        bus.mem_write(0x6000, 0x12);
+        bus.mem_write(0x7FFF, 0x34);
+        let sram = bus.save_sram().unwrap();
+        assert_eq!(sram.len(), 0x2000);
+
+        let mut rom = tests::create_simple_test_rom();
+        rom.has_battery = true;
+        let mut bus = Bus::new(rom, |_ppu: &Ppu, _joypad: &mut Joypad| {});
+        bus.load_sram(&sram);
+        assert_eq!(bus.mem_read(0x6000), 0x12);
+        assert_eq!(bus.mem_read(0x7FFF), 0x34);
+    }
+
+    #[test]
+    fn test_bus_sram_without_battery() {
+        let mut bus = Bus::new(
+            tests::create_simple_test_rom(),
+            |_ppu: &Ppu, _joypad: &mut Joypad| {},
+        );
+        bus.mem_write(0x6000, 0x12);
+        assert_eq!(bus.mem_read(0x6000), 0x12);
+        assert!(bus.save_sram().is_none());
+    }
+
+    #[test]
+    fn test_bus_forced_mapper() {
+        // A mapper 0 header on 32KB of PRG, run as the two 16KB banks of mapper 2 (UxROM)
+        let mut prg = vec![0xA0; 0x4000];
+        prg.extend(vec![0xB0; 0x4000]);
+        let data = RomBuilder::new().prg(prg).ines_data();
+        let rom = Rom::with_forced_mapper(&data, 2).unwrap();
+        let mut bus = Bus::new(rom, |_ppu: &Ppu, _joypad: &mut Joypad| {});
+        assert_eq!(bus.mem_read(0x8000), 0xA0);
+        assert_eq!(bus.mem_read(0xFFFF), 0xB0);
+
+        bus.mem_write(0x8000, 0x01);
+        assert_eq!(bus.mem_read(0x8000), 0xB0);
+        assert_eq!(bus.mem_read(0xFFFF), 0xB0);
+    }
+
+    #[test]
+    fn test_bus_game_loop_callback_feeds_joypad() {
+        let mut bus = Bus::new(
+            tests::create_simple_test_rom(),
+            |_ppu: &Ppu, joypad: &mut Joypad| {
+                joypad.set_button_status(JoypadButton::START, true);
+            },
+        );
+        while bus.take_frame().is_none() {
+            bus.tick(1);
+        }
+
+        bus.mem_write(0x4016, 1);
+        bus.mem_write(0x4016, 0);
+        let joypad1_bits: Vec<u8> = (0..8).map(|_| bus.mem_read(0x4016)).collect();
+        assert_eq!(joypad1_bits, vec![0, 0, 0, 1, 0, 0, 0, 0]);
+    }
+
+    fn run_frames<F: FnMut(&Bus)>(bus: &mut Bus, frames: usize, mut on_frame: F) {
+        for _ in 0..frames {
+            while bus.take_frame().is_none() {
+                bus.tick(1);
+            }
+            on_frame(bus);
+        }
+    }
+
+    #[test]
+    fn test_bus_input_recording_and_playback() {
+        let mut live_buttons = vec![
+            JoypadButton::empty(),
+            JoypadButton::BUTTON_A,
+            JoypadButton::RIGHT,
+            JoypadButton::BUTTON_A,
+            JoypadButton::empty(),
+            JoypadButton::BUTTON_A | JoypadButton::RIGHT,
+        ]
+        .into_iter();
+        let mut bus = Bus::new(
+            tests::create_simple_test_rom(),
+            |_ppu: &Ppu, joypad: &mut Joypad| {
+                joypad.set_pressed_buttons(live_buttons.next().unwrap_or_else(JoypadButton::empty));
+            },
+        );
+        bus.start_recording();
+        let mut live_input = Vec::new();
+        run_frames(&mut bus, 6, |bus| {
+            live_input.push(bus.joypad1.pressed_buttons())
+        });
+        let recording = bus.stop_recording();
+        assert_eq!(recording, vec![0x00, 0x01, 0x80, 0x01, 0x00, 0x81]);
+        drop(bus);
+
+        // Replayed without any live input
+        let mut bus = Bus::new(
+            tests::create_simple_test_rom(),
+            |_ppu: &Ppu, _joypad: &mut Joypad| {},
+        );
+        bus.play(recording);
+        let mut replayed_input = Vec::new();
+        run_frames(&mut bus, 6, |bus| {
+            replayed_input.push(bus.joypad1.pressed_buttons())
+        });
+        assert_eq!(replayed_input, live_input);
+
+        // Back to live input once the recording ran out
+        assert!(bus.is_playing());
+        run_frames(&mut bus, 1, |_bus| {});
+        assert!(!bus.is_playing());
+    }
+
+    #[test]
+    fn test_bus_second_joypad() {
+        let mut bus = Bus::new(
+            tests::create_simple_test_rom(),
+            |_ppu: &Ppu, _joypad: &mut Joypad| {},
+        );
+        bus.joypad1.set_button_status(JoypadButton::BUTTON_A, true);
+        bus.joypad2.set_button_status(JoypadButton::BUTTON_B, true);
+        bus.joypad2.set_button_status(JoypadButton::RIGHT, true);
+
+        bus.mem_write(0x4016, 1);
+        bus.mem_write(0x4016, 0);
+        let joypad2_bits: Vec<u8> = (0..8).map(|_| bus.mem_read(0x4017)).collect();
+        assert_eq!(joypad2_bits, vec![0, 1, 0, 0, 0, 0, 0, 1]);
+        assert_eq!(bus.mem_read(0x4016), 1);
+    }
+
+    #[test]
+    fn test_bus_dmc_dma_stalls_the_cpu() {
+        let mut rom = tests::create_simple_test_rom();
+        rom.prg_rom[0x4000] = 0xAB; // 0xC000
+        let mut bus = Bus::new(rom, |_ppu: &Ppu, _joypad: &mut Joypad| {});
+        bus.mem_write(0x4010, 0x8F); // IRQ enabled, fastest rate
+        bus.mem_write(0x4012, 0x00); // sample at 0xC000
+        bus.mem_write(0x4013, 0x01); // 17 bytes
+        bus.mem_write(0x4015, 0b10000);
+
+        // The first byte is fetched right away
+        bus.tick(1);
+        assert_eq!(bus.cycles(), 1 + DMC_DMA_CYCLES as usize);
+
+        // Then one byte every 8 output cycles of 54 CPU cycles, until the IRQ after the last one
+        assert!(!bus.poll_irq_status());
+        while bus.cycles() < 17 * 8 * 54 {
+            bus.tick(1);
+        }
+        assert!(bus.poll_irq_status());
+        assert!(!bus.apu().dmc_active());
+
+        // Reading the status doesn't acknowledge the DMC IRQ, writing it does
+        assert_eq!(bus.mem_read(0x4015), 0b1000_0000);
+        assert!(bus.poll_irq_status());
+        bus.mem_write(0x4015, 0);
+        assert!(!bus.poll_irq_status());
+    }
+
+    #[test]
+    fn test_bus_apu_status_read_acknowledges_frame_irq() {
+        let mut bus = Bus::new(
+            tests::create_simple_test_rom(),
+            |_ppu: &Ppu, _joypad: &mut Joypad| {},
+        );
+        while !bus.poll_irq_status() {
+            bus.tick(1);
+        }
+        assert_eq!(bus.mem_read(0x4015), 0b0100_0000);
+        assert!(!bus.poll_irq_status());
+        assert_eq!(bus.mem_read(0x4015), 0);
+    }
+
+    #[test]
+    fn test_bus_apu_frame_counter_mode() {
+        let mut bus = Bus::new(
+            tests::create_simple_test_rom(),
+            |_ppu: &Ppu, _joypad: &mut Joypad| {},
+        );
+        assert_eq!(bus.apu().frame_counter_mode(), 4);
+        bus.mem_write(0x4017, 0x80);
+        assert_eq!(bus.apu().frame_counter_mode(), 5);
+    }
 }
@@ -1,31 +1,132 @@
+use crate::nes::mapper::{Mapper, Mapper0, Mapper2, Mapper7};
+use std::error::Error;
+use std::fmt;
+use std::time::Duration;
+
 const NES_FILE_SIGNATURE: [u8; 4] = [0x4E, 0x45, 0x53, 0x1A];
 const PRG_ROM_PAGE_SIZE: usize = 16384; // 16KB
 const CHR_ROM_PAGE_SIZE: usize = 8192; // 8KB
+const PRG_RAM_PAGE_SIZE: usize = 8192; // 8KB
+const DEFAULT_RAM_SIZE: usize = 8192; // 8KB
+
+const HEADER_SIZE: usize = 16;
+const TRAINER_SIZE: usize = 512;
+
+const INES_VERSION_1: u8 = 0;
+const INES_VERSION_2: u8 = 2;
 
 #[derive(Debug, PartialEq)]
+pub enum RomError {
+    InvalidSignature,
+    UnsupportedInesVersion(u8),
+    UnsupportedMapper(u16),
+    /// The file is shorter than what the header says
+    TruncatedData,
+}
+
+impl fmt::Display for RomError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RomError::InvalidSignature => write!(f, "ROM data is not in iNES file format"),
+            RomError::UnsupportedInesVersion(version) => {
+                write!(f, "iNES version {} ROM format not supported", version)
+            }
+            RomError::UnsupportedMapper(mapper) => write!(f, "Mapper {} not supported", mapper),
+            RomError::TruncatedData => write!(f, "ROM data is shorter than its header says"),
+        }
+    }
+}
+
+impl Error for RomError {}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
 pub enum MirroringMode {
     Vertical,
     Horizontal,
     FourScreen,
+    /// All four nametables show the first 1KB of VRAM
+    SingleScreenLower,
+    /// All four nametables show the second 1KB of VRAM
+    SingleScreenUpper,
 }
 
+/// Video standard the game was made for. It sets the frame timing of the PPU.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub enum TvSystem {
+    #[default]
+    Ntsc,
+    Pal,
+}
+
+impl TvSystem {
+    /// Scanlines per frame, including vblank and the pre-render scanline
+    pub fn scanlines_per_frame(&self) -> u16 {
+        match self {
+            TvSystem::Ntsc => 262,
+            TvSystem::Pal => 312,
+        }
+    }
+
+    /// CPU cycles per frame, at 3 (NTSC) or 3.2 (PAL) PPU dots per CPU cycle. The real NTSC
+    /// console skips a dot every other frame with rendering on, which the PPU doesn't
+    /// emulate, so this matches the frames it actually produces.
+    pub fn cpu_cycles_per_frame(&self) -> f64 {
+        match self {
+            TvSystem::Ntsc => 262.0 * 341.0 / 3.0,
+            TvSystem::Pal => 312.0 * 341.0 / 3.2,
+        }
+    }
+
+    /// Frames per second of the emulated console, e.g. 60.0985 for NTSC
+    pub fn frame_rate(&self) -> f64 {
+        self.cpu_clock_rate() / self.cpu_cycles_per_frame()
+    }
+
+    /// Time a host should spend on each frame to run at the speed of the emulated console
+    pub fn frame_duration(&self) -> Duration {
+        Duration::from_secs_f64(1.0 / self.frame_rate())
+    }
+
+    /// CPU clock in Hz
+    pub fn cpu_clock_rate(&self) -> f64 {
+        match self {
+            TvSystem::Ntsc => 1_789_773.0,
+            TvSystem::Pal => 1_662_607.0,
+        }
+    }
+}
+
+/// A parsed iNES file. It can be cloned to run the same cartridge in several consoles.
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Rom {
     pub prg_rom: Vec<u8>,
+    /// Empty when the header reports zero CHR banks, the mapper then provides CHR RAM
     pub chr_rom: Vec<u8>,
-    pub mapper: u8,
+    /// PRG RAM (0x6000-0x7FFF), battery-backed or not, 0 if the cart has none
+    pub prg_ram_size: usize,
+    /// CHR RAM, 0 if the cart has CHR-ROM instead
+    pub chr_ram_size: usize,
+    pub mapper: u16,
+    pub submapper: u8,
     pub screen_mirroring: MirroringMode,
+    pub has_battery: bool,
+    pub tv_system: TvSystem,
 }
 
 impl Rom {
-    pub fn new(raw_data: &Vec<u8>) -> Result<Self, String> {
-        if &raw_data[0..4] != NES_FILE_SIGNATURE {
-            return Err("ROM data is not in iNES file format".to_string());
+    pub fn new(raw_data: &[u8]) -> Result<Self, RomError> {
+        if raw_data.len() < HEADER_SIZE {
+            return Err(RomError::TruncatedData);
+        }
+        if raw_data[0..4] != NES_FILE_SIGNATURE {
+            return Err(RomError::InvalidSignature);
         }
 
         let ines_version = (raw_data[7] >> 2) & 0b11;
-        if ines_version != 0 {
-            return Err("NES2.0 ROM format not supported".to_string());
+        if ines_version != INES_VERSION_1 && ines_version != INES_VERSION_2 {
+            return Err(RomError::UnsupportedInesVersion(ines_version));
         }
+        let is_nes2 = ines_version == INES_VERSION_2;
 
         let is_mirroring_four_screen = raw_data[6] & 0b1000 != 0;
         let is_mirroring_vertical = raw_data[6] & 0b1 != 0;
@@ -35,22 +136,255 @@ impl Rom {
             (false, false) => MirroringMode::Horizontal,
         };
 
-        let mapper = (raw_data[7] & 0b1111_0000) | (raw_data[6] >> 4);
+        let mut mapper = ((raw_data[7] & 0b1111_0000) | (raw_data[6] >> 4)) as u16;
+        let mut submapper = 0;
+        if is_nes2 {
+            // NES 2.0 extends the mapper number with the low nibble of byte 8
+            mapper |= ((raw_data[8] & 0b1111) as u16) << 8;
+            submapper = raw_data[8] >> 4;
+        }
+        let has_battery = raw_data[6] & 0b10 != 0;
+        // NES 2.0 keeps the timing in byte 12 (0: NTSC, 1: PAL, 2: multiple, 3: Dendy),
+        // iNES in bit 0 of byte 9. Anything that isn't PAL runs as NTSC.
+        let is_pal = if is_nes2 {
+            raw_data[12] & 0b11 == 1
+        } else {
+            raw_data[9] & 0b1 != 0
+        };
+        let tv_system = if is_pal {
+            TvSystem::Pal
+        } else {
+            TvSystem::Ntsc
+        };
         let skip_trainer = raw_data[6] & 0b100 != 0;
 
-        let prg_rom_size = raw_data[4] as usize * PRG_ROM_PAGE_SIZE;
-        let chr_rom_size = raw_data[5] as usize * CHR_ROM_PAGE_SIZE;
+        let (prg_ram_size, chr_ram_size) = if is_nes2 {
+            // Bytes 10 and 11: volatile RAM in the low nibble, battery-backed in the high one
+            (
+                Self::nes2_ram_size(raw_data[10] & 0b1111) + Self::nes2_ram_size(raw_data[10] >> 4),
+                Self::nes2_ram_size(raw_data[11] & 0b1111) + Self::nes2_ram_size(raw_data[11] >> 4),
+            )
+        } else {
+            // Byte 8 counts 8KB units of PRG RAM, with 0 meaning 8KB for compatibility.
+            // Carts without CHR-ROM get 8KB of CHR RAM.
+            (
+                (raw_data[8] as usize).max(1) * PRG_RAM_PAGE_SIZE,
+                if raw_data[5] == 0 {
+                    DEFAULT_RAM_SIZE
+                } else {
+                    0
+                },
+            )
+        };
 
-        let prg_rom_start_pos = 16 + if skip_trainer { 512 } else { 0 };
-        let chr_rom_start_pos = prg_rom_start_pos + prg_rom_size;
+        let (prg_rom_size, chr_rom_size) = if is_nes2 {
+            // The nibbles of byte 9 are the upper bits of the PRG and CHR sizes. Sizes too
+            // large to address can't be in the file either.
+            (
+                Self::nes2_rom_size(raw_data[4], raw_data[9] & 0b1111, PRG_ROM_PAGE_SIZE)
+                    .ok_or(RomError::TruncatedData)?,
+                Self::nes2_rom_size(raw_data[5], raw_data[9] >> 4, CHR_ROM_PAGE_SIZE)
+                    .ok_or(RomError::TruncatedData)?,
+            )
+        } else {
+            (
+                raw_data[4] as usize * PRG_ROM_PAGE_SIZE,
+                raw_data[5] as usize * CHR_ROM_PAGE_SIZE,
+            )
+        };
+
+        let prg_rom_start_pos = HEADER_SIZE + if skip_trainer { TRAINER_SIZE } else { 0 };
+        let chr_rom_start_pos = prg_rom_start_pos
+            .checked_add(prg_rom_size)
+            .ok_or(RomError::TruncatedData)?;
+        let chr_rom_end_pos = chr_rom_start_pos
+            .checked_add(chr_rom_size)
+            .ok_or(RomError::TruncatedData)?;
+        if raw_data.len() < chr_rom_end_pos {
+            return Err(RomError::TruncatedData);
+        }
 
         Ok(Rom {
             prg_rom: raw_data[prg_rom_start_pos..(prg_rom_start_pos + prg_rom_size)].to_vec(),
-            chr_rom: raw_data[chr_rom_start_pos..(chr_rom_start_pos + chr_rom_size)].to_vec(),
+            chr_rom: raw_data[chr_rom_start_pos..chr_rom_end_pos].to_vec(),
             mapper,
+            submapper,
             screen_mirroring,
+            has_battery,
+            tv_system,
+            prg_ram_size,
+            chr_ram_size,
         })
     }
+
+    /// The size in bytes, `None` if it doesn't fit in a usize
+    fn nes2_rom_size(lsb: u8, msb: u8, page_size: usize) -> Option<usize> {
+        if msb == 0b1111 {
+            // Exponent-multiplier notation: 2^E * (MM * 2 + 1) bytes, with lsb = EEEEEEMM
+            let exponent = (lsb >> 2) as u32;
+            let multiplier = (lsb & 0b11) as usize * 2 + 1;
+            2usize.checked_pow(exponent)?.checked_mul(multiplier)
+        } else {
+            ((msb as usize) << 8 | lsb as usize).checked_mul(page_size)
+        }
+    }
+
+    // RAM sizes are shift counts: 64 << shift bytes, 0 meaning no RAM
+    fn nes2_ram_size(shift: u8) -> usize {
+        if shift == 0 {
+            0
+        } else {
+            64 << shift
+        }
+    }
+
+    /// Parses the ROM but overrides the mapper from the header, e.g. for mis-dumped ROMs
+    pub fn with_forced_mapper(raw_data: &[u8], mapper: u16) -> Result<Self, RomError> {
+        let mut rom = Rom::new(raw_data)?;
+        rom.mapper = mapper;
+        Ok(rom)
+    }
+
+    /// Fails with `RomError::UnsupportedMapper` if the mapper isn't implemented. Such carts
+    /// still load, but run as mapper 0, which only works if they never switch banks.
+    pub fn check_mapper(&self) -> Result<(), RomError> {
+        match self.mapper {
+            0 | 2 | 7 => Ok(()),
+            mapper => Err(RomError::UnsupportedMapper(mapper)),
+        }
+    }
+
+    pub fn chr_rom(&self) -> &[u8] {
+        &self.chr_rom
+    }
+
+    pub fn mapper(&self) -> u16 {
+        self.mapper
+    }
+
+    pub fn screen_mirroring(&self) -> MirroringMode {
+        self.screen_mirroring
+    }
+
+    /// Builds the mapper for the parsed mapper number. It happens when the bus is created
+    /// rather than while parsing, so that overrides like `with_forced_mapper` apply.
+    /// Mappers that aren't implemented yet fall back to mapper 0, see `check_mapper`.
+    pub fn into_mapper(self) -> Box<dyn Mapper> {
+        match self.mapper {
+            2 => Box::new(
+                Mapper2::new(self.prg_rom, self.chr_rom, self.screen_mirroring)
+                    .with_chr_ram_size(self.chr_ram_size),
+            ),
+            7 => Box::new(
+                Mapper7::new(self.prg_rom, self.chr_rom).with_chr_ram_size(self.chr_ram_size),
+            ),
+            _ => Box::new(
+                Mapper0::new(
+                    self.prg_rom,
+                    self.chr_rom,
+                    self.screen_mirroring,
+                    self.has_battery,
+                )
+                .with_ram_sizes(self.prg_ram_size, self.chr_ram_size),
+            ),
+        }
+    }
+}
+
+/// Assembles an iNES image from its parts, e.g. to turn a test program into a `Rom`
+/// without doing the header math by hand.
+pub struct RomBuilder {
+    prg: Vec<u8>,
+    chr: Vec<u8>,
+    mapper: u8,
+    mirroring: MirroringMode,
+    reset_vector: Option<u16>,
+}
+
+impl RomBuilder {
+    pub fn new() -> Self {
+        RomBuilder {
+            prg: Vec::new(),
+            chr: Vec::new(),
+            mapper: 0,
+            mirroring: MirroringMode::Horizontal,
+            reset_vector: None,
+        }
+    }
+
+    /// PRG-ROM contents, padded with 0s to whole 16KB banks. With 16 or 32KB on mapper 0,
+    /// the first byte is at 0x8000.
+    pub fn prg(mut self, prg: Vec<u8>) -> Self {
+        self.prg = prg;
+        self
+    }
+
+    /// CHR-ROM contents, padded with 0s to whole 8KB banks. Without it the cart gets CHR RAM.
+    pub fn chr(mut self, chr: Vec<u8>) -> Self {
+        self.chr = chr;
+        self
+    }
+
+    pub fn mapper(mut self, mapper: u8) -> Self {
+        self.mapper = mapper;
+        self
+    }
+
+    /// Only the modes an iNES header can hold are kept, the single-screen ones are
+    /// selected at runtime by the mapper and end up as horizontal.
+    pub fn mirroring(mut self, mirroring: MirroringMode) -> Self {
+        self.mirroring = mirroring;
+        self
+    }
+
+    /// Writes the address into the reset vector (0xFFFC-0xFFFD) of the last PRG bank
+    pub fn reset_vector(mut self, addr: u16) -> Self {
+        self.reset_vector = Some(addr);
+        self
+    }
+
+    /// The iNES file, as it would be read from disk
+    pub fn ines_data(&self) -> Vec<u8> {
+        let mut prg = self.prg.clone();
+        let prg_len = Self::padded_len(prg.len(), PRG_ROM_PAGE_SIZE);
+        prg.resize(prg_len.max(PRG_ROM_PAGE_SIZE), 0);
+        if let Some(addr) = self.reset_vector {
+            let vector_pos = prg.len() - 4;
+            prg[vector_pos..vector_pos + 2].copy_from_slice(&addr.to_le_bytes());
+        }
+        let mut chr = self.chr.clone();
+        chr.resize(Self::padded_len(chr.len(), CHR_ROM_PAGE_SIZE), 0);
+
+        let mirroring_flags = match self.mirroring {
+            MirroringMode::Vertical => 0b1,
+            MirroringMode::FourScreen => 0b1000,
+            _ => 0,
+        };
+        let mut data = Vec::with_capacity(HEADER_SIZE + prg.len() + chr.len());
+        data.extend(&NES_FILE_SIGNATURE);
+        data.push((prg.len() / PRG_ROM_PAGE_SIZE) as u8);
+        data.push((chr.len() / CHR_ROM_PAGE_SIZE) as u8);
+        data.push((self.mapper << 4) | mirroring_flags);
+        data.push(self.mapper & 0b1111_0000);
+        data.resize(HEADER_SIZE, 0);
+        data.extend(prg);
+        data.extend(chr);
+        data
+    }
+
+    pub fn build(&self) -> Rom {
+        Rom::new(&self.ines_data()).expect("RomBuilder assembles a valid iNES image")
+    }
+
+    fn padded_len(len: usize, page_size: usize) -> usize {
+        len.div_ceil(page_size) * page_size
+    }
+}
+
+impl Default for RomBuilder {
+    fn default() -> Self {
+        RomBuilder::new()
+    }
 }
 
 #[cfg(test)]
@@ -82,38 +416,39 @@ pub mod tests {
     }
 
     pub fn create_simple_test_rom() -> Rom {
-        let test_rom = create_rom(InputRomData {
-            header: vec![
-                0x4E, 0x45, 0x53, 0x1A, 0x02, 0x01, 0x31, 00, 00, 00, 00, 00, 00, 00, 00, 00,
-            ],
-            trainer: None,
-            prg_rom: vec![1; 2 * PRG_ROM_PAGE_SIZE],
-            chr_rom: vec![2; 1 * CHR_ROM_PAGE_SIZE],
-        });
+        Rom::new(&create_simple_test_rom_data()).unwrap()
+    }
 
-        Rom::new(&test_rom).unwrap()
+    fn create_simple_test_rom_builder() -> RomBuilder {
+        RomBuilder::new()
+            .prg(vec![1; 2 * PRG_ROM_PAGE_SIZE])
+            .chr(vec![2; CHR_ROM_PAGE_SIZE])
+            .mapper(3)
+            .mirroring(MirroringMode::Vertical)
+    }
+
+    fn create_simple_test_rom_data() -> Vec<u8> {
+        create_simple_test_rom_builder().ines_data()
     }
 
     pub fn create_simple_test_rom_with_data(
         raw_prg_data: Vec<u8>,
         raw_chr_data: Option<Vec<u8>>,
     ) -> Rom {
-        let mut test_rom = create_simple_test_rom();
-
-        test_rom.prg_rom[0..raw_prg_data.len()].copy_from_slice(&raw_prg_data[..]);
-        test_rom.prg_rom[(0xFFFC-0x8000) as usize] = 0x00;
-        test_rom.prg_rom[(0xFFFD-0x8000) as usize] = 0x80;
-        // CPU reads position 0xFFFC to get the start of the program_counter
-        // The 0xFFFC-0x8000 is to set the address relative to zero
-        // CPU start at address 0x8000
-
-        if raw_chr_data.is_some() {
-            let chr_data = raw_chr_data.as_ref().unwrap();
-            test_rom.chr_rom[0..chr_data.len()]
-                .copy_from_slice(&chr_data[..]);
+        let mut prg = vec![1; 2 * PRG_ROM_PAGE_SIZE];
+        prg[0..raw_prg_data.len()].copy_from_slice(&raw_prg_data[..]);
+        let mut chr = vec![2; CHR_ROM_PAGE_SIZE];
+        if let Some(chr_data) = raw_chr_data {
+            chr[0..chr_data.len()].copy_from_slice(&chr_data[..]);
         }
 
-        test_rom
+        // CPU reads position 0xFFFC to get the start of the program_counter,
+        // the program starts at 0x8000
+        create_simple_test_rom_builder()
+            .prg(prg)
+            .chr(chr)
+            .reset_vector(0x8000)
+            .build()
     }
 
     #[test]
@@ -123,6 +458,20 @@ pub mod tests {
         assert_eq!(rom.chr_rom, vec![2; 1 * CHR_ROM_PAGE_SIZE]);
         assert_eq!(rom.mapper, 3);
         assert_eq!(rom.screen_mirroring, MirroringMode::Vertical);
+        assert!(!rom.has_battery);
+    }
+
+    #[test]
+    fn test_rom_clone() {
+        let rom = create_simple_test_rom();
+        let clone = rom.clone();
+        assert_eq!(clone, rom);
+        assert_eq!(clone.chr_rom(), &[2; CHR_ROM_PAGE_SIZE][..]);
+        assert_eq!(clone.mapper(), 3);
+        assert_eq!(clone.screen_mirroring(), MirroringMode::Vertical);
+
+        let forced = Rom::with_forced_mapper(&create_simple_test_rom_data(), 2).unwrap();
+        assert_ne!(forced, rom);
     }
 
     #[test]
@@ -159,10 +508,175 @@ pub mod tests {
     }
 
     #[test]
-    fn test_ines2_not_supported() {
+    fn test_rom_creation_with_forced_mapper() {
+        let rom = Rom::with_forced_mapper(&create_simple_test_rom_data(), 2).unwrap();
+        assert_eq!(rom.mapper, 2);
+        assert_eq!(rom.prg_rom, vec![1; 2 * PRG_ROM_PAGE_SIZE]);
+        assert_eq!(rom.chr_rom, vec![2; CHR_ROM_PAGE_SIZE]);
+        assert_eq!(rom.screen_mirroring, MirroringMode::Vertical);
+
+        // The simple test ROM has two PRG banks, mapper 2 (UxROM) switches the first one
+        let mut data = create_simple_test_rom_data();
+        data[HEADER_SIZE + PRG_ROM_PAGE_SIZE] = 0xAB;
+        let mut mapper = Rom::with_forced_mapper(&data, 2).unwrap().into_mapper();
+        assert_eq!(mapper.cpu_read(0x8000), 0x01);
+        assert_eq!(mapper.cpu_read(0xC000), 0xAB);
+        mapper.cpu_write(0x8000, 0x01);
+        assert_eq!(mapper.cpu_read(0x8000), 0xAB);
+    }
+
+    #[test]
+    fn test_rom_check_mapper() {
+        let rom = create_simple_test_rom();
+        let error = rom.check_mapper().err().unwrap();
+        assert_eq!(error, RomError::UnsupportedMapper(3));
+        assert_eq!(error.to_string(), "Mapper 3 not supported");
+
+        // A header with an unimplemented mapper can still be forced onto an implemented one
+        let rom = Rom::with_forced_mapper(&create_simple_test_rom_data(), 0).unwrap();
+        assert_eq!(rom.check_mapper(), Ok(()));
+    }
+
+    #[test]
+    fn test_rom_creation_from_embedded_bytes() {
+        const NESTEST: &[u8] = include_bytes!("../../platform/phantom_sandbox/nestest.nes");
+
+        let rom = Rom::new(NESTEST).unwrap();
+        assert_eq!(rom.prg_rom.len(), PRG_ROM_PAGE_SIZE);
+        assert_eq!(rom.prg_rom[..], NESTEST[16..16 + PRG_ROM_PAGE_SIZE]);
+        assert_eq!(rom.chr_rom.len(), CHR_ROM_PAGE_SIZE);
+        assert_eq!(rom.mapper, 0);
+        assert_eq!(rom.screen_mirroring, MirroringMode::Horizontal);
+    }
+
+    #[test]
+    fn test_ines2_rom_creation() {
+        let test_rom = create_rom(InputRomData {
+            header: vec![
+                // Mapper 0x123 with submapper 4, 2 PRG pages and 1 CHR page through byte 9
+                0x4E, 0x45, 0x53, 0x1A, 0x02, 0x01, 0x31, 0x28, 0x41, 00, 00, 00, 00, 00, 00, 00,
+            ],
+            trainer: None,
+            prg_rom: vec![1; 2 * PRG_ROM_PAGE_SIZE],
+            chr_rom: vec![2; CHR_ROM_PAGE_SIZE],
+        });
+        let rom = Rom::new(&test_rom).unwrap();
+        assert_eq!(rom.mapper, 0x123);
+        assert_eq!(rom.submapper, 4);
+        assert_eq!(rom.prg_rom.len(), 2 * PRG_ROM_PAGE_SIZE);
+        assert_eq!(rom.chr_rom.len(), CHR_ROM_PAGE_SIZE);
+        assert_eq!(rom.screen_mirroring, MirroringMode::Vertical);
+    }
+
+    #[test]
+    fn test_rom_builder() {
+        let rom = RomBuilder::new()
+            .prg(vec![0xEA, 0x00])
+            .mapper(0x17)
+            .mirroring(MirroringMode::FourScreen)
+            .reset_vector(0xC000)
+            .build();
+        assert_eq!(rom.prg_rom.len(), PRG_ROM_PAGE_SIZE);
+        assert_eq!(rom.prg_rom[..3], [0xEA, 0x00, 0x00]);
+        assert_eq!(rom.prg_rom[0x3FFC..0x3FFE], [0x00, 0xC0]);
+        assert!(rom.chr_rom.is_empty());
+        assert_eq!(rom.mapper, 0x17);
+        assert_eq!(rom.screen_mirroring, MirroringMode::FourScreen);
+
+        let rom = RomBuilder::new()
+            .prg(vec![0; PRG_ROM_PAGE_SIZE + 1])
+            .chr(vec![0xFF; 16])
+            .build();
+        assert_eq!(rom.prg_rom.len(), 2 * PRG_ROM_PAGE_SIZE);
+        assert_eq!(rom.chr_rom.len(), CHR_ROM_PAGE_SIZE);
+        assert_eq!(rom.screen_mirroring, MirroringMode::Horizontal);
+    }
+
+    #[test]
+    fn test_rom_tv_system() {
+        assert_eq!(create_simple_test_rom().tv_system, TvSystem::Ntsc);
+
+        let mut data = create_simple_test_rom_data();
+        data[9] = 0x01;
+        assert_eq!(Rom::new(&data).unwrap().tv_system, TvSystem::Pal);
+
+        // NES 2.0: byte 9 holds the ROM sizes, the timing is in byte 12
+        let mut data = create_simple_test_rom_data();
+        data[7] = 0x08;
+        data[12] = 0x01;
+        assert_eq!(Rom::new(&data).unwrap().tv_system, TvSystem::Pal);
+        data[12] = 0x03; // Dendy
+        assert_eq!(Rom::new(&data).unwrap().tv_system, TvSystem::Ntsc);
+    }
+
+    #[test]
+    fn test_tv_system_frame_timing() {
+        assert!((TvSystem::Ntsc.cpu_cycles_per_frame() - 29780.67).abs() < 0.01);
+        assert!((TvSystem::Ntsc.frame_rate() - 60.0985).abs() < 0.0001);
+        assert_eq!(TvSystem::Ntsc.frame_duration().as_micros(), 16639);
+        assert!((TvSystem::Pal.cpu_cycles_per_frame() - 33247.5).abs() < 0.01);
+        assert!((TvSystem::Pal.frame_rate() - 50.0070).abs() < 0.0001);
+    }
+
+    #[test]
+    fn test_rom_ram_sizes() {
+        let rom = create_simple_test_rom();
+        assert_eq!(rom.prg_ram_size, 0x2000); // 0 in byte 8 means 8KB
+        assert_eq!(rom.chr_ram_size, 0);
+
+        let mut data = create_simple_test_rom_data();
+        data[8] = 2;
+        assert_eq!(Rom::new(&data).unwrap().prg_ram_size, 0x4000);
+
+        let rom = RomBuilder::new().build(); // no CHR-ROM
+        assert_eq!(rom.chr_ram_size, 0x2000);
+
+        // NES 2.0: 64 << shift bytes, volatile plus battery-backed
+        let mut data = create_simple_test_rom_data();
+        data[7] = 0x08;
+        data[10] = 0x77;
+        data[11] = 0x00;
+        let rom = Rom::new(&data).unwrap();
+        assert_eq!(rom.prg_ram_size, 0x4000);
+        assert_eq!(rom.chr_ram_size, 0);
+        data[10] = 0x00;
+        data[11] = 0x07;
+        let rom = Rom::new(&data).unwrap();
+        assert_eq!(rom.prg_ram_size, 0);
+        assert_eq!(rom.chr_ram_size, 0x2000);
+    }
+
+    #[test]
+    fn test_ines2_rom_sizes() {
+        let size = Rom::nes2_rom_size(0x02, 0x1, PRG_ROM_PAGE_SIZE);
+        assert_eq!(size, Some(0x102 * PRG_ROM_PAGE_SIZE));
+        // 2^14 * (1 * 2 + 1) = 48KB
+        let size = Rom::nes2_rom_size(0b111001, 0xF, PRG_ROM_PAGE_SIZE);
+        assert_eq!(size, Some(48 * 1024));
+        // 2^63 * 3 overflows
+        assert_eq!(Rom::nes2_rom_size(0b11111101, 0xF, PRG_ROM_PAGE_SIZE), None);
+    }
+
+    #[test]
+    fn test_ines2_rom_size_overflow() {
+        // Exponent 63 for both sizes: 2^63 bytes each, which can't be added up
+        let mut data = create_simple_test_rom_data();
+        data[7] = 0x08;
+        data[9] = 0xFF;
+        data[4] = 0b11111100;
+        data[5] = 0b11111100;
+        assert_eq!(Rom::new(&data).err(), Some(RomError::TruncatedData));
+
+        // Multiplier 3: 2^63 * 3 bytes
+        data[4] = 0b11111101;
+        assert_eq!(Rom::new(&data).err(), Some(RomError::TruncatedData));
+    }
+
+    #[test]
+    fn test_unknown_ines_version_not_supported() {
         let test_rom = create_rom(InputRomData {
             header: vec![
-                0x4E, 0x45, 0x53, 0x1A, 0x01, 0x01, 0x31, 0x8, 00, 00, 00, 00, 00, 00, 00, 00,
+                0x4E, 0x45, 0x53, 0x1A, 0x01, 0x01, 0x31, 0x4, 00, 00, 00, 00, 00, 00, 00, 00,
             ],
             trainer: None,
             prg_rom: vec![1; 1 * PRG_ROM_PAGE_SIZE],
@@ -174,4 +688,23 @@ pub mod tests {
             Result::Err(_) => assert!(true),
         }
     }
+
+    #[test]
+    fn test_rom_errors() {
+        assert_eq!(Rom::new(&[0x4E, 0x45]).err(), Some(RomError::TruncatedData));
+
+        let mut raw_rom = create_simple_test_rom_data();
+        raw_rom[3] = 0x00;
+        assert_eq!(Rom::new(&raw_rom).err(), Some(RomError::InvalidSignature));
+
+        let mut raw_rom = create_simple_test_rom_data();
+        raw_rom[7] = 0b1100;
+        let error = Rom::new(&raw_rom).err().unwrap();
+        assert_eq!(error, RomError::UnsupportedInesVersion(3));
+        assert_eq!(error.to_string(), "iNES version 3 ROM format not supported");
+
+        let raw_rom = create_simple_test_rom_data();
+        let truncated = &raw_rom[..raw_rom.len() - 1];
+        assert_eq!(Rom::new(truncated).err(), Some(RomError::TruncatedData));
+    }
 }
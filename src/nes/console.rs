@@ -0,0 +1,223 @@
+//! A whole console behind a small frame-at-a-time API, for embedders that have no game loop
+//! of their own to hand to the `Bus` (e.g. a WASM build driven by `requestAnimationFrame`)
+use crate::nes::bus::Bus;
+use crate::nes::cartridge::{Rom, RomError};
+use crate::nes::cpu::Cpu;
+use crate::nes::joypad::{Joypad, JoypadButton};
+use crate::nes::ppu::Ppu;
+use crate::nes::render::frame::Frame;
+use crate::nes::state::{MachineState, StateError, StateReader, StateWriter};
+use std::collections::VecDeque;
+
+const REPORT_SIGNATURE: [u8; 4] = [b'P', b'H', b'R', 0x1A];
+// First controller input kept for bug reports, 10 seconds at 60 FPS
+const RECENT_INPUT_FRAMES: usize = 600;
+
+pub struct Console {
+    cpu: Cpu<'static>,
+    // Reused across frames, so stepping doesn't allocate
+    frame_rgba: Vec<u8>,
+    recent_input: VecDeque<u8>,
+}
+
+/// Everything needed to reproduce a bug, see `Console::capture_report`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BugReport {
+    pub state: MachineState,
+    /// The last completed frame as a PNG
+    pub frame_png: Vec<u8>,
+    /// The first controller's buttons on each of the last frames, oldest first, in the
+    /// same format as `Bus::stop_recording`
+    pub last_inputs: Vec<u8>,
+}
+
+impl BugReport {
+    /// The report as a single file, to attach to an issue
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut report = StateWriter::new();
+        report.write_bytes(&self.state.to_bytes());
+        report.write_bytes(&self.frame_png);
+        report.write_bytes(&self.last_inputs);
+        let mut bytes = REPORT_SIGNATURE.to_vec();
+        bytes.extend(report.into_bytes());
+        bytes
+    }
+
+    /// Reads back a report saved with `to_bytes`
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, StateError> {
+        if bytes.len() < REPORT_SIGNATURE.len() {
+            return Err(StateError::TruncatedData);
+        }
+        if bytes[0..4] != REPORT_SIGNATURE {
+            return Err(StateError::InvalidSignature);
+        }
+        let mut report = StateReader::new(&bytes[4..]);
+        Ok(BugReport {
+            state: MachineState::from_bytes(report.read_bytes()?)?,
+            frame_png: report.read_bytes()?.to_vec(),
+            last_inputs: report.read_bytes()?.to_vec(),
+        })
+    }
+}
+
+impl Console {
+    /// Loads an iNES file and powers the console on
+    pub fn new_from_rom_bytes(rom_bytes: &[u8]) -> Result<Self, RomError> {
+        let rom = Rom::new(rom_bytes)?;
+        let bus = Bus::new(rom, |_ppu: &Ppu, _joypad: &mut Joypad| {});
+        let mut cpu = Cpu::new(bus);
+        cpu.reset();
+        Ok(Console {
+            cpu,
+            frame_rgba: Frame::new().data_rgba(),
+            recent_input: VecDeque::with_capacity(RECENT_INPUT_FRAMES),
+        })
+    }
+
+    /// Runs the emulation until the PPU completes the next frame
+    pub fn step_frame(&mut self) {
+        if self.recent_input.len() == RECENT_INPUT_FRAMES {
+            self.recent_input.pop_front();
+        }
+        let buttons = self.cpu.bus_mut().joypad1_mut().pressed_buttons();
+        self.recent_input.push_back(buttons.bits());
+
+        let frame = self.cpu.run_until_frame();
+        frame.write_rgba(&mut self.frame_rgba);
+    }
+
+    /// The last completed frame as 256x240 RGBA pixels, black before the first `step_frame`
+    pub fn frame_rgba(&self) -> &[u8] {
+        &self.frame_rgba
+    }
+
+    /// Presses or releases a button of the first controller
+    pub fn set_button(&mut self, button: JoypadButton, pressed: bool) {
+        self.cpu
+            .bus_mut()
+            .joypad1_mut()
+            .set_button_status(button, pressed);
+    }
+
+    /// Sets the callback that receives the bus' diagnostic warnings, see `Bus::set_log_callback`
+    pub fn set_log_callback<F>(&mut self, log_callback: F)
+    where
+        F: FnMut(&str) + 'static,
+    {
+        self.cpu.bus_mut().set_log_callback(log_callback);
+    }
+
+    /// Snapshot of the machine, see `MachineState`
+    pub fn save_state(&self) -> MachineState {
+        self.cpu.save_state()
+    }
+
+    /// Restores a state saved while running the same ROM. The frame is updated on the next
+    /// `step_frame`.
+    pub fn load_state(&mut self, state: &MachineState) -> Result<(), StateError> {
+        self.cpu.load_state(state)
+    }
+
+    /// Bundles the machine state, the last completed frame and the input of the last
+    /// frames (up to 10 seconds), e.g. to attach a reproducing snapshot to an issue
+    pub fn capture_report(&self) -> BugReport {
+        BugReport {
+            state: self.save_state(),
+            frame_png: self.cpu.bus().ppu().frame().png_bytes(),
+            last_inputs: self.recent_input.iter().copied().collect(),
+        }
+    }
+
+    pub fn cpu(&self) -> &Cpu<'static> {
+        &self.cpu
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nes::cartridge::RomBuilder;
+
+    #[test]
+    fn test_console_rejects_invalid_rom() {
+        assert!(Console::new_from_rom_bytes(&[0; 16]).is_err());
+    }
+
+    #[test]
+    fn test_console_step_frame() {
+        // Strobes the first controller, stores its A button in $00 and loops
+        let program = vec![
+            0xA9, 0x01, 0x8D, 0x16, 0x40, // LDA #$01; STA $4016
+            0xA9, 0x00, 0x8D, 0x16, 0x40, // LDA #$00; STA $4016
+            0xAD, 0x16, 0x40, 0x85, 0x00, // LDA $4016; STA $00
+            0x4C, 0x00, 0x80, // JMP $8000
+        ];
+        let rom = RomBuilder::new()
+            .prg(program)
+            .reset_vector(0x8000)
+            .ines_data();
+        let mut console = Console::new_from_rom_bytes(&rom).unwrap();
+        assert_eq!(console.frame_rgba().len(), 256 * 240 * 4);
+
+        console.set_button(JoypadButton::BUTTON_A, true);
+        console.step_frame();
+        assert_eq!(console.frame_rgba().len(), 256 * 240 * 4);
+        assert_eq!(console.frame_rgba()[3], 0xFF);
+        assert_eq!(console.cpu().mem_peek(0x00), 1);
+
+        console.set_button(JoypadButton::BUTTON_A, false);
+        console.step_frame();
+        assert_eq!(console.cpu().mem_peek(0x00), 0);
+    }
+
+    #[test]
+    fn test_console_capture_report() {
+        // Counts loop iterations in $01-$02 and shows them in the palette
+        let program = vec![
+            0xE6, 0x01, 0xD0, 0x02, 0xE6, 0x02, // INC $01; BNE +2; INC $02
+            0xA9, 0x3F, 0x8D, 0x06, 0x20, // LDA #$3F; STA $2006
+            0xA9, 0x00, 0x8D, 0x06, 0x20, // LDA #$00; STA $2006
+            0xA5, 0x02, 0x8D, 0x07, 0x20, // LDA $02; STA $2007
+            0x4C, 0x00, 0x80, // JMP $8000
+        ];
+        let rom = RomBuilder::new()
+            .prg(program)
+            .reset_vector(0x8000)
+            .ines_data();
+        let mut console = Console::new_from_rom_bytes(&rom).unwrap();
+        console.step_frame();
+        console.set_button(JoypadButton::BUTTON_A, true);
+        console.step_frame();
+        console.step_frame();
+
+        let report = console.capture_report();
+        assert_eq!(report.last_inputs, vec![0x00, 0x01, 0x01]);
+        assert_eq!(&report.frame_png[1..4], b"PNG");
+        let loaded = BugReport::from_bytes(&report.to_bytes()).unwrap();
+        assert_eq!(loaded, report);
+
+        // A console running the same ROM picks up where the reported one was
+        let mut reproduced = Console::new_from_rom_bytes(&rom).unwrap();
+        reproduced.load_state(&loaded.state).unwrap();
+        assert_eq!(reproduced.save_state(), report.state);
+        reproduced.set_button(JoypadButton::BUTTON_A, true);
+        console.step_frame();
+        reproduced.step_frame();
+        assert_ne!(console.cpu().mem_peek(0x02), 0);
+        assert_eq!(
+            reproduced.cpu().mem_peek(0x01),
+            console.cpu().mem_peek(0x01)
+        );
+        assert_eq!(
+            reproduced.cpu().mem_peek(0x02),
+            console.cpu().mem_peek(0x02)
+        );
+        assert_eq!(reproduced.frame_rgba(), console.frame_rgba());
+        assert_eq!(reproduced.save_state(), console.save_state());
+
+        assert_eq!(
+            BugReport::from_bytes(&report.state.to_bytes()),
+            Err(StateError::InvalidSignature)
+        );
+    }
+}
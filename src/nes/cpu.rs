@@ -1,10 +1,14 @@
 use crate::nes::bus::Bus;
+use crate::nes::debug;
 /// Implementation of the NES' custom 6502 CPU
 use crate::nes::memory::Memory;
 use crate::nes::opcodes::{AddressingMode, OpCode, OPCODES_MAP};
+use crate::nes::render::frame::Frame;
 use crate::nes::interrupt;
+use crate::nes::state::{MachineState, StateError, StateWriter};
 use bitflags::bitflags;
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
+use std::fmt::Write;
 
 const ZEROTH_BIT: u8 = 0b00000001;
 const FIRST_BIT: u8 = 0b00000010;
@@ -20,6 +24,9 @@ const PROGRAM_ROM_START_ADDR: u16 = 0x8000;
 const STACK_START_ADDR: u16 = 0x0100;
 const STACK_RESET_ADDR: u8 = 0xFD;
 
+const RECENT_INSTRUCTIONS_SIZE: usize = 32;
+const PANIC_CONTEXT_INSTRUCTIONS: usize = 5;
+
 bitflags! {
     /// # Status Register (P) http://wiki.nesdev.com/w/index.php/Status_flags
     ///
@@ -53,6 +60,15 @@ pub struct Cpu<'a> {
     program_counter: u16,
     stack_pointer: u8,
     bus: Bus<'a>,
+
+    allow_unofficial_opcodes: bool,
+    decimal_enabled: bool,
+    recent_instructions: VecDeque<(u16, u8)>,
+    stop_requested: bool,
+    halted: bool,
+    // Raised through `trigger_nmi` and `trigger_irq`, on top of the bus' interrupt lines
+    nmi_pending: bool,
+    irq_pending: bool,
 }
 
 impl Memory for Cpu<'_> {
@@ -83,6 +99,13 @@ impl<'a> Cpu<'a> {
             program_counter: 0,
             stack_pointer: STACK_RESET_ADDR,
             bus,
+            allow_unofficial_opcodes: true,
+            decimal_enabled: false,
+            recent_instructions: VecDeque::with_capacity(RECENT_INSTRUCTIONS_SIZE),
+            stop_requested: false,
+            halted: false,
+            nmi_pending: false,
+            irq_pending: false,
         }
     }
 
@@ -92,8 +115,51 @@ impl<'a> Cpu<'a> {
         self.register_y = 0;
         self.status = CpuFlags::from_bits_truncate(0b100100);
         self.stack_pointer = STACK_RESET_ADDR;
+        self.halted = false;
+        self.nmi_pending = false;
+        self.irq_pending = false;
+        self.bus.reset();
 
         self.program_counter = self.mem_read_u16(0xFFFC);
+        if self.program_counter < PROGRAM_ROM_START_ADDR {
+            // Usually a bad ROM dump or a misconfigured mapper
+            self.bus.log(format_args!(
+                "Reset vector {:#06X} points outside of PRG-ROM",
+                self.program_counter
+            ));
+        }
+    }
+
+    /// Snapshot of the CPU registers and everything on the bus, see `MachineState`
+    pub fn save_state(&self) -> MachineState {
+        let mut state = StateWriter::new();
+        state.write_u8(self.register_a);
+        state.write_u8(self.register_x);
+        state.write_u8(self.register_y);
+        state.write_u8(self.status.bits());
+        state.write_u16(self.program_counter);
+        state.write_u8(self.stack_pointer);
+        state.write_bool(self.halted);
+        state.write_bool(self.nmi_pending);
+        state.write_bool(self.irq_pending);
+        self.bus.save_state(&mut state);
+        state.into_state()
+    }
+
+    /// Restores a state saved with `save_state` while running the same ROM.
+    /// On error the machine is left partially restored, e.g. reset it before going on.
+    pub fn load_state(&mut self, state: &MachineState) -> Result<(), StateError> {
+        let mut state = state.reader();
+        self.register_a = state.read_u8()?;
+        self.register_x = state.read_u8()?;
+        self.register_y = state.read_u8()?;
+        self.status = CpuFlags::from_bits_truncate(state.read_u8()?);
+        self.program_counter = state.read_u16()?;
+        self.stack_pointer = state.read_u8()?;
+        self.halted = state.read_bool()?;
+        self.nmi_pending = state.read_bool()?;
+        self.irq_pending = state.read_bool()?;
+        self.bus.load_state(&mut state)
     }
 
     #[deprecated = "No longer usable due to prg_rom being looked for writes"]
@@ -115,6 +181,37 @@ impl<'a> Cpu<'a> {
         self.run_with_callback(|_| {});
     }
 
+    /// Runs until the PPU completes a frame and returns it rendered, so that headless
+    /// front-ends (tests, WASM) can drive the emulation one frame at a time
+    pub fn run_until_frame(&mut self) -> &Frame {
+        // Drops a frame completed by an earlier run, which isn't the one the caller waits for
+        self.bus.take_frame();
+        self.run_with_callback(|cpu| {
+            if cpu.bus.frame_ready() {
+                cpu.stop();
+            }
+        });
+        // A halted CPU returns early, but the PPU keeps drawing frames
+        while !self.bus.frame_ready() {
+            self.bus.tick(1);
+        }
+        self.bus.take_frame().expect("A frame is ready")
+    }
+
+    /// Runs until at least `budget` CPU cycles have elapsed, or the CPU halts, so that callers
+    /// (e.g. fuzzers) can bound the execution of programs that never stop on their own.
+    /// The last instruction can overshoot the budget by a few cycles.
+    pub fn run_for_cycles(&mut self, budget: usize) {
+        let end = self.total_cycles() + budget;
+        self.run_with_callback(|cpu| {
+            if cpu.total_cycles() >= end {
+                cpu.stop();
+            }
+        });
+    }
+
+    /// Runs the program calling `callback` right before every instruction, e.g. to trace it.
+    /// Frames keep being delivered through the bus' game loop callback in the meantime.
     pub fn run_with_callback<F>(&mut self, mut callback: F)
     where
         F: FnMut(&mut Cpu),
@@ -122,23 +219,33 @@ impl<'a> Cpu<'a> {
         let ref opcodes: HashMap<u8, &'static OpCode> = *OPCODES_MAP;
 
         loop {
-            if let Some(_nmi) = self.bus.poll_nmi_status() {
-                self.manage_interrupt(interrupt::NMI);
+            if self.halted {
+                return;
             }
+            self.poll_interrupts();
 
             callback(self);
+            if self.stop_requested {
+                self.stop_requested = false;
+                return;
+            }
 
             let code = self.mem_read(self.program_counter);
-            self.program_counter += 1;
+            self.record_instruction(self.program_counter, code);
+            self.program_counter = self.program_counter.wrapping_add(1);
             let program_counter_state = self.program_counter;
 
             let opcode = opcodes
                 .get(&code)
                 .expect(&format!("OpCode {:x} could not be recognised!", code));
 
+            if !self.allow_unofficial_opcodes && !opcode.is_official() {
+                self.illegal_opcode(code);
+            }
+
             match code {
                 0xEA => { /* NOP - Do Nothing */ }
-                0x00 => return,
+                0x00 => self.brk(),
                 0x40 => {
                     self.rti();
                 }
@@ -360,9 +467,7 @@ impl<'a> Cpu<'a> {
                     self.isb(opcode.mode());
                 }
                 0x02 | 0x12 | 0x22 | 0x32 | 0x42 | 0x52 | 0x62 | 0x72 | 0x92 | 0xB2 | 0xD2
-                | 0xF2 => {
-                    // NOP - do nothing
-                }
+                | 0xF2 => self.jam(),
                 0x1A | 0x3A | 0x5A | 0x7A | 0xDA | 0xFA => {
                     // NOP - do nothing
                 }
@@ -393,19 +498,57 @@ impl<'a> Cpu<'a> {
                 0x87 | 0x97 | 0x8F | 0x83 => {
                     self.sax(opcode.mode());
                 }
-                _ => {
-                    panic!("OpCode {} is not a valid instruction!", code);
-                }
             }
 
             self.bus.tick(opcode.cycles());
 
             if program_counter_state == self.program_counter {
-                self.program_counter += (opcode.len() - 1) as u16;
+                self.program_counter = self.program_counter.wrapping_add((opcode.len() - 1) as u16);
             }
         }
     }
 
+    /// Makes `run`/`run_with_callback` return before executing the next instruction.
+    /// Meant to be called from the `run_with_callback` callback. The bus' game loop callback
+    /// has no access to the CPU, to stop at a frame boundary see `run_until_frame`.
+    pub fn stop(&mut self) {
+        self.stop_requested = true;
+    }
+
+    /// Whether a KIL/JAM opcode froze the CPU. Only a reset gets it running again.
+    pub fn is_halted(&self) -> bool {
+        self.halted
+    }
+
+    /// CPU cycles elapsed since the last reset, page crosses, taken branches,
+    /// interrupts and OAM DMA stalls included
+    pub fn total_cycles(&self) -> usize {
+        self.bus.cycles()
+    }
+
+    fn record_instruction(&mut self, addr: u16, code: u8) {
+        if self.recent_instructions.len() == RECENT_INSTRUCTIONS_SIZE {
+            self.recent_instructions.pop_front();
+        }
+        self.recent_instructions.push_back((addr, code));
+    }
+
+    fn illegal_opcode(&self, code: u8) -> ! {
+        panic!("OpCode {} is not a valid instruction!", code);
+    }
+
+    fn jam(&mut self) {
+        // The PC stays on the JAM opcode, like on hardware
+        self.program_counter = self.program_counter.wrapping_sub(1);
+        self.halted = true;
+    }
+
+    fn brk(&mut self) {
+        // BRK is followed by a padding byte, so the pushed return address skips it
+        self.program_counter = self.program_counter.wrapping_add(1);
+        self.manage_interrupt(interrupt::BRK);
+    }
+
     fn rti(&mut self) {
         self.status.bits = self.stack_pop();
         self.status.remove(CpuFlags::BREAK);
@@ -435,7 +578,7 @@ impl<'a> Cpu<'a> {
     }
 
     fn asl(&mut self, mode: &AddressingMode) -> u8 {
-        let (addr, _) = self.compute_operand_address(mode);
+        let addr = self.compute_write_address(mode);
         let mut mem_value = self.mem_read(addr);
 
         let carry_val = SEVENTH_BIT & mem_value;
@@ -499,7 +642,7 @@ impl<'a> Cpu<'a> {
     }
 
     fn dec(&mut self, mode: &AddressingMode) {
-        let (addr, _) = self.compute_operand_address(mode);
+        let addr = self.compute_write_address(mode);
         let mut mem_value = self.mem_read(addr);
         mem_value = mem_value.wrapping_sub(1);
         self.mem_write(addr, mem_value);
@@ -518,7 +661,7 @@ impl<'a> Cpu<'a> {
     }
 
     fn lsr(&mut self, mode: &AddressingMode) -> u8 {
-        let (addr, _) = self.compute_operand_address(mode);
+        let addr = self.compute_write_address(mode);
         let mut mem_value = self.mem_read(addr);
 
         if mem_value & ZEROTH_BIT == 1 {
@@ -555,7 +698,7 @@ impl<'a> Cpu<'a> {
     }
 
     fn rol(&mut self, mode: &AddressingMode) -> u8 {
-        let (addr, _) = self.compute_operand_address(mode);
+        let addr = self.compute_write_address(mode);
         let mem_value = self.mem_read(addr);
         let result = self.rol_internal(mem_value);
         self.mem_write(addr, result);
@@ -586,7 +729,7 @@ impl<'a> Cpu<'a> {
     }
 
     fn ror(&mut self, mode: &AddressingMode) -> u8 {
-        let (addr, _) = self.compute_operand_address(mode);
+        let addr = self.compute_write_address(mode);
         let mem_value = self.mem_read(addr);
         let result = self.ror_internal(mem_value);
         self.mem_write(addr, result);
@@ -670,13 +813,14 @@ impl<'a> Cpu<'a> {
     }
 
     fn jsr(&mut self) {
-        self.stack_push_u16(self.program_counter + 2 /*+2 extra bytes to read*/ - 1);
+        // The return address is the last byte of the JSR, +2 extra bytes to read -1
+        self.stack_push_u16(self.program_counter.wrapping_add(1));
         let addr = self.mem_read_u16(self.program_counter);
         self.program_counter = addr;
     }
 
     fn rts(&mut self) {
-        self.program_counter = self.stack_pop_u16() + 1;
+        self.program_counter = self.stack_pop_u16().wrapping_add(1);
     }
 
     fn dex(&mut self) {
@@ -688,7 +832,7 @@ impl<'a> Cpu<'a> {
     }
 
     fn inc(&mut self, mode: &AddressingMode) -> u8 {
-        let (addr, _) = self.compute_operand_address(mode);
+        let addr = self.compute_write_address(mode);
         let mut mem_value = self.mem_read(addr);
         mem_value = mem_value.wrapping_add(1);
         self.mem_write(addr, mem_value);
@@ -736,7 +880,7 @@ impl<'a> Cpu<'a> {
     }
 
     fn sta(&mut self, mode: &AddressingMode) {
-        let (addr, _) = self.compute_operand_address(mode);
+        let addr = self.compute_write_address(mode);
         self.mem_write(addr, self.register_a);
     }
 
@@ -798,7 +942,7 @@ impl<'a> Cpu<'a> {
     }
 
     fn dcp(&mut self, mode: &AddressingMode) {
-        let (addr, _) = self.compute_operand_address(mode);
+        let addr = self.compute_write_address(mode);
         let mut mem_value = self.mem_read(addr);
         mem_value = mem_value.wrapping_sub(1);
         self.mem_write(addr, mem_value);
@@ -910,7 +1054,7 @@ impl<'a> Cpu<'a> {
     }
 
     fn las(&mut self, mode: &AddressingMode) {
-        let (addr, _) = self.compute_operand_address(mode);
+        let (addr, page_cross) = self.compute_operand_address(mode);
         let mut mem_value = self.mem_read(addr);
         mem_value &= self.stack_pointer;
         self.register_a = mem_value; // Code repetition to avoid unnecessary multiple flag updates
@@ -918,38 +1062,58 @@ impl<'a> Cpu<'a> {
         self.stack_pointer = mem_value;
         self.update_zero_flag(mem_value);
         self.update_negative_flag(mem_value);
+
+        if page_cross {
+            self.bus.tick(1);
+        }
     }
 
     fn tas(&mut self, mode: &AddressingMode) {
         self.stack_pointer = self.register_a & self.register_x;
-        let (addr, _) = self.compute_operand_address(mode);
-        let result = ((addr >> 8) as u8 + 1) & self.stack_pointer;
-        self.mem_write(addr, result);
+        self.unstable_store(mode, self.stack_pointer);
     }
 
     fn ahx(&mut self, mode: &AddressingMode) {
-        let (addr, _) = self.compute_operand_address(mode);
-        let result = self.register_a & self.register_x & (addr >> 8) as u8;
-        self.mem_write(addr, result);
+        self.unstable_store(mode, self.register_a & self.register_x);
     }
 
     fn shx(&mut self, mode: &AddressingMode) {
-        let (addr, _) = self.compute_operand_address(mode);
-        let result = self.register_x & ((addr >> 8) as u8 + 1);
-        self.mem_write(addr, result);
+        self.unstable_store(mode, self.register_x);
     }
 
     fn shy(&mut self, mode: &AddressingMode) {
-        let (addr, _) = self.compute_operand_address(mode);
-        let result = self.register_y & ((addr >> 8) as u8 + 1);
+        self.unstable_store(mode, self.register_y);
+    }
+
+    // https://www.nesdev.org/wiki/Programming_with_unofficial_opcodes
+    // TAS, AHX, SHX and SHY store the value ANDed with the high byte of the base (un-indexed)
+    // address plus 1. When the indexing crosses a page, the stored value also replaces the
+    // high byte of the address that is written to.
+    fn unstable_store(&mut self, mode: &AddressingMode, value: u8) {
+        let addr = self.compute_write_address(mode);
+        let index = match mode {
+            AddressingMode::AbsoluteX => self.register_x,
+            _ => self.register_y,
+        };
+        let base = addr.wrapping_sub(index as u16);
+        let result = value & ((base >> 8) as u8).wrapping_add(1);
+        let addr = if self.page_cross(base, addr) {
+            (result as u16) << 8 | (addr & 0x00FF)
+        } else {
+            addr
+        };
         self.mem_write(addr, result);
     }
 
     fn lax(&mut self, mode: &AddressingMode) {
-        let (addr, _) = self.compute_operand_address(mode);
+        let (addr, page_cross) = self.compute_operand_address(mode);
         let mem_value = self.mem_read(addr);
         self.set_register_a(mem_value);
         self.register_x = mem_value;
+
+        if page_cross {
+            self.bus.tick(1);
+        }
     }
 
     fn sax(&mut self, mode: &AddressingMode) {
@@ -997,6 +1161,18 @@ impl<'a> Cpu<'a> {
     }
 
     fn add_to_register_a(&mut self, data: u8) {
+        let register_a = self.register_a;
+        let carry_in = self.status.contains(CpuFlags::CARRY);
+        self.add_to_register_a_binary(data);
+
+        if self.is_decimal_arithmetic() {
+            let (result, carry_out) = Self::bcd_add(register_a, data, carry_in);
+            self.register_a = result;
+            self.status.set(CpuFlags::CARRY, carry_out);
+        }
+    }
+
+    fn add_to_register_a_binary(&mut self, data: u8) {
         let carry_in: u16 = if self.status.contains(CpuFlags::CARRY) {
             1
         } else {
@@ -1027,7 +1203,45 @@ impl<'a> Cpu<'a> {
     }
 
     fn sub_to_register_a(&mut self, data: u8) {
-        self.add_to_register_a((data as i8).wrapping_neg().wrapping_sub(1) as u8);
+        let register_a = self.register_a;
+        let carry_in = self.status.contains(CpuFlags::CARRY);
+        self.add_to_register_a_binary((data as i8).wrapping_neg().wrapping_sub(1) as u8);
+
+        if self.is_decimal_arithmetic() {
+            self.register_a = Self::bcd_sub(register_a, data, carry_in);
+        }
+    }
+
+    // Like on the NMOS 6502, the flags other than carry keep following the binary result
+    fn is_decimal_arithmetic(&self) -> bool {
+        self.decimal_enabled && self.status.contains(CpuFlags::DECIMAL_MODE)
+    }
+
+    fn bcd_add(a: u8, b: u8, carry_in: bool) -> (u8, bool) {
+        let mut low = (a & 0x0F) + (b & 0x0F) + carry_in as u8;
+        let mut high = (a >> 4) + (b >> 4);
+        if low > 9 {
+            low += 6;
+            high += 1;
+        }
+        if high > 9 {
+            high += 6;
+        }
+        ((high << 4) | (low & 0x0F), high > 0x0F)
+    }
+
+    // The carry is the same as in binary subtraction, only the result needs adjusting
+    fn bcd_sub(a: u8, b: u8, carry_in: bool) -> u8 {
+        let mut low = (a & 0x0F) as i8 - (b & 0x0F) as i8 - !carry_in as i8;
+        let mut high = (a >> 4) as i8 - (b >> 4) as i8;
+        if low < 0 {
+            low -= 6;
+            high -= 1;
+        }
+        if high < 0 {
+            high -= 6;
+        }
+        ((high << 4) as u8) | (low as u8 & 0x0F)
     }
 
     fn and_with_register_a(&mut self, data: u8) {
@@ -1093,10 +1307,31 @@ impl<'a> Cpu<'a> {
     }
 
     fn compute_operand_address(&mut self, mode: &AddressingMode) -> (u16, bool) {
-        match mode {
+        let (addr, page_cross) = match mode {
             AddressingMode::Immediate => (self.program_counter, false),
             _ => self.compute_real_address(mode, self.program_counter),
+        };
+        if page_cross {
+            // The CPU adds the index to the low byte first, reading from the unfixed address
+            // (still in the base page) before it carries into the high byte
+            self.mem_read(addr.wrapping_sub(0x100));
         }
+        (addr, page_cross)
+    }
+
+    // Stores and read-modify-write instructions always take the extra cycle with indexed
+    // addressing, so the dummy read happens even when no page is crossed
+    fn compute_write_address(&mut self, mode: &AddressingMode) -> u16 {
+        let (addr, page_cross) = self.compute_operand_address(mode);
+        match mode {
+            AddressingMode::AbsoluteX | AddressingMode::AbsoluteY | AddressingMode::IndirectY
+                if !page_cross =>
+            {
+                self.mem_read(addr);
+            }
+            _ => {}
+        }
+        addr
     }
 
     pub fn compute_real_address(&mut self, mode: &AddressingMode, addr: u16) -> (u16, bool) {
@@ -1127,18 +1362,14 @@ impl<'a> Cpu<'a> {
                 let base = self.mem_read(addr);
 
                 let ptr = base.wrapping_add(self.register_x);
-                let lo = self.mem_read(ptr as u16);
-                let hi = self.mem_read(ptr.wrapping_add(1) as u16);
-                ((hi as u16) << 8 | (lo as u16), false)
+                (self.mem_read_u16_zp(ptr), false)
             }
             AddressingMode::IndirectY => {
                 let base = self.mem_read(addr);
 
-                let lo = self.mem_read(base as u16);
-                let hi = self.mem_read((base as u8).wrapping_add(1) as u16);
-                let deref_base = (hi as u16) << 8 | (lo as u16);
+                let deref_base = self.mem_read_u16_zp(base);
                 let deref = deref_base.wrapping_add(self.register_y as u16);
-                (deref, self.page_cross(deref, deref_base))
+                (deref, self.page_cross(deref_base, deref))
             }
             _ => {
                 panic!("Memory addressing mode {:?} is not supported", mode);
@@ -1150,11 +1381,38 @@ impl<'a> Cpu<'a> {
         addr1 & 0xFF00 != addr2 & 0xFF00
     }
 
+    // NMI takes priority over IRQ, which is ignored while interrupts are disabled
+    fn poll_interrupts(&mut self) {
+        // Both NMI sources are polled, so that one that happens alongside the other is not
+        // serviced twice
+        let nmi = self.bus.poll_nmi_status().is_some() | std::mem::take(&mut self.nmi_pending);
+        if nmi {
+            self.manage_interrupt(interrupt::NMI);
+        } else if (self.bus.poll_irq_status() || self.irq_pending)
+            && !self.status.contains(CpuFlags::INTERRUPT_DISABLE)
+        {
+            self.irq_pending = false;
+            self.manage_interrupt(interrupt::IRQ);
+        }
+    }
+
+    /// Raises an NMI, serviced before the next instruction, e.g. to test NMI handlers
+    /// without running the PPU until vblank
+    pub fn trigger_nmi(&mut self) {
+        self.nmi_pending = true;
+    }
+
+    /// Raises an IRQ, serviced before the first instruction that runs with interrupts
+    /// enabled. Unlike the bus' IRQ sources it needs no acknowledge, it is serviced once.
+    pub fn trigger_irq(&mut self) {
+        self.irq_pending = true;
+    }
+
     fn manage_interrupt(&mut self, interrupt: interrupt::Interrupt) {
         self.stack_push_u16(self.program_counter);
         let mut status_flags = self.status.clone();
-        status_flags.set(CpuFlags::BREAK, interrupt.b_flag_mask & FOURTH_BIT == 1);
-        status_flags.set(CpuFlags::BREAK2, interrupt.b_flag_mask & FIFTH_BIT == 1);
+        status_flags.set(CpuFlags::BREAK, interrupt.b_flag_mask & FOURTH_BIT != 0);
+        status_flags.set(CpuFlags::BREAK2, interrupt.b_flag_mask & FIFTH_BIT != 0);
 
         self.stack_push(status_flags.bits());
         self.status.insert(CpuFlags::INTERRUPT_DISABLE);
@@ -1163,6 +1421,88 @@ impl<'a> Cpu<'a> {
         self.program_counter = self.mem_read_u16(interrupt.vec_addr);
     }
 
+    /// Whether undocumented opcodes get executed (the NES relies on them)
+    /// or are treated as illegal instructions, like a strict 6502 would.
+    pub fn set_allow_unofficial_opcodes(&mut self, allow: bool) {
+        self.allow_unofficial_opcodes = allow;
+    }
+
+    pub fn allow_unofficial_opcodes(&self) -> bool {
+        self.allow_unofficial_opcodes
+    }
+
+    /// Whether ADC and SBC honor the decimal flag. The NES' 2A03 has no decimal mode,
+    /// so it is off by default; enable it to emulate a plain 6502.
+    pub fn set_decimal_supported(&mut self, supported: bool) {
+        self.decimal_enabled = supported;
+    }
+
+    pub fn decimal_supported(&self) -> bool {
+        self.decimal_enabled
+    }
+
+    /// Last executed instructions as (address, opcode) pairs, from oldest to newest
+    pub fn recent_instructions(&self) -> Vec<(u16, u8)> {
+        self.recent_instructions.iter().copied().collect()
+    }
+
+    /// Disassembles `count` consecutive instructions starting at `start`, e.g. for a code
+    /// listing. Memory is peeked, so it has no side effects on the PPU or the mappers.
+    pub fn disassemble_range(&self, start: u16, count: usize) -> Vec<(u16, String)> {
+        let mut result = Vec::with_capacity(count);
+        let mut addr = start;
+        for _ in 0..count {
+            let (text, len) = debug::disassemble_instruction(self, addr);
+            result.push((addr, text));
+            addr = addr.wrapping_add(len as u16);
+        }
+        result
+    }
+
+    /// Formats the CPU state for a crash report: the registers, the code
+    /// at the program counter and the last executed instructions
+    pub fn panic_context(&self) -> String {
+        let mut context = String::new();
+        writeln!(context, "PC: ${:04X}", self.program_counter).unwrap();
+        writeln!(
+            context,
+            "A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X}",
+            self.register_a,
+            self.register_x,
+            self.register_y,
+            self.status.bits(),
+            self.stack_pointer
+        )
+        .unwrap();
+
+        writeln!(context, "Disassembly:").unwrap();
+        let disassembly = self.disassemble_range(self.program_counter, PANIC_CONTEXT_INSTRUCTIONS);
+        for (i, (addr, text)) in disassembly.iter().enumerate() {
+            let marker = if i == 0 { '>' } else { ' ' };
+            writeln!(context, "{} ${:04X}  {}", marker, addr, text).unwrap();
+        }
+
+        writeln!(context, "Recent instructions:").unwrap();
+        for (addr, _) in self.recent_instructions.iter() {
+            let (text, _) = debug::disassemble_instruction(self, *addr);
+            writeln!(context, "  ${:04X}  {}", addr, text).unwrap();
+        }
+        context
+    }
+
+    pub fn bus(&self) -> &Bus<'a> {
+        &self.bus
+    }
+
+    pub fn bus_mut(&mut self) -> &mut Bus<'a> {
+        &mut self.bus
+    }
+
+    /// Reads memory without side effects, see `Bus::mem_peek`
+    pub fn mem_peek(&self, addr: u16) -> u8 {
+        self.bus.mem_peek(addr)
+    }
+
     pub fn program_counter(&self) -> u16 {
         self.program_counter
     }
@@ -1192,8 +1532,660 @@ impl<'a> Cpu<'a> {
 mod tests {
     use super::*;
     use crate::nes::cartridge::tests;
+    use crate::nes::cartridge::{MirroringMode, Rom, TvSystem};
     use crate::nes::ppu::Ppu;
     use crate::nes::joypad::Joypad;
+    use std::cell::Cell;
+
+    // BRK no longer ends the run loop, so the test programs stop on it explicitly
+    fn stop_at_brk(cpu: &mut Cpu) {
+        if cpu.mem_read(cpu.program_counter) == 0x00 {
+            cpu.stop();
+        }
+    }
+
+    fn run_until_brk(cpu: &mut Cpu) {
+        cpu.run_with_callback(stop_at_brk);
+    }
+
+    #[test]
+    fn test_brk() {
+        // BRK; padding; the handler at $8010 is where the run is stopped
+        let mut rom = tests::create_simple_test_rom_with_data(vec![0x00, 0xFF], None);
+        rom.prg_rom[0x7FFE] = 0x10;
+        rom.prg_rom[0x7FFF] = 0x80;
+        let bus = Bus::new(rom, |_ppu: &Ppu, _joypad: &mut Joypad| {});
+        let mut cpu = Cpu::new(bus);
+        cpu.reset();
+        cpu.run_with_callback(|cpu| {
+            if cpu.program_counter == 0x8010 {
+                cpu.stop();
+            }
+        });
+
+        assert_eq!(cpu.program_counter, 0x8010);
+        assert!(cpu.status.contains(CpuFlags::INTERRUPT_DISABLE));
+        let pushed_status = cpu.stack_pop();
+        assert_eq!(pushed_status & 0b00110000, 0b00110000); // B flag set on the pushed copy
+        assert_eq!(cpu.stack_pop_u16(), 0x8002);
+    }
+
+    #[test]
+    fn test_irq_serviced_right_after_rti() {
+        // SEI; push a return address of $9000 and a status with interrupts enabled;
+        // wait for the APU frame IRQ; RTI
+        let mut rom = tests::create_simple_test_rom_with_data(
+            vec![
+                0x78, 0xA9, 0x90, 0x48, 0xA9, 0x00, 0x48, 0xA9, 0x00, 0x48, 0xA0, 0x18, 0xA2, 0x00,
+                0xCA, 0xD0, 0xFD, 0x88, 0xD0, 0xF8, 0x40,
+            ],
+            None,
+        );
+        rom.prg_rom[0x7FFE] = 0x00;
+        rom.prg_rom[0x7FFF] = 0xA0;
+        let bus = Bus::new(rom, |_ppu: &Ppu, _joypad: &mut Joypad| {});
+        let mut cpu = Cpu::new(bus);
+        cpu.reset();
+        cpu.run_with_callback(|cpu| {
+            if cpu.program_counter == 0xA000 {
+                cpu.stop();
+            }
+        });
+
+        assert!(cpu.bus.poll_irq_status());
+        assert_eq!(cpu.program_counter, 0xA000);
+        assert!(cpu.status.contains(CpuFlags::INTERRUPT_DISABLE));
+        let pushed_status = cpu.stack_pop();
+        assert_eq!(pushed_status & 0b00110000, 0b00100000); // B flag clear on the pushed copy
+        assert_eq!(cpu.stack_pop_u16(), 0x9000); // the instruction after RTI never ran
+    }
+
+    #[test]
+    fn test_irq_ignored_while_interrupts_disabled() {
+        let rom = tests::create_simple_test_rom_with_data(vec![0x00], None);
+        let bus = Bus::new(rom, |_ppu: &Ppu, _joypad: &mut Joypad| {});
+        let mut cpu = Cpu::new(bus);
+        cpu.reset();
+        while !cpu.bus.poll_irq_status() {
+            cpu.bus.tick(1);
+        }
+
+        cpu.poll_interrupts();
+        assert_eq!(cpu.program_counter, 0x8000);
+    }
+
+    #[test]
+    fn test_nmi_takes_priority_over_irq() {
+        let mut rom = tests::create_simple_test_rom_with_data(vec![0x00], None);
+        rom.prg_rom[0x7FFA] = 0x00;
+        rom.prg_rom[0x7FFB] = 0x90;
+        rom.prg_rom[0x7FFE] = 0x00;
+        rom.prg_rom[0x7FFF] = 0xA0;
+        let bus = Bus::new(rom, |_ppu: &Ppu, _joypad: &mut Joypad| {});
+        let mut cpu = Cpu::new(bus);
+        cpu.reset();
+        cpu.status.remove(CpuFlags::INTERRUPT_DISABLE);
+
+        // Wait for the second frame's vblank, the frame IRQ is already pending by then
+        while cpu.bus.cycles() < 57200 {
+            cpu.bus.tick(1);
+        }
+        cpu.mem_write(0x2000, 0b10000000); // enabling NMI during vblank triggers it
+        assert!(cpu.bus.poll_irq_status());
+
+        cpu.poll_interrupts();
+        assert_eq!(cpu.program_counter, 0x9000);
+        cpu.poll_interrupts(); // the IRQ waits until the NMI handler re-enables interrupts
+        assert_eq!(cpu.program_counter, 0x9000);
+    }
+
+    #[test]
+    fn test_trigger_nmi() {
+        // NOP; the NMI handler at $9000
+        let mut rom = tests::create_simple_test_rom_with_data(vec![0xEA], None);
+        rom.prg_rom[0x7FFA] = 0x00;
+        rom.prg_rom[0x7FFB] = 0x90;
+        let bus = Bus::new(rom, |_ppu: &Ppu, _joypad: &mut Joypad| {});
+        let mut cpu = Cpu::new(bus);
+        cpu.reset();
+
+        cpu.trigger_nmi();
+        cpu.run_with_callback(|cpu| cpu.stop());
+        assert_eq!(cpu.program_counter, 0x9000);
+        assert_eq!(cpu.stack_pointer, STACK_RESET_ADDR.wrapping_sub(3));
+        assert_eq!(cpu.mem_read_u16(0x01FC), 0x8000);
+        assert_eq!(cpu.mem_read(0x01FB), 0b0010_0100);
+
+        // Serviced once
+        cpu.run_with_callback(|cpu| cpu.stop());
+        assert_eq!(cpu.program_counter, 0x9000);
+    }
+
+    #[test]
+    fn test_trigger_irq_honors_interrupt_disable() {
+        let mut rom = tests::create_simple_test_rom_with_data(vec![0xEA], None);
+        rom.prg_rom[0x7FFE] = 0x00;
+        rom.prg_rom[0x7FFF] = 0xA0;
+        let bus = Bus::new(rom, |_ppu: &Ppu, _joypad: &mut Joypad| {});
+        let mut cpu = Cpu::new(bus);
+        cpu.reset();
+
+        cpu.trigger_irq();
+        cpu.run_with_callback(|cpu| cpu.stop());
+        assert_eq!(cpu.program_counter, 0x8000);
+
+        cpu.status.remove(CpuFlags::INTERRUPT_DISABLE);
+        cpu.run_with_callback(|cpu| cpu.stop());
+        assert_eq!(cpu.program_counter, 0xA000);
+    }
+
+    #[test]
+    fn test_reset_vector_read_through_mirrored_prg_bank() {
+        // With a single 16KB bank the vectors at 0xFFFA-0xFFFF come from 0xBFFA-0xBFFF
+        let mut prg_rom = vec![0; 0x4000];
+        prg_rom[0x3FFC] = 0x34;
+        prg_rom[0x3FFD] = 0x92;
+        let rom = Rom {
+            prg_rom,
+            chr_rom: vec![0; 0x2000],
+            mapper: 0,
+            submapper: 0,
+            screen_mirroring: MirroringMode::Horizontal,
+            has_battery: false,
+            tv_system: TvSystem::Ntsc,
+            prg_ram_size: 0x2000,
+            chr_ram_size: 0,
+        };
+        let bus = Bus::new(rom, |_ppu: &Ppu, _joypad: &mut Joypad| {});
+        let mut cpu = Cpu::new(bus);
+        cpu.reset();
+        assert_eq!(cpu.program_counter(), 0x9234);
+    }
+
+    #[test]
+    fn test_reset_vector_outside_prg_rom_warns() {
+        let mut rom = tests::create_simple_test_rom_with_data(vec![0x00], None);
+        rom.prg_rom[0x7FFC] = 0x00;
+        rom.prg_rom[0x7FFD] = 0x00;
+        let mut warnings = Vec::new();
+
+        let mut bus = Bus::new(rom, |_ppu: &Ppu, _joypad: &mut Joypad| {});
+        bus.set_log_callback(|message| warnings.push(message.to_string()));
+        let mut cpu = Cpu::new(bus);
+        cpu.reset();
+        assert_eq!(cpu.program_counter, 0x0000);
+        drop(cpu);
+
+        assert_eq!(
+            warnings,
+            vec!["Reset vector 0x0000 points outside of PRG-ROM"]
+        );
+    }
+
+    #[test]
+    fn test_reset_vector_inside_prg_rom_does_not_warn() {
+        let rom = tests::create_simple_test_rom_with_data(vec![0x00], None);
+        let mut warnings = Vec::new();
+
+        let mut bus = Bus::new(rom, |_ppu: &Ppu, _joypad: &mut Joypad| {});
+        bus.set_log_callback(|message| warnings.push(message.to_string()));
+        let mut cpu = Cpu::new(bus);
+        cpu.reset();
+        drop(cpu);
+
+        assert!(warnings.is_empty());
+    }
+
+    fn run_counting_cycles(cpu: &mut Cpu) -> HashMap<u16, usize> {
+        let mut cycles_at = HashMap::new();
+        cpu.run_with_callback(|cpu| {
+            cycles_at.insert(cpu.program_counter, cpu.bus.cycles());
+            stop_at_brk(cpu);
+        });
+        cycles_at
+    }
+
+    #[test]
+    fn test_total_cycles() {
+        let rom = tests::create_simple_test_rom_with_data(
+            vec![
+                0xA2, 0x01, // LDX #$01, 2 cycles
+                0xBD, 0xFF, 0x80, // LDA $80FF,X, 4 cycles +1 page cross
+                0xBD, 0x00, 0x80, // LDA $8000,X, 4 cycles
+                0xD0, 0x00, // BNE +0, 2 cycles +1 taken
+                0xEA, // NOP, 2 cycles
+                0x00,
+            ],
+            None,
+        );
+        let bus = Bus::new(rom, |_ppu: &Ppu, _joypad: &mut Joypad| {});
+        let mut cpu = Cpu::new(bus);
+        cpu.reset();
+        let start = cpu.total_cycles();
+        let mut cycles_at_brk = 0;
+        cpu.run_with_callback(|cpu| {
+            if cpu.mem_read(cpu.program_counter) == 0x00 {
+                cycles_at_brk = cpu.total_cycles();
+                cpu.stop();
+            }
+        });
+
+        assert_eq!(cycles_at_brk - start, 2 + 5 + 4 + 3 + 2);
+    }
+
+    // Cycles taken by the given instruction, with X and Y set to the given index and
+    // the zero page pointer at $10 pointing to $02FF
+    fn instruction_cycles(instruction: &[u8], index: u8) -> usize {
+        let mut program = vec![0xA2, index, 0xA0, index]; // LDX, LDY
+        program.extend_from_slice(instruction);
+        program.push(0x00);
+        let rom = tests::create_simple_test_rom_with_data(program, None);
+        let bus = Bus::new(rom, |_ppu: &Ppu, _joypad: &mut Joypad| {});
+        let mut cpu = Cpu::new(bus);
+        cpu.reset();
+        cpu.mem_write(0x10, 0xFF);
+        cpu.mem_write(0x11, 0x02);
+
+        let mut cycles = HashMap::new();
+        cpu.run_with_callback(|cpu| {
+            cycles.insert(cpu.program_counter, cpu.total_cycles());
+            stop_at_brk(cpu);
+        });
+        let brk_addr = 0x8004 + instruction.len() as u16;
+        cycles[&brk_addr] - cycles[&0x8004]
+    }
+
+    #[test]
+    fn test_read_modify_write_cycles_ignore_page_cross() {
+        for index in [0, 1].iter() {
+            assert_eq!(instruction_cycles(&[0x1E, 0xFF, 0x02], *index), 7); // ASL $02FF,X
+            assert_eq!(instruction_cycles(&[0xFE, 0xFF, 0x02], *index), 7); // INC $02FF,X
+            assert_eq!(instruction_cycles(&[0xDF, 0xFF, 0x02], *index), 7); // DCP $02FF,X
+            assert_eq!(instruction_cycles(&[0xFB, 0xFF, 0x02], *index), 7); // ISB $02FF,Y
+            assert_eq!(instruction_cycles(&[0x1B, 0xFF, 0x02], *index), 7); // SLO $02FF,Y
+            assert_eq!(instruction_cycles(&[0x33, 0x10], *index), 8); // RLA ($10),Y
+            assert_eq!(instruction_cycles(&[0x53, 0x10], *index), 8); // SRE ($10),Y
+            assert_eq!(instruction_cycles(&[0x73, 0x10], *index), 8); // RRA ($10),Y
+        }
+    }
+
+    #[test]
+    fn test_store_cycles_ignore_page_cross() {
+        for index in [0, 1].iter() {
+            assert_eq!(instruction_cycles(&[0x9D, 0xFF, 0x02], *index), 5); // STA $02FF,X
+            assert_eq!(instruction_cycles(&[0x99, 0xFF, 0x02], *index), 5); // STA $02FF,Y
+            assert_eq!(instruction_cycles(&[0x91, 0x10], *index), 6); // STA ($10),Y
+        }
+    }
+
+    #[test]
+    fn test_unofficial_read_cycles_with_page_cross() {
+        assert_eq!(instruction_cycles(&[0xBF, 0xFF, 0x02], 0), 4); // LAX $02FF,Y
+        assert_eq!(instruction_cycles(&[0xBF, 0xFF, 0x02], 1), 5);
+        assert_eq!(instruction_cycles(&[0xB3, 0x10], 0), 5); // LAX ($10),Y
+        assert_eq!(instruction_cycles(&[0xB3, 0x10], 1), 6);
+        assert_eq!(instruction_cycles(&[0xBB, 0xFF, 0x02], 0), 4); // LAS $02FF,Y
+        assert_eq!(instruction_cycles(&[0xBB, 0xFF, 0x02], 1), 5);
+    }
+
+    #[test]
+    fn test_indirect_pointers_wrap_within_zero_page() {
+        let rom = tests::create_simple_test_rom_with_data(
+            vec![
+                0xB1, 0xFF, // LDA ($FF),Y
+                0xAA, // TAX
+                0xA1, 0xFE, // LDA ($FE,X)
+                0x00,
+            ],
+            None,
+        );
+        let bus = Bus::new(rom, |_ppu: &Ppu, _joypad: &mut Joypad| {});
+        let mut cpu = Cpu::new(bus);
+        cpu.reset();
+        cpu.mem_write(0xFF, 0x34);
+        cpu.mem_write(0x00, 0x02); // not 0x0100
+        cpu.mem_write(0x0234, 0x01);
+        cpu.mem_write(0x0100, 0x05);
+        cpu.mem_write(0x0534, 0xAB);
+        run_until_brk(&mut cpu);
+
+        // The second pointer is at $FE + 1 = $FF too
+        assert_eq!(cpu.register_x(), 0x01);
+        assert_eq!(cpu.register_a(), 0x01);
+    }
+
+    #[test]
+    fn test_indirect_y_page_cross_cycles() {
+        assert_eq!(instruction_cycles(&[0xB1, 0x10], 0), 5); // LDA ($10),Y reads $02FF
+        assert_eq!(instruction_cycles(&[0xB1, 0x10], 1), 6); // LDA ($10),Y reads $0300
+        assert_eq!(instruction_cycles(&[0x71, 0x10], 1), 6); // ADC ($10),Y
+        assert_eq!(instruction_cycles(&[0xD1, 0x10], 1), 6); // CMP ($10),Y
+    }
+
+    #[test]
+    fn test_reset_clears_cycles_and_ppu_timing() {
+        let rom = tests::create_simple_test_rom_with_data(vec![0x4C, 0x00, 0x80], None); // JMP $8000
+        let bus = Bus::new(rom, |_ppu: &Ppu, _joypad: &mut Joypad| {});
+        let mut cpu = Cpu::new(bus);
+        cpu.reset();
+        cpu.run_with_callback(|cpu| {
+            if cpu.bus().ppu().scanline() == 100 {
+                cpu.stop();
+            }
+        });
+        assert!(cpu.total_cycles() > 0);
+
+        cpu.reset();
+        assert_eq!(cpu.total_cycles(), 0);
+        assert_eq!(cpu.bus().ppu().scanline(), 0);
+        assert_eq!(cpu.program_counter(), 0x8000);
+    }
+
+    #[test]
+    fn test_rts_cycles() {
+        // JSR $8004; BRK; RTS
+        let rom = tests::create_simple_test_rom_with_data(vec![0x20, 0x04, 0x80, 0x00, 0x60], None);
+        let bus = Bus::new(rom, |_ppu: &Ppu, _joypad: &mut Joypad| {});
+        let mut cpu = Cpu::new(bus);
+        cpu.reset();
+        let cycles_at = run_counting_cycles(&mut cpu);
+
+        assert_eq!(cycles_at[&0x8004] - cycles_at[&0x8000], 6);
+        assert_eq!(cycles_at[&0x8003] - cycles_at[&0x8004], 6);
+    }
+
+    #[test]
+    fn test_rti_cycles_and_interrupt_flag_restoration() {
+        // Push a return address of $800C and a status with interrupts enabled,
+        // then SEI; RTI; BRK; PHP; BRK
+        let rom = tests::create_simple_test_rom_with_data(
+            vec![
+                0xA9, 0x80, 0x48, 0xA9, 0x0C, 0x48, 0xA9, 0x00, 0x48, 0x78, 0x40, 0x00, 0x08, 0x00,
+            ],
+            None,
+        );
+        let bus = Bus::new(rom, |_ppu: &Ppu, _joypad: &mut Joypad| {});
+        let mut cpu = Cpu::new(bus);
+        cpu.reset();
+        let cycles_at = run_counting_cycles(&mut cpu);
+
+        assert_eq!(cycles_at[&0x800C] - cycles_at[&0x800A], 6);
+        // The instruction right after RTI already runs with interrupts enabled
+        let pushed_status = cpu.mem_read(0x0100 + cpu.stack_pointer as u16 + 1);
+        assert_eq!(pushed_status & CpuFlags::INTERRUPT_DISABLE.bits(), 0);
+        assert!(!cpu.status.contains(CpuFlags::INTERRUPT_DISABLE));
+    }
+
+    #[test]
+    fn test_panic_context() {
+        // LDX #$02; DEX; BNE -3; LDA #$05; BRK
+        let rom = tests::create_simple_test_rom_with_data(
+            vec![0xA2, 0x02, 0xCA, 0xD0, 0xFD, 0xA9, 0x05, 0x00],
+            None,
+        );
+        let bus = Bus::new(rom, |_ppu: &Ppu, _joypad: &mut Joypad| {});
+        let mut cpu = Cpu::new(bus);
+        cpu.reset();
+        run_until_brk(&mut cpu);
+
+        let context = cpu.panic_context();
+        assert!(context.starts_with("PC: $8007\nA:05 X:00 Y:00 P:24 SP:FD\n"));
+        assert!(context.contains("> $8007  BRK\n"));
+        assert!(context.contains("Recent instructions:\n  $8000  LDX #$02\n  $8002  DEX\n"));
+        assert!(context.ends_with("  $8003  BNE $8002\n  $8005  LDA #$05\n"));
+    }
+
+    #[test]
+    fn test_disassemble_range() {
+        // LDX #$02; DEX; BNE -3; BRK
+        let rom =
+            tests::create_simple_test_rom_with_data(vec![0xA2, 0x02, 0xCA, 0xD0, 0xFD, 0x00], None);
+        let bus = Bus::new(rom, |_ppu: &Ppu, _joypad: &mut Joypad| {});
+        let mut cpu = Cpu::new(bus);
+        cpu.reset();
+        assert_eq!(
+            cpu.disassemble_range(0x8000, 4),
+            vec![
+                (0x8000, "LDX #$02".to_string()),
+                (0x8002, "DEX".to_string()),
+                (0x8003, "BNE $8002".to_string()),
+                (0x8005, "BRK".to_string()),
+            ]
+        );
+
+        // Walking over the PPU registers doesn't acknowledge vblank
+        while cpu.bus().ppu().status_register_bits() & 0b1000_0000 == 0 {
+            cpu.bus_mut().tick(1);
+        }
+        cpu.disassemble_range(0x2000, 8);
+        assert_ne!(cpu.bus().ppu().status_register_bits() & 0b1000_0000, 0);
+    }
+
+    #[test]
+    fn test_indexed_dummy_reads() {
+        // The PPU data register (0x2007) increments the VRAM address on every read,
+        // which makes the dummy reads visible
+        let rom = tests::create_simple_test_rom_with_data(
+            vec![
+                0xA9, 0x21, 0x8D, 0x06, 0x20, // LDA #$21; STA $2006
+                0xA9, 0x00, 0x8D, 0x06, 0x20, // LDA #$00; STA $2006
+                0xA2, 0x07, 0xA9, 0x55, 0x9D, 0x00, 0x20, // LDX #$07; LDA #$55; STA $2000,X
+                0xA2, 0x0F, 0xBD, 0xF8, 0x20, // LDX #$0F; LDA $20F8,X (page cross)
+                0xA2, 0x00, 0xBD, 0x07, 0x20, // LDX #$00; LDA $2007,X
+                0xA9, 0x66, 0x8D, 0x07, 0x20, // LDA #$66; STA $2007
+                0x00,
+            ],
+            None,
+        );
+        let bus = Bus::new(rom, |_ppu: &Ppu, _joypad: &mut Joypad| {});
+        let mut cpu = Cpu::new(bus);
+        cpu.reset();
+        run_until_brk(&mut cpu);
+
+        let ppu = cpu.bus().ppu();
+        // The indexed store reads $2007 before writing it, the page crossing load reads it twice
+        assert_eq!(ppu.read_nametable_at(0x2100), 0x00);
+        assert_eq!(ppu.read_nametable_at(0x2101), 0x55);
+        assert_eq!(ppu.read_nametable_at(0x2104), 0x00);
+        assert_eq!(ppu.read_nametable_at(0x2105), 0x66);
+    }
+
+    #[test]
+    fn test_recent_instructions() {
+        // LDX #$02; DEX; BNE -3; BRK
+        let rom =
+            tests::create_simple_test_rom_with_data(vec![0xA2, 0x02, 0xCA, 0xD0, 0xFD, 0x00], None);
+        let bus = Bus::new(rom, |_ppu: &Ppu, _joypad: &mut Joypad| {});
+        let mut cpu = Cpu::new(bus);
+        cpu.reset();
+        run_until_brk(&mut cpu);
+        assert_eq!(
+            cpu.recent_instructions(),
+            vec![
+                (0x8000, 0xA2),
+                (0x8002, 0xCA),
+                (0x8003, 0xD0),
+                (0x8002, 0xCA),
+                (0x8003, 0xD0),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_recent_instructions_keeps_the_latest() {
+        // LDX #$40; DEX; BNE -3; BRK
+        let rom =
+            tests::create_simple_test_rom_with_data(vec![0xA2, 0x40, 0xCA, 0xD0, 0xFD, 0x00], None);
+        let bus = Bus::new(rom, |_ppu: &Ppu, _joypad: &mut Joypad| {});
+        let mut cpu = Cpu::new(bus);
+        cpu.reset();
+        run_until_brk(&mut cpu);
+
+        let recent = cpu.recent_instructions();
+        assert_eq!(recent.len(), RECENT_INSTRUCTIONS_SIZE);
+        assert_eq!(recent[RECENT_INSTRUCTIONS_SIZE - 1], (0x8003, 0xD0));
+        assert_eq!(recent[RECENT_INSTRUCTIONS_SIZE - 2], (0x8002, 0xCA));
+        assert_eq!(recent[0], (0x8002, 0xCA));
+    }
+
+    #[test]
+    fn test_jsr_return_address_wraps() {
+        // JSR at $FFFE, its operand high byte wraps around to $0000
+        let mut rom = tests::create_simple_test_rom_with_data(vec![0x00], None);
+        rom.prg_rom[0x7FFC] = 0xFE;
+        rom.prg_rom[0x7FFD] = 0xFF;
+        rom.prg_rom[0x7FFE] = 0x20;
+        rom.prg_rom[0x7FFF] = 0x00;
+        let bus = Bus::new(rom, |_ppu: &Ppu, _joypad: &mut Joypad| {});
+        let mut cpu = Cpu::new(bus);
+        cpu.reset();
+        cpu.mem_write(0x0000, 0x80); // JSR $8000
+        cpu.mem_write(0x01FC, 0xFF);
+        cpu.mem_write(0x01FD, 0xFF);
+        run_until_brk(&mut cpu);
+
+        assert_eq!(cpu.program_counter(), 0x8000);
+        assert_eq!(cpu.mem_read(0x01FC), 0x00);
+        assert_eq!(cpu.mem_read(0x01FD), 0x00);
+    }
+
+    #[test]
+    fn test_rts_return_address_wraps() {
+        // RTS to $FFFF + 1, where RAM has a BRK
+        let rom = tests::create_simple_test_rom_with_data(vec![0x60], None);
+        let bus = Bus::new(rom, |_ppu: &Ppu, _joypad: &mut Joypad| {});
+        let mut cpu = Cpu::new(bus);
+        cpu.reset();
+        cpu.stack_push_u16(0xFFFF);
+        run_until_brk(&mut cpu);
+
+        assert_eq!(cpu.program_counter(), 0x0000);
+    }
+
+    #[test]
+    fn test_jam_halts_the_cpu() {
+        // LDA #$01; JAM; LDA #$02
+        let rom = tests::create_simple_test_rom_with_data(vec![0xA9, 0x01, 0x02, 0xA9, 0x02], None);
+        let bus = Bus::new(rom, |_ppu: &Ppu, _joypad: &mut Joypad| {});
+        let mut cpu = Cpu::new(bus);
+        cpu.reset();
+        cpu.run();
+
+        assert!(cpu.is_halted());
+        assert_eq!(cpu.program_counter(), 0x8002);
+        assert_eq!(cpu.register_a(), 0x01);
+
+        cpu.run(); // stays halted
+        cpu.run_until_frame();
+        assert_eq!(cpu.program_counter(), 0x8002);
+
+        cpu.reset();
+        assert!(!cpu.is_halted());
+    }
+
+    #[test]
+    fn test_jam_at_the_end_of_memory() {
+        // JMP $FFFF, with a JAM there that wraps the PC to 0
+        let mut program = vec![0; 0x8000];
+        program[..3].copy_from_slice(&[0x4C, 0xFF, 0xFF]);
+        program[0x7FFF] = 0x02;
+        let rom = tests::create_simple_test_rom_with_data(program, None);
+        let bus = Bus::new(rom, |_ppu: &Ppu, _joypad: &mut Joypad| {});
+        let mut cpu = Cpu::new(bus);
+        cpu.reset();
+        cpu.run();
+
+        assert!(cpu.is_halted());
+        assert_eq!(cpu.program_counter(), 0xFFFF);
+    }
+
+    #[test]
+    fn test_run_until_frame() {
+        // JMP $8000
+        let rom = tests::create_simple_test_rom_with_data(vec![0x4C, 0x00, 0x80], None);
+        let mut frames = 0;
+        let bus = Bus::new(rom, |_ppu: &Ppu, _joypad: &mut Joypad| frames += 1);
+        let mut cpu = Cpu::new(bus);
+        cpu.reset();
+
+        assert_eq!(cpu.run_until_frame().data().len(), 256 * 240 * 3);
+        let first_frame_cycles = cpu.bus().cycles();
+        cpu.run_until_frame();
+        // 262 scanlines of 341 PPU cycles, at 3 PPU cycles per CPU cycle
+        let cycles = cpu.bus().cycles() - first_frame_cycles;
+        assert!((cycles as i64 - 262 * 341 / 3).abs() <= 3);
+        drop(cpu);
+        assert_eq!(frames, 2);
+    }
+
+    #[test]
+    fn test_run_until_frame_after_another_run() {
+        // JMP $8000
+        let rom = tests::create_simple_test_rom_with_data(vec![0x4C, 0x00, 0x80], None);
+        let frames = Cell::new(0);
+        let bus = Bus::new(rom, |_ppu: &Ppu, _joypad: &mut Joypad| {
+            frames.set(frames.get() + 1)
+        });
+        let mut cpu = Cpu::new(bus);
+        cpu.reset();
+
+        // Completes a frame that nobody takes
+        cpu.run_for_cycles(40_000);
+        assert_eq!(frames.get(), 1);
+
+        cpu.run_until_frame();
+        assert_eq!(frames.get(), 2);
+        cpu.run_until_frame();
+        assert_eq!(frames.get(), 3);
+    }
+
+    #[test]
+    fn test_run_for_cycles() {
+        // JMP $8000, 3 cycles
+        let rom = tests::create_simple_test_rom_with_data(vec![0x4C, 0x00, 0x80], None);
+        let bus = Bus::new(rom, |_ppu: &Ppu, _joypad: &mut Joypad| {});
+        let mut cpu = Cpu::new(bus);
+        cpu.reset();
+
+        let start = cpu.total_cycles();
+        cpu.run_for_cycles(1000);
+        assert_eq!(cpu.total_cycles() - start, 1002);
+        cpu.run_for_cycles(0);
+        assert_eq!(cpu.total_cycles() - start, 1002);
+    }
+
+    #[test]
+    fn test_trace_while_rendering_frames() {
+        // LDY #$20; LDX #$00; DEX; BNE -3; DEY; BNE -8; BRK
+        let rom = tests::create_simple_test_rom_with_data(
+            vec![
+                0xA0, 0x20, 0xA2, 0x00, 0xCA, 0xD0, 0xFD, 0x88, 0xD0, 0xF8, 0x00,
+            ],
+            None,
+        );
+        let mut frames = 0;
+        let mut trace = Vec::new();
+
+        let bus = Bus::new(rom, |_ppu: &Ppu, _joypad: &mut Joypad| frames += 1);
+        let mut cpu = Cpu::new(bus);
+        cpu.reset();
+        cpu.run_with_callback(|cpu| {
+            let code = cpu.mem_read(cpu.program_counter);
+            trace.push(format!(
+                "{:04X}  {:02X}  A:{:02X} X:{:02X} Y:{:02X}",
+                cpu.program_counter, code, cpu.register_a, cpu.register_x, cpu.register_y
+            ));
+            stop_at_brk(cpu);
+        });
+        drop(cpu);
+
+        assert_eq!(frames, 1);
+        // LDY, then 32 times LDX + 256 * (DEX + BNE) + DEY + BNE, then BRK
+        assert_eq!(trace.len(), 1 + 32 * (1 + 256 * 2 + 2) + 1);
+        assert_eq!(trace[0], "8000  A0  A:00 X:00 Y:00");
+        assert_eq!(trace[1], "8002  A2  A:00 X:00 Y:20");
+        assert_eq!(trace[trace.len() - 1], "800A  00  A:00 X:00 Y:00");
+    }
 
     #[test]
     fn test_0xa9_lda_immediate_load_data() {
@@ -1201,7 +2193,7 @@ mod tests {
         let bus = Bus::new(rom, |_ppu: &Ppu, _joypad: &mut Joypad| {});
         let mut cpu = Cpu::new(bus);
         cpu.reset();
-        cpu.run();
+        run_until_brk(&mut cpu);
         assert_eq!(cpu.register_a, 0x05);
         assert_eq!(cpu.status.bits() & 0b0000_0010, 0b00);
         assert_eq!(cpu.status.bits() & 0b1000_0000, 0);
@@ -1213,7 +2205,7 @@ mod tests {
         let bus = Bus::new(rom, |_ppu: &Ppu, _joypad: &mut Joypad| {});
         let mut cpu = Cpu::new(bus);
         cpu.reset();
-        cpu.run();
+        run_until_brk(&mut cpu);
         assert_eq!(cpu.status.bits() & 0b0000_0010, 0b10);
     }
 
@@ -1223,7 +2215,7 @@ mod tests {
         let bus = Bus::new(rom, |_ppu: &Ppu, _joypad: &mut Joypad| {});
         let mut cpu = Cpu::new(bus);
         cpu.reset();
-        cpu.run();
+        run_until_brk(&mut cpu);
         assert_eq!(cpu.status.bits() & 0b1000_0000, 0b1000_0000);
     }
 
@@ -1234,7 +2226,7 @@ mod tests {
         let mut cpu = Cpu::new(bus);
         cpu.mem_write(0x10, 0x55);
         cpu.reset();
-        cpu.run();
+        run_until_brk(&mut cpu);
         assert_eq!(cpu.register_a, 0x55);
     }
 
@@ -1246,7 +2238,7 @@ mod tests {
         let mut cpu = Cpu::new(bus);
         cpu.mem_write(0x8F, 0x55);
         cpu.reset();
-        cpu.run();
+        run_until_brk(&mut cpu);
         assert_eq!(cpu.register_a, 0x55);
     }
 
@@ -1257,7 +2249,7 @@ mod tests {
         let mut cpu = Cpu::new(bus);
         cpu.mem_write(0x008F, 0x55);
         cpu.reset();
-        cpu.run();
+        run_until_brk(&mut cpu);
         assert_eq!(cpu.register_a, 0x55);
     }
 
@@ -1271,7 +2263,7 @@ mod tests {
         let mut cpu = Cpu::new(bus);
         cpu.mem_write(0x008F, 0x55);
         cpu.reset();
-        cpu.run();
+        run_until_brk(&mut cpu);
         assert_eq!(cpu.register_a, 0x55);
     }
 
@@ -1286,7 +2278,7 @@ mod tests {
         cpu.mem_write(0x008F, 0x55);
         cpu.mem_write(0x0055, 0x0A);
         cpu.reset();
-        cpu.run();
+        run_until_brk(&mut cpu);
         assert_eq!(cpu.register_a, 0x0A);
     }
 
@@ -1296,7 +2288,7 @@ mod tests {
         let bus = Bus::new(rom, |_ppu: &Ppu, _joypad: &mut Joypad| {});
         let mut cpu = Cpu::new(bus);
         cpu.reset();
-        cpu.run();
+        run_until_brk(&mut cpu);
         assert_eq!(cpu.register_a, 2);
     }
 
@@ -1306,7 +2298,7 @@ mod tests {
         let bus = Bus::new(rom, |_ppu: &Ppu, _joypad: &mut Joypad| {});
         let mut cpu = Cpu::new(bus);
         cpu.reset();
-        cpu.run();
+        run_until_brk(&mut cpu);
         assert_eq!(cpu.register_a, 0xFE);
         assert!(cpu.status.contains(CpuFlags::OVERFLOW));
     }
@@ -1317,7 +2309,7 @@ mod tests {
         let bus = Bus::new(rom, |_ppu: &Ppu, _joypad: &mut Joypad| {});
         let mut cpu = Cpu::new(bus);
         cpu.reset();
-        cpu.run();
+        run_until_brk(&mut cpu);
         assert_eq!(cpu.register_a, 0x91);
     }
 
@@ -1328,7 +2320,7 @@ mod tests {
         let mut cpu = Cpu::new(bus);
         cpu.mem_write(0x10, 0x02);
         cpu.reset();
-        cpu.run();
+        run_until_brk(&mut cpu);
         assert_eq!(cpu.mem_read(0x10), 0x04);
     }
 
@@ -1338,7 +2330,7 @@ mod tests {
         let bus = Bus::new(rom, |_ppu: &Ppu, _joypad: &mut Joypad| {});
         let mut cpu = Cpu::new(bus);
         cpu.reset();
-        cpu.run();
+        run_until_brk(&mut cpu);
         assert_eq!(cpu.register_a, 0x02);
     }
 
@@ -1349,7 +2341,7 @@ mod tests {
         let mut cpu = Cpu::new(bus);
         cpu.mem_write(0x10, 0x01);
         cpu.reset();
-        cpu.run();
+        run_until_brk(&mut cpu);
         assert!(!cpu.status.contains(CpuFlags::ZERO));
     }
 
@@ -1359,7 +2351,7 @@ mod tests {
         let bus = Bus::new(rom, |_ppu: &Ppu, _joypad: &mut Joypad| {});
         let mut cpu = Cpu::new(bus);
         cpu.reset();
-        cpu.run();
+        run_until_brk(&mut cpu);
         assert!(cpu.status.contains(CpuFlags::ZERO));
     }
 
@@ -1370,7 +2362,7 @@ mod tests {
         let mut cpu = Cpu::new(bus);
         cpu.mem_write(0x10, 0x01);
         cpu.reset();
-        cpu.run();
+        run_until_brk(&mut cpu);
         assert_eq!(cpu.mem_read(0x10), 0x00);
     }
 
@@ -1380,7 +2372,7 @@ mod tests {
         let bus = Bus::new(rom, |_ppu: &Ppu, _joypad: &mut Joypad| {});
         let mut cpu = Cpu::new(bus);
         cpu.reset();
-        cpu.run();
+        run_until_brk(&mut cpu);
         assert_eq!(cpu.register_a, 0x11);
     }
 
@@ -1391,7 +2383,7 @@ mod tests {
         let mut cpu = Cpu::new(bus);
         cpu.mem_write(0x10, 0x10);
         cpu.reset();
-        cpu.run();
+        run_until_brk(&mut cpu);
         assert_eq!(cpu.mem_read(0x10), 0b00001000);
     }
 
@@ -1401,7 +2393,7 @@ mod tests {
         let bus = Bus::new(rom, |_ppu: &Ppu, _joypad: &mut Joypad| {});
         let mut cpu = Cpu::new(bus);
         cpu.reset();
-        cpu.run();
+        run_until_brk(&mut cpu);
         assert_eq!(cpu.register_a, 0b00001000);
     }
 
@@ -1411,7 +2403,7 @@ mod tests {
         let bus = Bus::new(rom, |_ppu: &Ppu, _joypad: &mut Joypad| {});
         let mut cpu = Cpu::new(bus);
         cpu.reset();
-        cpu.run();
+        run_until_brk(&mut cpu);
         assert_eq!(cpu.register_a, 0x1F);
     }
 
@@ -1422,7 +2414,7 @@ mod tests {
         let mut cpu = Cpu::new(bus);
         cpu.mem_write(0x10, 0x80);
         cpu.reset();
-        cpu.run();
+        run_until_brk(&mut cpu);
         assert_eq!(cpu.mem_read(0x10), 0x00);
         assert!(cpu.status.contains(CpuFlags::CARRY));
     }
@@ -1433,7 +2425,7 @@ mod tests {
         let bus = Bus::new(rom, |_ppu: &Ppu, _joypad: &mut Joypad| {});
         let mut cpu = Cpu::new(bus);
         cpu.reset();
-        cpu.run();
+        run_until_brk(&mut cpu);
         assert_eq!(cpu.register_a, 0x00);
         assert!(cpu.status.contains(CpuFlags::CARRY));
     }
@@ -1445,7 +2437,7 @@ mod tests {
         let mut cpu = Cpu::new(bus);
         cpu.mem_write(0x10, 0x01);
         cpu.reset();
-        cpu.run();
+        run_until_brk(&mut cpu);
         assert_eq!(cpu.mem_read(0x10), 0x00);
         assert!(cpu.status.contains(CpuFlags::CARRY));
     }
@@ -1456,7 +2448,7 @@ mod tests {
         let bus = Bus::new(rom, |_ppu: &Ppu, _joypad: &mut Joypad| {});
         let mut cpu = Cpu::new(bus);
         cpu.reset();
-        cpu.run();
+        run_until_brk(&mut cpu);
         assert_eq!(cpu.register_a, 0x40);
     }
 
@@ -1468,7 +2460,7 @@ mod tests {
         let bus = Bus::new(rom, |_ppu: &Ppu, _joypad: &mut Joypad| {});
         let mut cpu = Cpu::new(bus);
         cpu.reset();
-        cpu.run();
+        run_until_brk(&mut cpu);
         assert_eq!(cpu.register_a as i8, -1);
     }
 
@@ -1481,7 +2473,7 @@ mod tests {
         let bus = Bus::new(rom, |_ppu: &Ppu, _joypad: &mut Joypad| {});
         let mut cpu = Cpu::new(bus);
         cpu.reset();
-        cpu.run();
+        run_until_brk(&mut cpu);
         assert_eq!(cpu.register_a, 0x00);
     }
 
@@ -1491,7 +2483,7 @@ mod tests {
         let bus = Bus::new(rom, |_ppu: &Ppu, _joypad: &mut Joypad| {});
         let mut cpu = Cpu::new(bus);
         cpu.reset();
-        cpu.run();
+        run_until_brk(&mut cpu);
         assert_eq!(cpu.register_x, 0x00);
         assert_eq!(cpu.register_a, 0x01);
     }
@@ -1502,7 +2494,7 @@ mod tests {
         let bus = Bus::new(rom, |_ppu: &Ppu, _joypad: &mut Joypad| {});
         let mut cpu = Cpu::new(bus);
         cpu.reset();
-        cpu.run();
+        run_until_brk(&mut cpu);
         assert_eq!(cpu.register_y, 0x01);
         assert_eq!(cpu.register_a, 0x02);
     }
@@ -1514,7 +2506,7 @@ mod tests {
         let mut cpu = Cpu::new(bus);
         cpu.mem_write(0x10, 0x01);
         cpu.reset();
-        cpu.run();
+        run_until_brk(&mut cpu);
         assert_eq!(cpu.mem_read(0x10), 0x02);
     }
 
@@ -1524,7 +2516,7 @@ mod tests {
         let bus = Bus::new(rom, |_ppu: &Ppu, _joypad: &mut Joypad| {});
         let mut cpu = Cpu::new(bus);
         cpu.reset();
-        cpu.run();
+        run_until_brk(&mut cpu);
         assert_eq!(cpu.register_x, 11);
     }
 
@@ -1535,7 +2527,7 @@ mod tests {
         let bus = Bus::new(rom, |_ppu: &Ppu, _joypad: &mut Joypad| {});
         let mut cpu = Cpu::new(bus);
         cpu.reset();
-        cpu.run();
+        run_until_brk(&mut cpu);
         assert_eq!(cpu.register_x, 1);
     }
 
@@ -1545,7 +2537,7 @@ mod tests {
         let bus = Bus::new(rom, |_ppu: &Ppu, _joypad: &mut Joypad| {});
         let mut cpu = Cpu::new(bus);
         cpu.reset();
-        cpu.run();
+        run_until_brk(&mut cpu);
         assert_eq!(cpu.status.bits() & 0b0000_0010, 0b10);
     }
 
@@ -1555,7 +2547,7 @@ mod tests {
         let bus = Bus::new(rom, |_ppu: &Ppu, _joypad: &mut Joypad| {});
         let mut cpu = Cpu::new(bus);
         cpu.reset();
-        cpu.run();
+        run_until_brk(&mut cpu);
         assert_eq!(cpu.status.bits() & 0b1000_0000, 0b1000_0000);
     }
 
@@ -1565,7 +2557,7 @@ mod tests {
         let bus = Bus::new(rom, |_ppu: &Ppu, _joypad: &mut Joypad| {});
         let mut cpu = Cpu::new(bus);
         cpu.reset();
-        cpu.run();
+        run_until_brk(&mut cpu);
         assert_eq!(cpu.register_y, 11);
     }
 
@@ -1575,7 +2567,7 @@ mod tests {
         let bus = Bus::new(rom, |_ppu: &Ppu, _joypad: &mut Joypad| {});
         let mut cpu = Cpu::new(bus);
         cpu.reset();
-        cpu.run();
+        run_until_brk(&mut cpu);
         assert_eq!(cpu.register_x, 10);
     }
 
@@ -1585,7 +2577,7 @@ mod tests {
         let bus = Bus::new(rom, |_ppu: &Ppu, _joypad: &mut Joypad| {});
         let mut cpu = Cpu::new(bus);
         cpu.reset();
-        cpu.run();
+        run_until_brk(&mut cpu);
         assert_eq!(cpu.register_y, 10);
     }
 
@@ -1595,7 +2587,7 @@ mod tests {
         let bus = Bus::new(rom, |_ppu: &Ppu, _joypad: &mut Joypad| {});
         let mut cpu = Cpu::new(bus);
         cpu.reset();
-        cpu.run();
+        run_until_brk(&mut cpu);
         assert_eq!(cpu.mem_read(0x10), 0x0A);
     }
 
@@ -1605,7 +2597,7 @@ mod tests {
         let bus = Bus::new(rom, |_ppu: &Ppu, _joypad: &mut Joypad| {});
         let mut cpu = Cpu::new(bus);
         cpu.reset();
-        cpu.run();
+        run_until_brk(&mut cpu);
         assert_eq!(cpu.mem_read(0x10), 0x0A);
     }
 
@@ -1615,7 +2607,7 @@ mod tests {
         let bus = Bus::new(rom, |_ppu: &Ppu, _joypad: &mut Joypad| {});
         let mut cpu = Cpu::new(bus);
         cpu.reset();
-        cpu.run();
+        run_until_brk(&mut cpu);
         assert_eq!(cpu.mem_read(0x10), 0x0A);
     }
 
@@ -1625,7 +2617,7 @@ mod tests {
         let bus = Bus::new(rom, |_ppu: &Ppu, _joypad: &mut Joypad| {});
         let mut cpu = Cpu::new(bus);
         cpu.reset();
-        cpu.run();
+        run_until_brk(&mut cpu);
         assert_eq!(cpu.register_x, 10);
     }
 
@@ -1635,7 +2627,7 @@ mod tests {
         let bus = Bus::new(rom, |_ppu: &Ppu, _joypad: &mut Joypad| {});
         let mut cpu = Cpu::new(bus);
         cpu.reset();
-        cpu.run();
+        run_until_brk(&mut cpu);
         assert_eq!(cpu.status.bits() & 0b0000_0010, 0b10);
     }
 
@@ -1645,7 +2637,7 @@ mod tests {
         let bus = Bus::new(rom, |_ppu: &Ppu, _joypad: &mut Joypad| {});
         let mut cpu = Cpu::new(bus);
         cpu.reset();
-        cpu.run();
+        run_until_brk(&mut cpu);
         assert_eq!(cpu.status.bits() & 0b1000_0000, 0b1000_0000);
     }
 
@@ -1655,7 +2647,7 @@ mod tests {
         let bus = Bus::new(rom, |_ppu: &Ppu, _joypad: &mut Joypad| {});
         let mut cpu = Cpu::new(bus);
         cpu.reset();
-        cpu.run();
+        run_until_brk(&mut cpu);
         assert_eq!(cpu.register_y, 10);
     }
 
@@ -1665,7 +2657,7 @@ mod tests {
         let bus = Bus::new(rom, |_ppu: &Ppu, _joypad: &mut Joypad| {});
         let mut cpu = Cpu::new(bus);
         cpu.reset();
-        cpu.run();
+        run_until_brk(&mut cpu);
         assert_eq!(cpu.register_a, 10);
     }
 
@@ -1675,10 +2667,51 @@ mod tests {
         let bus = Bus::new(rom, |_ppu: &Ppu, _joypad: &mut Joypad| {});
         let mut cpu = Cpu::new(bus);
         cpu.reset();
-        cpu.run();
+        run_until_brk(&mut cpu);
         assert_eq!(cpu.register_a, 10);
     }
 
+    fn run_decimal_program(program: Vec<u8>, decimal_supported: bool) -> Cpu<'static> {
+        let rom = tests::create_simple_test_rom_with_data(program, None);
+        let bus = Bus::new(rom, |_ppu: &Ppu, _joypad: &mut Joypad| {});
+        let mut cpu = Cpu::new(bus);
+        cpu.set_decimal_supported(decimal_supported);
+        cpu.reset();
+        run_until_brk(&mut cpu);
+        cpu
+    }
+
+    #[test]
+    fn test_adc_decimal_mode() {
+        // SED; CLC; LDA #$09; ADC #$01; BRK
+        let program = vec![0xF8, 0x18, 0xA9, 0x09, 0x69, 0x01, 0x00];
+        let cpu = run_decimal_program(program.clone(), true);
+        assert_eq!(cpu.register_a, 0x10);
+        assert!(!cpu.status.contains(CpuFlags::CARRY));
+
+        // The 2A03 ignores the decimal flag
+        let cpu = run_decimal_program(program, false);
+        assert_eq!(cpu.register_a, 0x0A);
+
+        // SED; CLC; LDA #$99; ADC #$01; BRK
+        let cpu = run_decimal_program(vec![0xF8, 0x18, 0xA9, 0x99, 0x69, 0x01, 0x00], true);
+        assert_eq!(cpu.register_a, 0x00);
+        assert!(cpu.status.contains(CpuFlags::CARRY));
+    }
+
+    #[test]
+    fn test_sbc_decimal_mode() {
+        // SED; SEC; LDA #$10; SBC #$01; BRK
+        let cpu = run_decimal_program(vec![0xF8, 0x38, 0xA9, 0x10, 0xE9, 0x01, 0x00], true);
+        assert_eq!(cpu.register_a, 0x09);
+        assert!(cpu.status.contains(CpuFlags::CARRY));
+
+        // SED; SEC; LDA #$00; SBC #$01; BRK
+        let cpu = run_decimal_program(vec![0xF8, 0x38, 0xA9, 0x00, 0xE9, 0x01, 0x00], true);
+        assert_eq!(cpu.register_a, 0x99);
+        assert!(!cpu.status.contains(CpuFlags::CARRY));
+    }
+
     #[test]
     fn test_0xc7_dcp_unofficial() {
         let rom = tests::create_simple_test_rom_with_data(vec![0xC7, 0x10, 0x00], None);
@@ -1686,10 +2719,24 @@ mod tests {
         let mut cpu = Cpu::new(bus);
         cpu.mem_write(0x10, 0x01);
         cpu.reset();
-        cpu.run();
+        run_until_brk(&mut cpu);
         assert_eq!(cpu.mem_read(0x10), 0x00);
     }
 
+    #[test]
+    fn test_0xc7_dcp_unofficial_disabled() {
+        let rom = tests::create_simple_test_rom_with_data(vec![0xC7, 0x10, 0x00], None);
+        let bus = Bus::new(rom, |_ppu: &Ppu, _joypad: &mut Joypad| {});
+        let mut cpu = Cpu::new(bus);
+        cpu.set_allow_unofficial_opcodes(false);
+        cpu.mem_write(0x10, 0x01);
+        cpu.reset();
+
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| cpu.run()));
+        assert!(result.is_err());
+        assert_eq!(cpu.mem_read(0x10), 0x01);
+    }
+
     #[test]
     fn test_0x27_rla_unofficial() {
         let rom = tests::create_simple_test_rom_with_data(vec![0xA9, 0xFF, 0x27, 0x10, 0x00], None);
@@ -1697,7 +2744,7 @@ mod tests {
         let mut cpu = Cpu::new(bus);
         cpu.mem_write(0x10, 0x01);
         cpu.reset();
-        cpu.run();
+        run_until_brk(&mut cpu);
         assert_eq!(cpu.register_a, 0x02);
     }
 
@@ -1708,7 +2755,7 @@ mod tests {
         let mut cpu = Cpu::new(bus);
         cpu.mem_write(0x10, 0x01);
         cpu.reset();
-        cpu.run();
+        run_until_brk(&mut cpu);
         assert_eq!(cpu.register_a, 0x02);
     }
 
@@ -1719,7 +2766,7 @@ mod tests {
         let mut cpu = Cpu::new(bus);
         cpu.mem_write(0x10, 0x02);
         cpu.reset();
-        cpu.run();
+        run_until_brk(&mut cpu);
         assert_eq!(cpu.register_a, 0xFE);
     }
 
@@ -1732,7 +2779,7 @@ mod tests {
         let bus = Bus::new(rom, |_ppu: &Ppu, _joypad: &mut Joypad| {});
         let mut cpu = Cpu::new(bus);
         cpu.reset();
-        cpu.run();
+        run_until_brk(&mut cpu);
         assert_eq!(cpu.register_x, 0x0D);
     }
 
@@ -1742,7 +2789,7 @@ mod tests {
         let bus = Bus::new(rom, |_ppu: &Ppu, _joypad: &mut Joypad| {});
         let mut cpu = Cpu::new(bus);
         cpu.reset();
-        cpu.run();
+        run_until_brk(&mut cpu);
         assert_eq!(cpu.register_a, 0x07);
         assert!(!cpu.status.contains(CpuFlags::CARRY));
         assert!(!cpu.status.contains(CpuFlags::OVERFLOW));
@@ -1755,7 +2802,7 @@ mod tests {
         let bus = Bus::new(rom, |_ppu: &Ppu, _joypad: &mut Joypad| {});
         let mut cpu = Cpu::new(bus);
         cpu.reset();
-        cpu.run();
+        run_until_brk(&mut cpu);
         assert_eq!(cpu.register_a, 0x01);
     }
 
@@ -1765,7 +2812,7 @@ mod tests {
         let bus = Bus::new(rom, |_ppu: &Ppu, _joypad: &mut Joypad| {});
         let mut cpu = Cpu::new(bus);
         cpu.reset();
-        cpu.run();
+        run_until_brk(&mut cpu);
         assert_eq!(cpu.register_a, 0xF0);
         assert!(cpu.status.contains(CpuFlags::CARRY));
     }
@@ -1776,7 +2823,7 @@ mod tests {
         let bus = Bus::new(rom, |_ppu: &Ppu, _joypad: &mut Joypad| {});
         let mut cpu = Cpu::new(bus);
         cpu.reset();
-        cpu.run();
+        run_until_brk(&mut cpu);
         assert_eq!(cpu.register_a, 0x78);
     }
 
@@ -1787,7 +2834,7 @@ mod tests {
         let mut cpu = Cpu::new(bus);
         cpu.mem_write(0x10, 0x10);
         cpu.reset();
-        cpu.run();
+        run_until_brk(&mut cpu);
         assert_eq!(cpu.mem_read(0x10), 0x08);
         assert_eq!(cpu.register_a, 0x09);
     }
@@ -1800,7 +2847,7 @@ mod tests {
         let mut cpu = Cpu::new(bus);
         cpu.mem_write(0x10, 0x01);
         cpu.reset();
-        cpu.run();
+        run_until_brk(&mut cpu);
         assert_eq!(cpu.mem_read(0x10), 0x02);
         assert_eq!(cpu.register_a, 0x00);
     }
@@ -1812,7 +2859,7 @@ mod tests {
         let mut cpu = Cpu::new(bus);
         cpu.mem_write(0x10, 0x01);
         cpu.reset();
-        cpu.run();
+        run_until_brk(&mut cpu);
         assert_eq!(cpu.register_a, 0x01);
         assert_eq!(cpu.register_x, 0x01);
     }
@@ -1826,7 +2873,71 @@ mod tests {
         let bus = Bus::new(rom, |_ppu: &Ppu, _joypad: &mut Joypad| {});
         let mut cpu = Cpu::new(bus);
         cpu.reset();
-        cpu.run();
+        run_until_brk(&mut cpu);
         assert_eq!(cpu.mem_read(0x10), 0xFE);
     }
+
+    #[test]
+    fn test_0x9e_shx_unofficial() {
+        // LDX #$FF; LDY #$05; SHX $0200,Y; LDX #$01; LDY #$20; SHX $02F0,Y (page cross)
+        let rom = tests::create_simple_test_rom_with_data(
+            vec![
+                0xA2, 0xFF, 0xA0, 0x05, 0x9E, 0x00, 0x02, 0xA2, 0x01, 0xA0, 0x20, 0x9E, 0xF0, 0x02,
+                0x00,
+            ],
+            None,
+        );
+        let bus = Bus::new(rom, |_ppu: &Ppu, _joypad: &mut Joypad| {});
+        let mut cpu = Cpu::new(bus);
+        cpu.reset();
+        run_until_brk(&mut cpu);
+        assert_eq!(cpu.mem_read(0x0205), 0x03);
+        // The stored value takes the place of the high byte of the address
+        assert_eq!(cpu.mem_read(0x0310), 0x00);
+        assert_eq!(cpu.mem_read(0x0110), 0x01);
+    }
+
+    #[test]
+    fn test_0x9c_shy_unofficial() {
+        // LDX #$10; LDY #$FF; SHY $0300,X
+        let rom = tests::create_simple_test_rom_with_data(
+            vec![0xA2, 0x10, 0xA0, 0xFF, 0x9C, 0x00, 0x03, 0x00],
+            None,
+        );
+        let bus = Bus::new(rom, |_ppu: &Ppu, _joypad: &mut Joypad| {});
+        let mut cpu = Cpu::new(bus);
+        cpu.reset();
+        run_until_brk(&mut cpu);
+        assert_eq!(cpu.mem_read(0x0310), 0x04);
+    }
+
+    #[test]
+    fn test_0x93_ahx_unofficial() {
+        // LDA #$FF; LDX #$0F; LDY #$01; AHX ($10),Y
+        let rom = tests::create_simple_test_rom_with_data(
+            vec![0xA9, 0xFF, 0xA2, 0x0F, 0xA0, 0x01, 0x93, 0x10, 0x00],
+            None,
+        );
+        let bus = Bus::new(rom, |_ppu: &Ppu, _joypad: &mut Joypad| {});
+        let mut cpu = Cpu::new(bus);
+        cpu.mem_write_u16(0x10, 0x0400);
+        cpu.reset();
+        run_until_brk(&mut cpu);
+        assert_eq!(cpu.mem_read(0x0401), 0x05);
+    }
+
+    #[test]
+    fn test_0x9b_tas_unofficial() {
+        // LDA #$FF; LDX #$F3; LDY #$00; TAS $0600,Y
+        let rom = tests::create_simple_test_rom_with_data(
+            vec![0xA9, 0xFF, 0xA2, 0xF3, 0xA0, 0x00, 0x9B, 0x00, 0x06, 0x00],
+            None,
+        );
+        let bus = Bus::new(rom, |_ppu: &Ppu, _joypad: &mut Joypad| {});
+        let mut cpu = Cpu::new(bus);
+        cpu.reset();
+        run_until_brk(&mut cpu);
+        assert_eq!(cpu.stack_pointer, 0xF3);
+        assert_eq!(cpu.mem_read(0x0600), 0x03);
+    }
 }
@@ -0,0 +1,102 @@
+//! Debugging helpers (disassembly) that front-ends and tools can share
+use crate::nes::cpu::Cpu;
+use crate::nes::opcodes::{AddressingMode, OPCODES_MAP};
+
+const ACCUMULATOR_OPCODES: [u8; 4] = [0x0A, 0x4A, 0x2A, 0x6A]; // ASL, LSR, ROL, ROR
+const JMP_INDIRECT_OPCODE: u8 = 0x6C;
+
+/// Disassembles the instruction at the given address without side effects,
+/// returning its mnemonic and operands (e.g. `LDA $0200,X`) and its length in bytes
+pub fn disassemble_instruction(cpu: &Cpu, addr: u16) -> (String, u8) {
+    let bus = cpu.bus();
+    let code = bus.mem_peek(addr);
+    let opcode = match OPCODES_MAP.get(&code) {
+        Some(opcode) => opcode,
+        None => return (format!(".db ${:02X}", code), 1),
+    };
+
+    let byte = bus.mem_peek(addr.wrapping_add(1));
+    let word = (bus.mem_peek(addr.wrapping_add(2)) as u16) << 8 | byte as u16;
+    let operands = match (opcode.mode(), opcode.len()) {
+        (AddressingMode::Immediate, _) => format!("#${:02X}", byte),
+        (AddressingMode::ZeroPage, _) => format!("${:02X}", byte),
+        (AddressingMode::ZeroPageX, _) => format!("${:02X},X", byte),
+        (AddressingMode::ZeroPageY, _) => format!("${:02X},Y", byte),
+        (AddressingMode::Absolute, _) => format!("${:04X}", word),
+        (AddressingMode::AbsoluteX, _) => format!("${:04X},X", word),
+        (AddressingMode::AbsoluteY, _) => format!("${:04X},Y", word),
+        (AddressingMode::IndirectX, _) => format!("(${:02X},X)", byte),
+        (AddressingMode::IndirectY, _) => format!("(${:02X}),Y", byte),
+        (AddressingMode::NoneAddressing, 1) if ACCUMULATOR_OPCODES.contains(&code) => {
+            "A".to_string()
+        }
+        (AddressingMode::NoneAddressing, 1) => String::new(),
+        (AddressingMode::NoneAddressing, 2) => {
+            // Branches: show the target instead of the relative offset
+            let target = addr.wrapping_add(2).wrapping_add(byte as i8 as u16);
+            format!("${:04X}", target)
+        }
+        (AddressingMode::NoneAddressing, _) if code == JMP_INDIRECT_OPCODE => {
+            format!("(${:04X})", word)
+        }
+        (AddressingMode::NoneAddressing, _) => format!("${:04X}", word),
+    };
+
+    let text = if operands.is_empty() {
+        opcode.mnemonic().to_string()
+    } else {
+        format!("{} {}", opcode.mnemonic(), operands)
+    };
+    (text, opcode.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nes::bus::Bus;
+    use crate::nes::cartridge::tests;
+    use crate::nes::joypad::Joypad;
+    use crate::nes::ppu::Ppu;
+
+    fn disassemble_all(program: Vec<u8>) -> Vec<String> {
+        let len = program.len() as u16;
+        let rom = tests::create_simple_test_rom_with_data(program, None);
+        let cpu = Cpu::new(Bus::new(rom, |_ppu: &Ppu, _joypad: &mut Joypad| {}));
+
+        let mut result = Vec::new();
+        let mut addr = 0x8000;
+        while addr < 0x8000 + len {
+            let (text, len) = disassemble_instruction(&cpu, addr);
+            result.push(text);
+            addr += len as u16;
+        }
+        result
+    }
+
+    #[test]
+    fn test_disassemble_instruction() {
+        let program = vec![
+            0xA9, 0x05, // LDA #$05
+            0x9D, 0x00, 0x02, // STA $0200,X
+            0xB1, 0x10, // LDA ($10),Y
+            0x0A, // ASL A
+            0xD0, 0xFA, // BNE -6
+            0x6C, 0x34, 0x12, // JMP ($1234)
+            0x20, 0x00, 0x90, // JSR $9000
+            0xE8, // INX
+        ];
+        assert_eq!(
+            disassemble_all(program),
+            vec![
+                "LDA #$05",
+                "STA $0200,X",
+                "LDA ($10),Y",
+                "ASL A",
+                "BNE $8004",
+                "JMP ($1234)",
+                "JSR $9000",
+                "INX",
+            ]
+        );
+    }
+}
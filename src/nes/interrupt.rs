@@ -3,6 +3,8 @@
 #[derive(Eq, PartialEq)]
 pub enum InterruptType {
     Nmi,
+    Irq,
+    Brk,
 }
 
 #[derive(Eq, PartialEq)]
@@ -18,4 +20,19 @@ pub const NMI: Interrupt = Interrupt {
     vec_addr: 0xFFFA,
     b_flag_mask: 0b00100000,
     cpu_cycles: 2,
-};
\ No newline at end of file
+};
+
+pub const IRQ: Interrupt = Interrupt {
+    itype: InterruptType::Irq,
+    vec_addr: 0xFFFE,
+    b_flag_mask: 0b00100000,
+    cpu_cycles: 2,
+};
+
+// The opcode table already accounts for BRK's cycles
+pub const BRK: Interrupt = Interrupt {
+    itype: InterruptType::Brk,
+    vec_addr: 0xFFFE,
+    b_flag_mask: 0b00110000,
+    cpu_cycles: 0,
+};
@@ -1,6 +1,11 @@
 /// Emulation of the NES' joypads
 
 use bitflags::bitflags;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use std::collections::HashMap;
+use std::hash::Hash;
 
 bitflags! {
     // https://wiki.nesdev.org/w/index.php/Controller_reading_code
@@ -18,6 +23,21 @@ bitflags! {
     }
 }
 
+// bitflags 1.x has no serde support, buttons are stored as their raw bits
+#[cfg(feature = "serde")]
+impl Serialize for JoypadButton {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u8(self.bits())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> Deserialize<'de> for JoypadButton {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        u8::deserialize(deserializer).map(JoypadButton::from_bits_truncate)
+    }
+}
+
 pub struct Joypad {
     strobe_mode: bool,
     button_index: u8,
@@ -55,6 +75,125 @@ impl Joypad {
     pub fn set_button_status(&mut self, button: JoypadButton, pressed: bool) {
         self.button_status.set(button, pressed)
     }
+
+    /// Whether all the given buttons are currently held
+    pub fn is_pressed(&self, button: JoypadButton) -> bool {
+        self.button_status.contains(button)
+    }
+
+    /// Every button currently held
+    pub fn pressed_buttons(&self) -> JoypadButton {
+        self.button_status
+    }
+
+    /// Holds exactly the given buttons, releasing every other one
+    pub fn set_pressed_buttons(&mut self, buttons: JoypadButton) {
+        self.button_status = buttons;
+    }
+
+    pub fn release_all(&mut self) {
+        self.button_status = JoypadButton::empty();
+    }
+}
+
+/// Maps front-end key ids (e.g. SDL keycodes, or DOM key names for a web front-end)
+/// to joypad buttons, so remapping works the same whatever the front-end is.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(bound(
+        serialize = "K: Serialize + Eq + Hash",
+        deserialize = "K: Deserialize<'de> + Eq + Hash"
+    ))
+)]
+pub struct JoypadConfig<K> {
+    bindings: HashMap<K, JoypadButton>,
+}
+
+impl<K: Eq + Hash> JoypadConfig<K> {
+    pub fn new() -> Self {
+        JoypadConfig {
+            bindings: HashMap::new(),
+        }
+    }
+
+    /// Binds the key to the button, returning what the key was bound to before
+    pub fn insert(&mut self, key: K, button: JoypadButton) -> Option<JoypadButton> {
+        self.bindings.insert(key, button)
+    }
+
+    pub fn remove(&mut self, key: &K) -> Option<JoypadButton> {
+        self.bindings.remove(key)
+    }
+
+    pub fn lookup(&self, key: &K) -> Option<JoypadButton> {
+        self.bindings.get(key).copied()
+    }
+
+    /// Presses or releases the button bound to the key. Returns false if the key isn't bound.
+    pub fn apply(&self, joypad: &mut Joypad, key: &K, pressed: bool) -> bool {
+        match self.lookup(key) {
+            Some(button) => {
+                joypad.set_button_status(button, pressed);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+impl<K: Eq + Hash> Default for JoypadConfig<K> {
+    fn default() -> Self {
+        JoypadConfig::new()
+    }
+}
+
+/// Maps an analog stick to the digital d-pad, ignoring any movement inside the deadzone.
+/// Axis values follow SDL's convention: -32768..=32767, negative being left/up.
+pub struct AnalogStickMapping {
+    deadzone: i16,
+}
+
+impl AnalogStickMapping {
+    pub const DEFAULT_DEADZONE: i16 = 8000;
+
+    pub fn new(deadzone: i16) -> Self {
+        AnalogStickMapping { deadzone }
+    }
+
+    pub fn deadzone(&self) -> i16 {
+        self.deadzone
+    }
+
+    pub fn set_deadzone(&mut self, deadzone: i16) {
+        self.deadzone = deadzone;
+    }
+
+    pub fn map_axes(&self, x: i16, y: i16) -> JoypadButton {
+        let deadzone = self.deadzone as i32;
+        let (x, y) = (x as i32, y as i32);
+
+        let mut buttons = JoypadButton::empty();
+        buttons.set(JoypadButton::LEFT, x < -deadzone);
+        buttons.set(JoypadButton::RIGHT, x > deadzone);
+        buttons.set(JoypadButton::UP, y < -deadzone);
+        buttons.set(JoypadButton::DOWN, y > deadzone);
+        buttons
+    }
+
+    /// Presses the d-pad directions the stick points to and releases the others
+    pub fn apply(&self, joypad: &mut Joypad, x: i16, y: i16) {
+        let buttons = self.map_axes(x, y);
+        for direction in [
+            JoypadButton::UP,
+            JoypadButton::DOWN,
+            JoypadButton::LEFT,
+            JoypadButton::RIGHT,
+        ] {
+            joypad.set_button_status(direction, buttons.contains(direction));
+        }
+    }
 }
 
 #[cfg(test)]
@@ -98,5 +237,91 @@ mod tests {
             joypad.write(0);
         });
     }
-}
 
+    #[test]
+    fn test_joypad_pressed_buttons() {
+        let mut joypad = Joypad::new();
+        joypad.set_button_status(JoypadButton::UP, true);
+        joypad.set_button_status(JoypadButton::BUTTON_A, true);
+
+        assert!(joypad.is_pressed(JoypadButton::UP));
+        assert!(joypad.is_pressed(JoypadButton::UP | JoypadButton::BUTTON_A));
+        assert!(!joypad.is_pressed(JoypadButton::UP | JoypadButton::DOWN));
+        assert_eq!(
+            joypad.pressed_buttons(),
+            JoypadButton::UP | JoypadButton::BUTTON_A
+        );
+
+        joypad.release_all();
+        assert!(joypad.pressed_buttons().is_empty());
+    }
+
+    #[test]
+    fn test_joypad_config() {
+        let mut config = JoypadConfig::new();
+        assert_eq!(config.insert("z", JoypadButton::BUTTON_A), None);
+        assert_eq!(config.insert("x", JoypadButton::BUTTON_B), None);
+        assert_eq!(config.lookup(&"z"), Some(JoypadButton::BUTTON_A));
+
+        // Remapping a key replaces its previous binding
+        assert_eq!(
+            config.insert("z", JoypadButton::START),
+            Some(JoypadButton::BUTTON_A)
+        );
+        assert_eq!(config.lookup(&"z"), Some(JoypadButton::START));
+
+        assert_eq!(config.remove(&"x"), Some(JoypadButton::BUTTON_B));
+        assert_eq!(config.lookup(&"x"), None);
+    }
+
+    #[test]
+    fn test_joypad_config_apply() {
+        let mut config = JoypadConfig::new();
+        config.insert(1, JoypadButton::LEFT);
+        let mut joypad = Joypad::new();
+
+        assert!(config.apply(&mut joypad, &1, true));
+        assert!(joypad.is_pressed(JoypadButton::LEFT));
+        assert!(!config.apply(&mut joypad, &2, true));
+        assert_eq!(joypad.pressed_buttons(), JoypadButton::LEFT);
+
+        config.apply(&mut joypad, &1, false);
+        assert!(joypad.pressed_buttons().is_empty());
+    }
+
+    #[test]
+    fn test_analog_stick_inside_deadzone() {
+        let mapping = AnalogStickMapping::new(8000);
+        assert!(mapping.map_axes(0, 0).is_empty());
+        assert!(mapping.map_axes(7999, -8000).is_empty());
+        assert!(mapping.map_axes(-8000, 8000).is_empty());
+    }
+
+    #[test]
+    fn test_analog_stick_outside_deadzone() {
+        let mapping = AnalogStickMapping::new(8000);
+        assert_eq!(mapping.map_axes(8001, 0), JoypadButton::RIGHT);
+        assert_eq!(mapping.map_axes(-32768, 0), JoypadButton::LEFT);
+        assert_eq!(mapping.map_axes(0, -20000), JoypadButton::UP);
+        assert_eq!(
+            mapping.map_axes(32767, 32767),
+            JoypadButton::RIGHT | JoypadButton::DOWN
+        );
+    }
+
+    #[test]
+    fn test_analog_stick_apply_to_joypad() {
+        let mapping = AnalogStickMapping::new(AnalogStickMapping::DEFAULT_DEADZONE);
+        let mut joypad = Joypad::new();
+        joypad.set_button_status(JoypadButton::BUTTON_A, true);
+
+        mapping.apply(&mut joypad, -32768, 0);
+        assert_eq!(
+            joypad.button_status,
+            JoypadButton::BUTTON_A | JoypadButton::LEFT
+        );
+
+        mapping.apply(&mut joypad, 0, 0);
+        assert_eq!(joypad.button_status, JoypadButton::BUTTON_A);
+    }
+}
@@ -0,0 +1,58 @@
+//! The CHR memory of a cart: its CHR-ROM, or CHR RAM for carts without one
+use crate::nes::state::{StateError, StateReader, StateWriter};
+
+const CHR_RAM_SIZE: usize = 8192; // 8KB
+
+pub(crate) struct Chr {
+    data: Vec<u8>,
+    is_ram: bool,
+}
+
+impl Chr {
+    /// Carts without CHR-ROM get 8KB of CHR RAM instead
+    pub fn new(chr_rom: Vec<u8>) -> Self {
+        let is_ram = chr_rom.is_empty();
+        Chr {
+            data: if is_ram {
+                vec![0; CHR_RAM_SIZE]
+            } else {
+                chr_rom
+            },
+            is_ram,
+        }
+    }
+
+    /// Uses the CHR RAM size declared by the ROM header, ignored for carts with CHR-ROM
+    pub fn with_ram_size(mut self, chr_ram_size: usize) -> Self {
+        if self.is_ram && chr_ram_size > 0 {
+            self.data = vec![0; chr_ram_size.min(CHR_RAM_SIZE)];
+        }
+        self
+    }
+
+    /// Reads from the pattern tables, mirroring CHR smaller than 8KB
+    pub fn read(&self, addr: u16) -> u8 {
+        self.data[addr as usize % self.data.len()]
+    }
+
+    /// Writes to CHR RAM. Writes to CHR-ROM are ignored, like on the real cart.
+    pub fn write(&mut self, addr: u16, data: u8) {
+        if self.is_ram {
+            let len = self.data.len();
+            self.data[addr as usize % len] = data;
+        }
+    }
+
+    /// Only CHR RAM is saved, CHR-ROM comes from the cart
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.write_bytes(if self.is_ram { &self.data } else { &[] });
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        if self.is_ram {
+            state.read_into(&mut self.data)
+        } else {
+            state.read_bytes().map(|_| ())
+        }
+    }
+}
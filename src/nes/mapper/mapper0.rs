@@ -0,0 +1,162 @@
+//! Mapper 0 (NROM): no bank switching, 16KB or 32KB of PRG-ROM and 8KB of CHR
+use crate::nes::cartridge::MirroringMode;
+use crate::nes::mapper::chr::Chr;
+use crate::nes::mapper::Mapper;
+use crate::nes::state::{StateError, StateReader, StateWriter};
+
+const PRG_RAM_SIZE: usize = 8192; // 8KB
+
+pub struct Mapper0 {
+    prg_rom: Vec<u8>,
+    chr: Chr,
+    prg_ram: Vec<u8>,
+    has_battery: bool,
+    mirroring: MirroringMode,
+}
+
+impl Mapper0 {
+    /// Carts without CHR-ROM get 8KB of CHR RAM instead, and every cart 8KB of PRG RAM,
+    /// unless `with_ram_sizes` says otherwise
+    pub fn new(
+        prg_rom: Vec<u8>,
+        chr_rom: Vec<u8>,
+        mirroring: MirroringMode,
+        has_battery: bool,
+    ) -> Self {
+        Mapper0 {
+            prg_rom,
+            chr: Chr::new(chr_rom),
+            prg_ram: vec![0; PRG_RAM_SIZE],
+            has_battery,
+            mirroring,
+        }
+    }
+
+    /// Uses the RAM sizes declared by the ROM header. The 8KB PRG RAM window mirrors
+    /// smaller RAM, and CHR RAM is only used by carts without CHR-ROM.
+    pub fn with_ram_sizes(mut self, prg_ram_size: usize, chr_ram_size: usize) -> Self {
+        self.prg_ram = vec![0; prg_ram_size.min(PRG_RAM_SIZE)];
+        self.chr = self.chr.with_ram_size(chr_ram_size);
+        self
+    }
+}
+
+impl Mapper for Mapper0 {
+    fn cpu_read(&self, addr: u16) -> u8 {
+        if addr < 0x8000 {
+            return match addr {
+                0x6000..=0x7FFF if !self.prg_ram.is_empty() => {
+                    self.prg_ram[(addr - 0x6000) as usize % self.prg_ram.len()]
+                }
+                _ => 0,
+            };
+        }
+        let mut addr = addr - 0x8000; // set addr relative to 0
+        if self.prg_rom.len() == 0x4000 && addr >= 0x4000 {
+            addr = addr % 0x4000; // Mirror if needed
+        }
+        self.prg_rom[addr as usize]
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        if let 0x6000..=0x7FFF = addr {
+            if !self.prg_ram.is_empty() {
+                let len = self.prg_ram.len();
+                self.prg_ram[(addr - 0x6000) as usize % len] = data;
+            }
+            return;
+        }
+        panic!("Bus: Attempted to write to PRG_ROM address {:#X}", addr);
+    }
+
+    fn ppu_read(&self, addr: u16) -> u8 {
+        self.chr.read(addr)
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        self.chr.write(addr, data);
+    }
+
+    fn mirroring(&self) -> MirroringMode {
+        self.mirroring
+    }
+
+    fn save_ram(&self) -> Option<Vec<u8>> {
+        if self.has_battery {
+            Some(self.prg_ram.to_vec())
+        } else {
+            None
+        }
+    }
+
+    fn load_ram(&mut self, data: &[u8]) {
+        if self.has_battery {
+            let len = data.len().min(self.prg_ram.len());
+            self.prg_ram[..len].copy_from_slice(&data[..len]);
+        }
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bytes(&self.prg_ram);
+        self.chr.save_state(state);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        state.read_into(&mut self.prg_ram)?;
+        self.chr.load_state(state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mapper0_mirrors_16kb_prg_rom() {
+        let mut prg_rom = vec![0; 0x4000];
+        prg_rom[0x0010] = 0xAB;
+        let mapper = Mapper0::new(prg_rom, vec![0; 0x2000], MirroringMode::Vertical, false);
+
+        assert_eq!(mapper.cpu_read(0x8010), 0xAB);
+        assert_eq!(mapper.cpu_read(0xC010), 0xAB);
+        assert_eq!(mapper.mirroring(), MirroringMode::Vertical);
+    }
+
+    #[test]
+    fn test_mapper0_ram_sizes() {
+        let mut mapper = Mapper0::new(vec![0; 0x4000], Vec::new(), MirroringMode::Vertical, true)
+            .with_ram_sizes(0x0800, 0x1000);
+        mapper.cpu_write(0x6001, 0xAB);
+        assert_eq!(mapper.cpu_read(0x6801), 0xAB); // 2KB mirrored in the 8KB window
+        assert_eq!(mapper.save_ram().unwrap().len(), 0x0800);
+        mapper.ppu_write(0x0002, 0xCD);
+        assert_eq!(mapper.ppu_read(0x1002), 0xCD);
+
+        // No PRG RAM at all
+        let mut mapper = Mapper0::new(vec![0; 0x4000], Vec::new(), MirroringMode::Vertical, false)
+            .with_ram_sizes(0, 0x2000);
+        mapper.cpu_write(0x6001, 0xAB);
+        assert_eq!(mapper.cpu_read(0x6001), 0x00);
+    }
+
+    #[test]
+    fn test_mapper0_chr_ram() {
+        let mut mapper = Mapper0::new(
+            vec![0; 0x4000],
+            Vec::new(),
+            MirroringMode::Horizontal,
+            false,
+        );
+        mapper.ppu_write(0x1FFF, 0xCD);
+        assert_eq!(mapper.ppu_read(0x1FFF), 0xCD);
+
+        let mut mapper = Mapper0::new(
+            vec![0; 0x4000],
+            vec![0; 0x2000],
+            MirroringMode::Horizontal,
+            false,
+        );
+        mapper.ppu_write(0x1FFF, 0xCD);
+        assert_eq!(mapper.ppu_read(0x1FFF), 0x00);
+    }
+}
@@ -0,0 +1,118 @@
+//! Mapper 2 (UxROM): switchable 16KB PRG-ROM bank at 0x8000, the last bank fixed at 0xC000,
+//! and 8KB of CHR, usually RAM
+use crate::nes::cartridge::MirroringMode;
+use crate::nes::mapper::chr::Chr;
+use crate::nes::mapper::Mapper;
+use crate::nes::state::{StateError, StateReader, StateWriter};
+
+const PRG_BANK_SIZE: usize = 16384; // 16KB
+
+pub struct Mapper2 {
+    prg_rom: Vec<u8>,
+    chr: Chr,
+    prg_bank: usize,
+    mirroring: MirroringMode,
+}
+
+impl Mapper2 {
+    /// Carts without CHR-ROM get 8KB of CHR RAM instead
+    pub fn new(prg_rom: Vec<u8>, chr_rom: Vec<u8>, mirroring: MirroringMode) -> Self {
+        Mapper2 {
+            prg_rom,
+            chr: Chr::new(chr_rom),
+            prg_bank: 0,
+            mirroring,
+        }
+    }
+
+    /// Uses the CHR RAM size declared by the ROM header, for carts without CHR-ROM
+    pub fn with_chr_ram_size(mut self, chr_ram_size: usize) -> Self {
+        self.chr = self.chr.with_ram_size(chr_ram_size);
+        self
+    }
+
+    fn prg_bank_count(&self) -> usize {
+        (self.prg_rom.len() / PRG_BANK_SIZE).max(1)
+    }
+}
+
+impl Mapper for Mapper2 {
+    fn cpu_read(&self, addr: u16) -> u8 {
+        if addr < 0x8000 {
+            return 0;
+        }
+        let bank = if addr < 0xC000 {
+            self.prg_bank
+        } else {
+            self.prg_bank_count() - 1
+        };
+        let offset = bank * PRG_BANK_SIZE + (addr & 0x3FFF) as usize;
+        self.prg_rom[offset % self.prg_rom.len()]
+    }
+
+    /// Bank select, %PPPP PPPP: the 16KB PRG bank at 0x8000 (P). Boards only wire the
+    /// low bits they need, so bank numbers past the end of the ROM wrap around.
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        // There is no PRG RAM
+        if addr < 0x8000 {
+            return;
+        }
+        self.prg_bank = data as usize % self.prg_bank_count();
+    }
+
+    fn ppu_read(&self, addr: u16) -> u8 {
+        self.chr.read(addr)
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        self.chr.write(addr, data);
+    }
+
+    fn mirroring(&self) -> MirroringMode {
+        self.mirroring
+    }
+
+    fn reset(&mut self) {
+        self.prg_bank = 0;
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_u8(self.prg_bank as u8);
+        self.chr.save_state(state);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.prg_bank = state.read_u8()? as usize % self.prg_bank_count();
+        self.chr.load_state(state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mapper2_switches_16kb_prg_banks() {
+        let mut prg_rom = vec![0; 4 * PRG_BANK_SIZE];
+        prg_rom[0x0010] = 0xAA;
+        prg_rom[2 * PRG_BANK_SIZE + 0x0010] = 0xBB;
+        prg_rom[3 * PRG_BANK_SIZE + 0x3FFC] = 0xCC;
+        let mut mapper = Mapper2::new(prg_rom, Vec::new(), MirroringMode::Vertical);
+
+        assert_eq!(mapper.cpu_read(0x8010), 0xAA);
+        assert_eq!(mapper.cpu_read(0xFFFC), 0xCC);
+        mapper.cpu_write(0x8000, 0x02);
+        assert_eq!(mapper.cpu_read(0x8010), 0xBB);
+        // The last bank stays in place
+        assert_eq!(mapper.cpu_read(0xFFFC), 0xCC);
+
+        // Bank numbers past the end of the ROM wrap around
+        mapper.cpu_write(0xFFFF, 0x04);
+        assert_eq!(mapper.cpu_read(0x8010), 0xAA);
+
+        mapper.cpu_write(0x8000, 0x02);
+        mapper.reset();
+        assert_eq!(mapper.cpu_read(0x8010), 0xAA);
+        assert_eq!(mapper.mirroring(), MirroringMode::Vertical);
+    }
+}
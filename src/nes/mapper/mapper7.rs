@@ -0,0 +1,131 @@
+//! Mapper 7 (AxROM): switchable 32KB PRG-ROM bank, 8KB of CHR RAM and single-screen
+//! mirroring selected by the same register
+use crate::nes::cartridge::MirroringMode;
+use crate::nes::mapper::chr::Chr;
+use crate::nes::mapper::Mapper;
+use crate::nes::state::{StateError, StateReader, StateWriter};
+
+const PRG_BANK_SIZE: usize = 32768; // 32KB
+
+const PRG_BANK_MASK: u8 = 0b0000_0111;
+const NAMETABLE_SELECT_FLAG: u8 = 0b0001_0000;
+
+pub struct Mapper7 {
+    prg_rom: Vec<u8>,
+    chr: Chr,
+    prg_bank: usize,
+    mirroring: MirroringMode,
+}
+
+impl Mapper7 {
+    /// Carts without CHR-ROM (all known AxROM boards) get 8KB of CHR RAM instead
+    pub fn new(prg_rom: Vec<u8>, chr_rom: Vec<u8>) -> Self {
+        Mapper7 {
+            prg_rom,
+            chr: Chr::new(chr_rom),
+            prg_bank: 0,
+            mirroring: MirroringMode::SingleScreenLower,
+        }
+    }
+
+    /// Uses the CHR RAM size declared by the ROM header, for carts without CHR-ROM
+    pub fn with_chr_ram_size(mut self, chr_ram_size: usize) -> Self {
+        self.chr = self.chr.with_ram_size(chr_ram_size);
+        self
+    }
+
+    fn prg_bank_count(&self) -> usize {
+        (self.prg_rom.len() / PRG_BANK_SIZE).max(1)
+    }
+}
+
+impl Mapper for Mapper7 {
+    fn cpu_read(&self, addr: u16) -> u8 {
+        if addr < 0x8000 {
+            return 0;
+        }
+        let offset = self.prg_bank * PRG_BANK_SIZE + (addr - 0x8000) as usize;
+        self.prg_rom[offset % self.prg_rom.len()]
+    }
+
+    /// Bank select, %---M -PPP: the 32KB PRG bank (P) and the nametable (M)
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        // There is no PRG RAM
+        if addr < 0x8000 {
+            return;
+        }
+        self.prg_bank = (data & PRG_BANK_MASK) as usize % self.prg_bank_count();
+        self.mirroring = if data & NAMETABLE_SELECT_FLAG != 0 {
+            MirroringMode::SingleScreenUpper
+        } else {
+            MirroringMode::SingleScreenLower
+        };
+    }
+
+    fn ppu_read(&self, addr: u16) -> u8 {
+        self.chr.read(addr)
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        self.chr.write(addr, data);
+    }
+
+    fn mirroring(&self) -> MirroringMode {
+        self.mirroring
+    }
+
+    fn reset(&mut self) {
+        self.prg_bank = 0;
+        self.mirroring = MirroringMode::SingleScreenLower;
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_u8(self.prg_bank as u8);
+        state.write_bool(self.mirroring == MirroringMode::SingleScreenUpper);
+        self.chr.save_state(state);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.prg_bank = state.read_u8()? as usize % self.prg_bank_count();
+        self.mirroring = if state.read_bool()? {
+            MirroringMode::SingleScreenUpper
+        } else {
+            MirroringMode::SingleScreenLower
+        };
+        self.chr.load_state(state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mapper7_switches_32kb_prg_banks() {
+        let mut prg_rom = vec![0; 4 * PRG_BANK_SIZE];
+        prg_rom[0x0010] = 0xAA;
+        prg_rom[2 * PRG_BANK_SIZE + 0x7FFC] = 0xBB;
+        let mut mapper = Mapper7::new(prg_rom, Vec::new());
+
+        assert_eq!(mapper.cpu_read(0x8010), 0xAA);
+        mapper.cpu_write(0x8000, 0x02);
+        assert_eq!(mapper.cpu_read(0xFFFC), 0xBB);
+        // Bank numbers past the end of the ROM wrap around
+        mapper.cpu_write(0xFFFF, 0x04);
+        assert_eq!(mapper.cpu_read(0x8010), 0xAA);
+
+        mapper.cpu_write(0x8000, 0x02);
+        mapper.reset();
+        assert_eq!(mapper.cpu_read(0x8010), 0xAA);
+    }
+
+    #[test]
+    fn test_mapper7_nametable_select() {
+        let mut mapper = Mapper7::new(vec![0; PRG_BANK_SIZE], Vec::new());
+        assert_eq!(mapper.mirroring(), MirroringMode::SingleScreenLower);
+        mapper.cpu_write(0x8000, NAMETABLE_SELECT_FLAG);
+        assert_eq!(mapper.mirroring(), MirroringMode::SingleScreenUpper);
+        mapper.cpu_write(0x8000, 0x00);
+        assert_eq!(mapper.mirroring(), MirroringMode::SingleScreenLower);
+    }
+}
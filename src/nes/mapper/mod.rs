@@ -0,0 +1,61 @@
+//! Cartridge mappers, which translate the CPU and PPU addresses into the cartridge memory
+mod chr;
+mod mapper0;
+mod mapper2;
+mod mapper7;
+
+pub use crate::nes::mapper::mapper0::Mapper0;
+pub use crate::nes::mapper::mapper2::Mapper2;
+pub use crate::nes::mapper::mapper7::Mapper7;
+
+use crate::nes::cartridge::MirroringMode;
+use crate::nes::state::{StateError, StateReader, StateWriter};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+/// The mapper is shared between the bus (PRG accesses) and the PPU (CHR accesses)
+pub type SharedMapper = Rc<RefCell<Box<dyn Mapper>>>;
+
+pub trait Mapper {
+    /// Reads from the cartridge space of the CPU (0x4020-0xFFFF)
+    fn cpu_read(&self, addr: u16) -> u8;
+
+    /// Writes to the cartridge space of the CPU, which is how games talk to bank-switching mappers
+    fn cpu_write(&mut self, addr: u16, data: u8);
+
+    /// Reads from the pattern tables (0x0000-0x1FFF)
+    fn ppu_read(&self, addr: u16) -> u8;
+
+    /// Writes to the pattern tables, only meaningful for carts with CHR RAM
+    fn ppu_write(&mut self, addr: u16, data: u8);
+
+    /// The nametable mirroring, which some mappers can switch at runtime
+    fn mirroring(&self) -> MirroringMode;
+
+    /// Contents of the battery-backed PRG RAM, `None` if the cart has no battery
+    fn save_ram(&self) -> Option<Vec<u8>> {
+        None
+    }
+
+    /// Restores the battery-backed PRG RAM, ignored if the cart has no battery
+    fn load_ram(&mut self, _data: &[u8]) {}
+
+    /// Puts the mapper registers (e.g. the selected banks) back in their power-on state
+    fn reset(&mut self) {}
+
+    /// Saves the mapper registers and the cartridge RAM, see `MachineState`
+    fn save_state(&self, _state: &mut StateWriter) {}
+
+    /// Restores what `save_state` saved
+    fn load_state(&mut self, _state: &mut StateReader) -> Result<(), StateError> {
+        Ok(())
+    }
+
+    /// Called by the PPU on every rise of its address line A12 while rendering,
+    /// which mappers like MMC3 use to count scanlines
+    fn notify_a12_rise(&mut self) {}
+}
+
+pub fn share(mapper: Box<dyn Mapper>) -> SharedMapper {
+    Rc::new(RefCell::new(mapper))
+}
@@ -34,15 +34,23 @@ pub trait Memory {
 
     fn mem_read_u16(&mut self, addr: u16) -> u16 {
         let lo = self.mem_read(addr) as u16;
-        let hi = self.mem_read(addr + 1) as u16;
+        let hi = self.mem_read(addr.wrapping_add(1)) as u16;
         (hi << 8) | (lo as u16)
     }
 
+    /// Reads a pointer from the zero page, whose high byte wraps around to 0x00 instead of
+    /// leaving the zero page, like the 6502 does for (zp,X) and (zp),Y
+    fn mem_read_u16_zp(&mut self, zp: u8) -> u16 {
+        let lo = self.mem_read(zp as u16) as u16;
+        let hi = self.mem_read(zp.wrapping_add(1) as u16) as u16;
+        (hi << 8) | lo
+    }
+
     fn mem_write_u16(&mut self, addr: u16, data: u16) {
         let hi = (data >> 8) as u8;
         let lo = (data & 0xff) as u8;
         self.mem_write(addr, lo);
-        self.mem_write(addr + 1, hi);
+        self.mem_write(addr.wrapping_add(1), hi);
     }
 }
 
@@ -50,17 +58,18 @@ pub trait Memory {
 mod tests {
     use super::*;
 
+    // 2KB mirrored across the whole address space
     struct TestMem {
         memory: [u8; 2048],
     }
 
     impl Memory for TestMem {
         fn mem_read(&mut self, addr: u16) -> u8 {
-            self.memory[addr as usize]
+            self.memory[addr as usize % 2048]
         }
 
         fn mem_write(&mut self, addr: u16, data: u8) {
-            self.memory[addr as usize] = data;
+            self.memory[addr as usize % 2048] = data;
         }
     }
 
@@ -72,6 +81,24 @@ mod tests {
         assert_eq!(mem.mem_read_u16(0x00), 0x0010);
     }
 
+    #[test]
+    fn test_memory_trait_mem_read_16_wraps_at_end_of_address_space() {
+        let mut mem = TestMem { memory: [0; 2048] };
+        mem.memory[0x07FF] = 0x34; // mirrored at 0xFFFF
+        mem.memory[0x0000] = 0x12;
+        assert_eq!(mem.mem_read_u16(0xFFFF), 0x1234);
+    }
+
+    #[test]
+    fn test_memory_trait_mem_read_16_zp_wraps_within_zero_page() {
+        let mut mem = TestMem { memory: [0; 2048] };
+        mem.memory[0x00FF] = 0x34;
+        mem.memory[0x0000] = 0x12;
+        mem.memory[0x0100] = 0xAB;
+        assert_eq!(mem.mem_read_u16_zp(0xFF), 0x1234);
+        assert_eq!(mem.mem_read_u16(0x00FF), 0xAB34);
+    }
+
     #[test]
     fn test_memory_trait_default_mem_write_16() {
         let mut mem = TestMem { memory: [0; 2048] };
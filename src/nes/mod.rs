@@ -5,5 +5,10 @@ pub mod cartridge;
 pub mod opcodes;
 pub mod ppu;
 pub mod joypad;
+pub mod mapper;
 pub mod render;
+pub mod apu;
+pub mod debug;
+pub mod console;
+pub mod state;
 mod interrupt;
@@ -1,7 +1,7 @@
 /// Operation codes and instructions for the the NES' custom 6502 CPU
 use std::collections::HashMap;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum AddressingMode {
     Immediate,
     ZeroPage,
@@ -60,6 +60,16 @@ impl OpCode {
     pub fn mode(&self) -> &AddressingMode {
         &self.mode
     }
+
+    /// Unofficial (undocumented) opcodes are the ones with a '*' prefixed mnemonic
+    pub fn is_official(&self) -> bool {
+        !self.mnemonic.starts_with('*')
+    }
+}
+
+/// Every opcode, sorted by code, e.g. to build an opcode reference table
+pub fn all() -> impl Iterator<Item = &'static OpCode> {
+    (0..=u8::MAX).filter_map(|code| OPCODES_MAP.get(&code).copied())
 }
 
 lazy_static! {
@@ -335,19 +345,19 @@ lazy_static! {
         OpCode::new(0xE3, "*ISB", 2, 8, AddressingMode::IndirectX),
         OpCode::new(0xF3, "*ISB", 2, 8, AddressingMode::IndirectY),
 
-        /* Technically these are KILL opcodes */
-        OpCode::new(0x02, "*NOP", 1, 2, AddressingMode::NoneAddressing),
-        OpCode::new(0x12, "*NOP", 1, 2, AddressingMode::NoneAddressing),
-        OpCode::new(0x22, "*NOP", 1, 2, AddressingMode::NoneAddressing),
-        OpCode::new(0x32, "*NOP", 1, 2, AddressingMode::NoneAddressing),
-        OpCode::new(0x42, "*NOP", 1, 2, AddressingMode::NoneAddressing),
-        OpCode::new(0x52, "*NOP", 1, 2, AddressingMode::NoneAddressing),
-        OpCode::new(0x62, "*NOP", 1, 2, AddressingMode::NoneAddressing),
-        OpCode::new(0x72, "*NOP", 1, 2, AddressingMode::NoneAddressing),
-        OpCode::new(0x92, "*NOP", 1, 2, AddressingMode::NoneAddressing),
-        OpCode::new(0xB2, "*NOP", 1, 2, AddressingMode::NoneAddressing),
-        OpCode::new(0xD2, "*NOP", 1, 2, AddressingMode::NoneAddressing),
-        OpCode::new(0xF2, "*NOP", 1, 2, AddressingMode::NoneAddressing),
+        /* KIL/JAM opcodes, they halt the CPU */
+        OpCode::new(0x02, "*JAM", 1, 2, AddressingMode::NoneAddressing),
+        OpCode::new(0x12, "*JAM", 1, 2, AddressingMode::NoneAddressing),
+        OpCode::new(0x22, "*JAM", 1, 2, AddressingMode::NoneAddressing),
+        OpCode::new(0x32, "*JAM", 1, 2, AddressingMode::NoneAddressing),
+        OpCode::new(0x42, "*JAM", 1, 2, AddressingMode::NoneAddressing),
+        OpCode::new(0x52, "*JAM", 1, 2, AddressingMode::NoneAddressing),
+        OpCode::new(0x62, "*JAM", 1, 2, AddressingMode::NoneAddressing),
+        OpCode::new(0x72, "*JAM", 1, 2, AddressingMode::NoneAddressing),
+        OpCode::new(0x92, "*JAM", 1, 2, AddressingMode::NoneAddressing),
+        OpCode::new(0xB2, "*JAM", 1, 2, AddressingMode::NoneAddressing),
+        OpCode::new(0xD2, "*JAM", 1, 2, AddressingMode::NoneAddressing),
+        OpCode::new(0xF2, "*JAM", 1, 2, AddressingMode::NoneAddressing),
 
         OpCode::new(0x1A, "*NOP", 1, 2, AddressingMode::NoneAddressing),
         OpCode::new(0x3A, "*NOP", 1, 2, AddressingMode::NoneAddressing),
@@ -358,19 +368,19 @@ lazy_static! {
 
         OpCode::new(0xAB, "*LXA", 2, 3, AddressingMode::Immediate), // Highly unstable and not used
         OpCode::new(0x8B, "*XAA", 2, 3, AddressingMode::Immediate), // Highly unstable and not used
-        OpCode::new(0xBB, "*LAS", 3, 2, AddressingMode::AbsoluteY), // Highly unstable and not used
-        OpCode::new(0x9B, "*TAS", 3, 2, AddressingMode::AbsoluteY), // Highly unstable and not used
-        OpCode::new(0x93, "*AHX", 2, /* guess */ 8, AddressingMode::IndirectY), // Highly unstable and not used
-        OpCode::new(0x9F, "*AHX", 3, /* guess */ 4/* or 5*/, AddressingMode::AbsoluteY), // Highly unstable and not used
-        OpCode::new(0x9E, "*SHX", 3, /* guess */ 4/* or 5*/, AddressingMode::AbsoluteY), // Highly unstable and not used
-        OpCode::new(0x9C, "*SHY", 3, /* guess */ 4/* or 5*/, AddressingMode::AbsoluteX), // Highly unstable and not used
+        OpCode::new(0xBB, "*LAS", 3, 4/*+1 if page crossed*/, AddressingMode::AbsoluteY), // Highly unstable and not used
+        OpCode::new(0x9B, "*TAS", 3, 5, AddressingMode::AbsoluteY), // Highly unstable and not used
+        OpCode::new(0x93, "*AHX", 2, 6, AddressingMode::IndirectY), // Highly unstable and not used
+        OpCode::new(0x9F, "*AHX", 3, 5, AddressingMode::AbsoluteY), // Highly unstable and not used
+        OpCode::new(0x9E, "*SHX", 3, 5, AddressingMode::AbsoluteY), // Highly unstable and not used
+        OpCode::new(0x9C, "*SHY", 3, 5, AddressingMode::AbsoluteX), // Highly unstable and not used
 
         OpCode::new(0xA7, "*LAX", 2, 3, AddressingMode::ZeroPage),
         OpCode::new(0xB7, "*LAX", 2, 4, AddressingMode::ZeroPageY),
         OpCode::new(0xAF, "*LAX", 3, 4, AddressingMode::Absolute),
-        OpCode::new(0xBF, "*LAX", 3, 4, AddressingMode::AbsoluteY),
+        OpCode::new(0xBF, "*LAX", 3, 4/*+1 if page crossed*/, AddressingMode::AbsoluteY),
         OpCode::new(0xA3, "*LAX", 2, 6, AddressingMode::IndirectX),
-        OpCode::new(0xB3, "*LAX", 2, 5, AddressingMode::IndirectY),
+        OpCode::new(0xB3, "*LAX", 2, 5/*+1 if page crossed*/, AddressingMode::IndirectY),
 
         OpCode::new(0x87, "*SAX", 2, 3, AddressingMode::ZeroPage),
         OpCode::new(0x97, "*SAX", 2, 4, AddressingMode::ZeroPageY),
@@ -386,3 +396,20 @@ lazy_static! {
         map
     };
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_all_opcodes() {
+        let codes: Vec<u8> = all().map(|op| op.code()).collect();
+        assert_eq!(codes, (0..=u8::MAX).collect::<Vec<u8>>());
+        assert_eq!(all().filter(|op| op.is_official()).count(), 151);
+
+        let lda = all().find(|op| op.code() == 0xBD).unwrap();
+        assert_eq!((lda.mnemonic(), lda.len(), lda.cycles()), ("LDA", 3, 4));
+        assert_eq!(*lda.mode(), AddressingMode::AbsoluteX);
+        assert!(!all().find(|op| op.code() == 0xA7).unwrap().is_official());
+    }
+}
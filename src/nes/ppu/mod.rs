@@ -1,22 +1,35 @@
 /// Implementation of the NES' PPU (picture-processing unit)
 mod registers;
+mod sprite;
 
-use crate::nes::cartridge::MirroringMode;
-use crate::nes::ppu::registers::address::AddressRegister;
+use crate::nes::cartridge::{MirroringMode, TvSystem};
+use crate::nes::mapper;
+use crate::nes::mapper::{Mapper0, SharedMapper};
 use crate::nes::ppu::registers::control::ControlRegister;
-use crate::nes::ppu::registers::mask::MaskRegister;
-use crate::nes::ppu::registers::scroll::ScrollRegister;
+use crate::nes::ppu::registers::mask::{Color, MaskRegister};
 use crate::nes::ppu::registers::status::StatusRegister;
+use crate::nes::ppu::registers::vram_address::VramAddressRegister;
+use crate::nes::render;
+use crate::nes::render::frame::Frame;
+use crate::nes::state::{StateError, StateReader, StateWriter};
+use std::mem;
+
+pub use crate::nes::ppu::sprite::Sprite;
+
+const VISIBLE_SCANLINES: usize = 240;
+// Same for NTSC and PAL, PAL just has a longer vblank
+const VBLANK_SCANLINE: u16 = 241;
+// 2KB inside the console, plus the 2KB that four-screen carts add for all four nametables
+const VRAM_SIZE: usize = 4096;
 
 pub struct Ppu {
-    vram: [u8; 2048],
-    chr_rom: Vec<u8>,
-    mirroring_mode: MirroringMode,
+    vram: [u8; VRAM_SIZE],
+    mapper: SharedMapper,
 
-    addr_register: AddressRegister,
     ctrl_register: ControlRegister,
     mask_register: MaskRegister,
-    scroll_register: ScrollRegister,
+    // Shared by the scroll (0x2005) and address (0x2006) registers
+    vram_addr_register: VramAddressRegister,
     status_register: StatusRegister,
 
     oam_addr_register: u8,
@@ -24,69 +37,420 @@ pub struct Ppu {
     palette_table: [u8; 32],
 
     internal_data_buffer: u8,
+    // Mask register as it was at the end of each visible scanline of the last frame
+    scanline_masks: [MaskRegister; VISIBLE_SCANLINES],
 
     scanline: u16,
     cycles: usize,
     nmi_interrupt: Option<u8>,
+    // Set by a status read right before vblank starts, which keeps the flag and the NMI
+    // from happening for that frame
+    vblank_suppressed: bool,
+    // Last value driven on the CPU-PPU data bus, unused status bits read it back
+    last_bus_value: u8,
+    tv_system: TvSystem,
+    // Takes the place of the mapper's mirroring once set, see `set_mirroring`
+    mirroring_override: Option<MirroringMode>,
+    // Row (0-479) of the four nametables that scanline 0 would show, see `scanline_scroll_y`
+    scroll_origin_y: u16,
+    // Set by a 0x2006 write during rendering, the scanlines after it start from t
+    scroll_y_reload_pending: bool,
+    // Column (0-511) of the four nametables at the left edge of the current scanline, and
+    // of the next one, copied from t at dot 257 like the horizontal bits of v
+    scroll_x: u16,
+    next_scroll_x: u16,
+    // Fetches of the current scanline (8 sprite slots, then the next scanline's first tiles)
+    // during which A12 rises, worked out when the sprite fetches start
+    a12_rise_fetches: u16,
+
+    // The frame the visible scanlines are rendered to, swapped with `frame` when it completes
+    frame_in_progress: Frame,
+    frame: Frame,
 }
 
 impl Ppu {
+    /// Creates an NTSC PPU backed by a mapper 0 cart with the given CHR-ROM
     pub fn new(chr_rom: Vec<u8>, mirroring_mode: MirroringMode) -> Self {
+        let mapper = Mapper0::new(Vec::new(), chr_rom, mirroring_mode, false);
+        Ppu::with_mapper(mapper::share(Box::new(mapper)), TvSystem::Ntsc)
+    }
+
+    /// Creates a PPU that accesses CHR and gets the mirroring through the cartridge mapper,
+    /// with the frame timing of the given TV system
+    pub fn with_mapper(mapper: SharedMapper, tv_system: TvSystem) -> Self {
         Ppu {
-            vram: [0; 2048],
-            chr_rom,
-            mirroring_mode,
-            addr_register: AddressRegister::new(),
+            vram: [0; VRAM_SIZE],
+            mapper,
             ctrl_register: ControlRegister::new(),
             mask_register: MaskRegister::new(),
-            scroll_register: ScrollRegister::new(),
+            vram_addr_register: VramAddressRegister::new(),
             status_register: StatusRegister::new(),
             oam_addr_register: 0,
             oam_data_register: [0; 64 * 4],
             palette_table: [0; 32],
             internal_data_buffer: 0,
+            scanline_masks: [MaskRegister::new(); VISIBLE_SCANLINES],
             scanline: 0,
             cycles: 0,
             nmi_interrupt: None,
+            vblank_suppressed: false,
+            last_bus_value: 0,
+            tv_system,
+            mirroring_override: None,
+            scroll_origin_y: 0,
+            scroll_y_reload_pending: false,
+            scroll_x: 0,
+            next_scroll_x: 0,
+            a12_rise_fetches: 0,
+            frame_in_progress: Frame::new(),
+            frame: Frame::new(),
         }
     }
 
+    /// Makes the PPU access CHR and get the mirroring through another mapper,
+    /// keeping the rest of its state
+    pub(crate) fn connect_mapper(&mut self, mapper: SharedMapper) {
+        self.mapper = mapper;
+    }
+
+    /// Soft reset (reset button). Registers go back to their reset state,
+    /// but VRAM, OAM and palette RAM keep their contents like on real hardware.
+    pub fn reset(&mut self) {
+        self.ctrl_register = ControlRegister::new();
+        self.mask_register = MaskRegister::new();
+        self.vram_addr_register = VramAddressRegister::new();
+        self.internal_data_buffer = 0;
+        self.scanline = 0;
+        self.cycles = 0;
+        self.nmi_interrupt = None;
+        self.vblank_suppressed = false;
+        self.scroll_origin_y = 0;
+        self.scroll_y_reload_pending = false;
+        self.scroll_x = 0;
+        self.next_scroll_x = 0;
+        self.a12_rise_fetches = 0;
+    }
+
+    /// Saves the registers, the PPU memory and the position in the frame, see `MachineState`.
+    /// The frame itself isn't saved, the next one is rendered from the loaded state.
+    pub(crate) fn save_state(&self, state: &mut StateWriter) {
+        state.write_bytes(&self.vram);
+        state.write_u8(self.ctrl_register.bits());
+        state.write_u8(self.mask_register.bits());
+        self.vram_addr_register.save_state(state);
+        state.write_u8(self.status_register.bits());
+        state.write_u8(self.oam_addr_register);
+        state.write_bytes(&self.oam_data_register);
+        state.write_bytes(&self.palette_table);
+        state.write_u8(self.internal_data_buffer);
+        state.write_u16(self.scanline);
+        state.write_u16(self.cycles as u16);
+        state.write_bool(self.nmi_interrupt.is_some());
+        state.write_bool(self.vblank_suppressed);
+        state.write_u8(self.last_bus_value);
+        state.write_u8(match self.mirroring_override {
+            None => 0,
+            Some(MirroringMode::Horizontal) => 1,
+            Some(MirroringMode::Vertical) => 2,
+            Some(MirroringMode::FourScreen) => 3,
+            Some(MirroringMode::SingleScreenLower) => 4,
+            Some(MirroringMode::SingleScreenUpper) => 5,
+        });
+        state.write_u16(self.scroll_origin_y);
+        state.write_bool(self.scroll_y_reload_pending);
+        state.write_u16(self.scroll_x);
+        state.write_u16(self.next_scroll_x);
+        state.write_u16(self.a12_rise_fetches);
+    }
+
+    /// Restores what `save_state` saved
+    pub(crate) fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        state.read_into(&mut self.vram)?;
+        self.ctrl_register.update(state.read_u8()?);
+        self.mask_register.update(state.read_u8()?);
+        self.vram_addr_register.load_state(state)?;
+        self.status_register = StatusRegister::from_bits_truncate(state.read_u8()?);
+        self.oam_addr_register = state.read_u8()?;
+        state.read_into(&mut self.oam_data_register)?;
+        state.read_into(&mut self.palette_table)?;
+        self.internal_data_buffer = state.read_u8()?;
+        self.scanline = state.read_u16()?;
+        self.cycles = state.read_u16()? as usize;
+        self.nmi_interrupt = if state.read_bool()? { Some(1) } else { None };
+        self.vblank_suppressed = state.read_bool()?;
+        self.last_bus_value = state.read_u8()?;
+        self.mirroring_override = match state.read_u8()? {
+            1 => Some(MirroringMode::Horizontal),
+            2 => Some(MirroringMode::Vertical),
+            3 => Some(MirroringMode::FourScreen),
+            4 => Some(MirroringMode::SingleScreenLower),
+            5 => Some(MirroringMode::SingleScreenUpper),
+            _ => None,
+        };
+        self.scroll_origin_y = state.read_u16()?;
+        self.scroll_y_reload_pending = state.read_bool()?;
+        self.scroll_x = state.read_u16()?;
+        self.next_scroll_x = state.read_u16()?;
+        self.a12_rise_fetches = state.read_u16()?;
+        Ok(())
+    }
+
+    /// Cold power-on. Same as a soft reset but the PPU memory is cleared as well.
+    pub fn power_on(&mut self) {
+        self.reset();
+        self.vram = [0; VRAM_SIZE];
+        self.status_register = StatusRegister::new();
+        self.oam_addr_register = 0;
+        self.oam_data_register = [0; 64 * 4];
+        self.palette_table = [0; 32];
+        self.scanline_masks = [MaskRegister::new(); VISIBLE_SCANLINES];
+        self.last_bus_value = 0;
+    }
+
+    /// Reads a palette entry (0-31), resolving the mirrors of the backdrop color
     pub fn read_palette_table_at(&self, index: usize) -> u8 {
-        self.palette_table[index]
+        self.palette_table[Self::mirror_palette_address(index as u16)]
     }
 
     pub fn read_vram_at(&self, index: usize) -> u8 {
         self.vram[index]
     }
 
-    pub fn chr_rom_slice(&self, from: usize, to: usize) -> &[u8] {
-        &self.chr_rom[from..=to]
+    /// Reads a byte from the logical nametable address space (0x2000-0x2FFF),
+    /// resolving the mirroring mode
+    pub fn read_nametable_at(&self, addr: u16) -> u8 {
+        self.vram[self.mirror_vram_address(addr) as usize]
+    }
+
+    /// Reads a byte from the pattern tables through the mapper
+    pub fn read_chr(&self, addr: u16) -> u8 {
+        self.mapper.borrow().ppu_read(addr)
     }
 
+    /// Advances the PPU by the given number of dots, one at a time.
+    /// Returns true if a frame was completed.
     pub fn tick(&mut self, cycles: u8) -> bool {
-        self.cycles += cycles as usize;
+        let mut frame_completed = false;
+        for _ in 0..cycles {
+            frame_completed |= self.step_dot();
+        }
+        frame_completed
+    }
+
+    /// Advances a single dot. `cycles` holds the dot of the current scanline (0-340).
+    fn step_dot(&mut self) -> bool {
+        self.cycles += 1;
+        let dot = self.cycles;
+        let scanline = self.scanline as usize;
+        let visible_scanline = scanline < VISIBLE_SCANLINES;
+        let pre_render_scanline = scanline == self.pre_render_scanline() as usize;
+
+        // Pixel x is output at dot x + 1
+        if visible_scanline && (1..=256).contains(&dot) {
+            self.check_sprite_zero_hit(scanline, dot - 1);
+        }
+        if visible_scanline && dot == 257 {
+            self.evaluate_sprite_overflow(scanline);
+        }
+        // The horizontal scroll bits of t are copied to v for the next scanline
+        if (visible_scanline || pre_render_scanline) && dot == 257 && self.rendering_enabled() {
+            self.next_scroll_x = self.scroll_x_from_t();
+        }
+        if dot == 257 {
+            self.a12_rise_fetches = if visible_scanline || pre_render_scanline {
+                self.find_a12_rise_fetches(scanline)
+            } else {
+                0
+            };
+        }
+        // The 8-dot fetches from dot 257 read the pattern table at their 4th dot
+        if (257..=328).contains(&dot) && (dot - 257) % 8 == 3 {
+            let fetch = (dot - 257) / 8;
+            if self.a12_rise_fetches & (1 << fetch) != 0 {
+                self.mapper.borrow_mut().notify_a12_rise();
+            }
+        }
+        // The vertical scroll bits of t are copied to v during dots 280-304
+        if pre_render_scanline && dot == 280 && self.rendering_enabled() {
+            self.scroll_origin_y = self.scroll_y_from_t();
+        }
+        // The vblank flag is set at dot 1, see `read_status_register` for why it matters
+        if scanline == VBLANK_SCANLINE as usize && dot == 1 {
+            self.start_vblank();
+        }
 
         if self.cycles >= 341 {
-            self.cycles = self.cycles - 341;
+            if visible_scanline {
+                self.scanline_masks[scanline] = self.mask_register;
+                let mut frame = mem::replace(&mut self.frame_in_progress, Frame::with_size(0, 0));
+                render::render_scanline(self, &mut frame, scanline as u16);
+                self.frame_in_progress = frame;
+            }
+            if self.scroll_y_reload_pending {
+                self.scroll_y_reload_pending = false;
+                // The next scanline shows the row in t, the pre-render one is followed by 0
+                let next_scanline = if visible_scanline {
+                    scanline as u16 + 1
+                } else {
+                    0
+                };
+                self.scroll_origin_y = (self.scroll_y_from_t() + 480 - next_scanline) % 480;
+                self.next_scroll_x = self.scroll_x_from_t();
+            }
+            self.scroll_x = self.next_scroll_x;
+            self.cycles -= 341;
             self.scanline += 1;
 
-            if self.scanline == 241 {
-                self.status_register.set_vblank_started_flag(true);
+            if self.scanline == self.pre_render_scanline() {
+                // Pre-render scanline: the flags are cleared at its first dot, not at frame wrap
+                self.status_register.set_sprite_overflow_flag(false);
                 self.status_register.set_sprite_zero_hit_flag(false);
-                if self.ctrl_register.has_vblank_nmi_flag() {
-                    self.nmi_interrupt = Some(1);
-                }
+                self.status_register.reset_vblank_status_flag();
             }
 
-            if self.scanline >= 262 {
+            if self.scanline >= self.tv_system.scanlines_per_frame() {
                 self.scanline = 0;
                 self.nmi_interrupt = None;
-                self.status_register.set_sprite_zero_hit_flag(false);
-                self.status_register.reset_vblank_status_flag();
+                mem::swap(&mut self.frame, &mut self.frame_in_progress);
                 return true;
             }
         }
-        return false;
+        false
+    }
+
+    fn start_vblank(&mut self) {
+        if self.vblank_suppressed {
+            self.vblank_suppressed = false;
+            return;
+        }
+        self.status_register.set_vblank_started_flag(true);
+        if self.ctrl_register.has_vblank_nmi_flag() {
+            self.nmi_interrupt = Some(1);
+        }
+    }
+
+    /// The last scanline of the frame, which prefetches the first visible one
+    fn pre_render_scanline(&self) -> u16 {
+        self.tv_system.scanlines_per_frame() - 1
+    }
+
+    /// Sets the sprite 0 hit flag if the opaque pixel of sprite 0 at the given x
+    /// overlaps an opaque background pixel on the given scanline
+    fn check_sprite_zero_hit(&mut self, scanline: usize, x: usize) {
+        if self.status_register.has_sprite_zero_hit()
+            || !self.mask_register.show_background()
+            || !self.mask_register.show_sprites()
+        {
+            return;
+        }
+
+        let sprite_y = self.oam_data_register[0] as usize;
+        let sprite_x = self.oam_data_register[3] as usize;
+        let sprite_height = self.ctrl_register.sprite_size() as usize;
+        if scanline < sprite_y || scanline >= sprite_y + sprite_height {
+            return;
+        }
+        // The hit never happens at x=255
+        if x < sprite_x || x >= (sprite_x + 8).min(255) {
+            return;
+        }
+
+        let left_column_hidden = !self.mask_register.is_leftmost_8_pixels_background()
+            || !self.mask_register.is_leftmost_8_pixels_sprites();
+        if x < 8 && left_column_hidden {
+            return;
+        }
+        if self.is_sprite_zero_pixel_opaque(x - sprite_x, scanline - sprite_y)
+            && render::is_background_pixel_opaque(self, x, scanline as u16)
+        {
+            self.status_register.set_sprite_zero_hit_flag(true);
+        }
+    }
+
+    /// Sets the sprite overflow flag when more than 8 sprites are on the given scanline.
+    /// The hardware's buggy evaluation (false positives and negatives) isn't emulated.
+    fn evaluate_sprite_overflow(&mut self, scanline: usize) {
+        if !self.rendering_enabled() {
+            return;
+        }
+        let sprite_height = self.ctrl_register.sprite_size() as usize;
+        let sprites_on_scanline = self
+            .oam_data_register
+            .chunks(4)
+            .filter(|sprite| {
+                let sprite_y = sprite[0] as usize;
+                scanline >= sprite_y && scanline < sprite_y + sprite_height
+            })
+            .count();
+        if sprites_on_scanline > 8 {
+            self.status_register.set_sprite_overflow_flag(true);
+        }
+    }
+
+    /// The fetches during which PPU A12 rises, as bits 0-7 for the sprite slots (dots 257-320)
+    /// and bit 8 for the next scanline's first tiles (dots 321-328). A12 is the pattern table
+    /// bit of each fetch: it stays at the background table's level during dots 1-256, then
+    /// follows the table of each sprite slot, then goes back to the background one.
+    /// The MMC3 ignores rises after A12 has been low for a single sprite slot, so they're
+    /// left out as well.
+    fn find_a12_rise_fetches(&self, scanline: usize) -> u16 {
+        if !self.rendering_enabled() {
+            return 0;
+        }
+        let background_high = self.ctrl_register.background_pattern_address() == 0x1000;
+        let mut fetches_high = [background_high; 9];
+        fetches_high[..8].copy_from_slice(&self.sprite_slot_a12_levels(scanline));
+
+        let mut low_dots = if background_high { 0 } else { 256 };
+        let mut rises = 0;
+        for (fetch, &high) in fetches_high.iter().enumerate() {
+            if !high {
+                low_dots += 8;
+                continue;
+            }
+            if low_dots > 8 {
+                rises |= 1 << fetch;
+            }
+            low_dots = 0;
+        }
+        rises
+    }
+
+    /// The A12 level of the pattern fetches of each sprite slot during the given scanline,
+    /// which loads the sprites of the next one. 8x8 sprites all use the table selected in the
+    /// control register, while 8x16 sprites pick theirs with bit 0 of the tile index. Unused
+    /// slots fetch tile 0xFF, which is in the high table for 8x16 sprites.
+    fn sprite_slot_a12_levels(&self, scanline: usize) -> [bool; 8] {
+        if self.ctrl_register.sprite_size() == 8 {
+            return [self.ctrl_register.sprite_pattern_address() == 0x1000; 8];
+        }
+        let mut levels = [true; 8];
+        // Sprite evaluation doesn't happen on the pre-render scanline, so every slot is unused
+        if scanline >= VISIBLE_SCANLINES {
+            return levels;
+        }
+        // Same in-range check as the sprite evaluation, sprites show up one scanline lower
+        let sprites_in_range = self.oam_data_register.chunks(4).filter(|sprite| {
+            let sprite_y = sprite[0] as usize;
+            scanline >= sprite_y && scanline < sprite_y + 16
+        });
+        for (level, sprite) in levels.iter_mut().zip(sprites_in_range) {
+            *level = sprite[1] & 1 == 1;
+        }
+        levels
+    }
+
+    fn is_sprite_zero_pixel_opaque(&self, column: usize, row: usize) -> bool {
+        let sprite = self.sprite(0);
+        let column = if sprite.flip_h { 7 - column } else { column };
+        let addr = self.sprite_pattern_row_address(&sprite, row);
+        self.is_pattern_pixel_opaque(addr, column)
+    }
+
+    // A pixel is transparent when both bit planes of the tile row are 0
+    fn is_pattern_pixel_opaque(&self, row_addr: u16, column: usize) -> bool {
+        let bit = 7 - column;
+        (self.read_chr(row_addr) | self.read_chr(row_addr + 8)) >> bit & 1 == 1
     }
 
     pub fn poll_nmi_interrupt(&mut self) -> Option<u8> {
@@ -94,54 +458,68 @@ impl Ppu {
     }
 
     pub fn read_data_register(&mut self) -> u8 {
-        let addr = self.addr_register.get_address();
+        let addr = self.vram_addr_register.get_address();
         self.increment_vram_address();
 
-        match addr {
+        let result = match addr {
             0x0000..=0x1FFF => {
                 let result = self.internal_data_buffer;
-                self.internal_data_buffer = self.chr_rom[addr as usize];
+                self.internal_data_buffer = self.read_chr(addr);
                 result
             }
-            0x2000..=0x2FFF => {
+            0x2000..=0x3EFF => {
+                // 0x3000..0x3EFF mirrors 0x2000..0x2EFF, mirror_vram_address takes care of it
                 let result = self.internal_data_buffer;
                 self.internal_data_buffer = self.vram[self.mirror_vram_address(addr) as usize];
                 result
             }
-            0x3F10 | 0x3F14 | 0x3F18 | 0x3F1C => {
-                // Addresses $3F10/$3F14/$3F18/$3F1C are mirrors of $3F00/$3F04/$3F08/$3F0C
-                let mirrored_addr = addr - 0x10;
-                self.palette_table[(mirrored_addr - 0x3f00) as usize]
+            0x3F00..=0x3FFF => {
+                // Palette reads are not buffered, but the buffer still gets refreshed
+                // with the nametable byte "underneath" the palette address
+                self.internal_data_buffer =
+                    self.vram[self.mirror_vram_address(addr - 0x1000) as usize];
+                self.palette_table[Self::mirror_palette_address(addr)]
             }
-            0x3000..=0x3EFF => panic!(
-                "Address space 0x3000..0x3EFF is not expected to be used, requested = {}",
-                addr
-            ),
-            0x3F00..=0x3FFF => self.palette_table[(addr - 0x3F00) as usize],
             _ => panic!("Unexpected access to mirrored memory address {}", addr),
+        };
+        self.last_bus_value = result;
+        result
+    }
+
+    /// Resolves the full 32-entry palette RAM through the given color palette,
+    /// e.g. to draw the active palettes in a palette viewer.
+    pub fn palette_rgb(&self, palette: &[(u8, u8, u8); 64]) -> [(u8, u8, u8); 32] {
+        let mut result = [(0, 0, 0); 32];
+        for (addr, rgb) in result.iter_mut().enumerate() {
+            let color_index = self.palette_table[Self::mirror_palette_address(addr as u16)];
+            *rgb = palette[(color_index & 0x3F) as usize];
+        }
+        result
+    }
+
+    /// Maps a palette address (0x3F00-0x3FFF, or an index 0-31) into the palette table.
+    /// Shared by the data register and the renderer so they agree on the mirrors.
+    fn mirror_palette_address(addr: u16) -> usize {
+        // 0x3F20-0x3FFF repeat 0x3F00-0x3F1F
+        match addr & 0x1F {
+            // Addresses $3F10/$3F14/$3F18/$3F1C are mirrors of $3F00/$3F04/$3F08/$3F0C
+            mirror @ (0x10 | 0x14 | 0x18 | 0x1C) => (mirror - 0x10) as usize,
+            palette_addr => palette_addr as usize,
         }
     }
 
     pub fn write_to_data_register(&mut self, data: u8) {
-        let addr = self.addr_register.get_address();
+        self.last_bus_value = data;
+        let addr = self.vram_addr_register.get_address();
 
         match addr {
             0x0000..=0x1FFF => {
-                println!("Attempt to write to chr ROM address {}", addr);
+                self.mapper.borrow_mut().ppu_write(addr, data);
             }
-            0x2000..=0x2FFF => {
+            0x2000..=0x3EFF => {
                 self.vram[self.mirror_vram_address(addr) as usize] = data;
             }
-            0x3000..=0x3EFF => unimplemented!(
-                "Address space 0x3000..0x3EFF is not expected to be used, requested = {}",
-                addr
-            ),
-            0x3F10 | 0x3F14 | 0x3F18 | 0x3F1C => {
-                // Addresses $3F10/$3F14/$3F18/$3F1C are mirrors of $3F00/$3F04/$3F08/$3F0C
-                let mirrored_addr = addr - 0x10;
-                self.palette_table[(mirrored_addr - 0x3F00) as usize] = data;
-            }
-            0x3F00..=0x3FFF => self.palette_table[(addr - 0x3F00) as usize] = data,
+            0x3F00..=0x3FFF => self.palette_table[Self::mirror_palette_address(addr)] = data,
             _ => panic!("Unexpected access to mirrored memory address {}", addr),
         }
 
@@ -149,16 +527,27 @@ impl Ppu {
     }
 
     pub fn write_to_address_register(&mut self, value: u8) {
-        self.addr_register.update(value);
+        self.last_bus_value = value;
+        let v_updated = self.vram_addr_register.write_address(value);
+        let scanline = self.scanline as usize;
+        let rendering_scanline =
+            scanline < VISIBLE_SCANLINES || scanline == self.pre_render_scanline() as usize;
+        if v_updated && rendering_scanline && self.rendering_enabled() {
+            // Games change the vertical scroll mid-frame this way, 0x2005 only writes t
+            self.scroll_y_reload_pending = true;
+        }
     }
 
     pub fn write_to_control_register(&mut self, value: u8) {
+        self.last_bus_value = value;
         let prev_nmi_flag = self.ctrl_register.has_vblank_nmi_flag();
         self.ctrl_register.update(value);
-        if !prev_nmi_flag
-            && self.ctrl_register.has_vblank_nmi_flag()
-            && self.status_register.has_vblank_started()
-        {
+        self.vram_addr_register.write_nametable_select(value);
+        if !self.ctrl_register.has_vblank_nmi_flag() {
+            // Disabling NMIs cancels one the CPU hasn't taken yet
+            self.nmi_interrupt = None;
+        } else if !prev_nmi_flag && self.status_register.has_vblank_started() {
+            // Enabling them during vblank generates one right away, every time
             self.nmi_interrupt = Some(1);
         }
     }
@@ -171,33 +560,236 @@ impl Ppu {
         self.ctrl_register.sprite_pattern_address()
     }
 
+    /// 8 or 16 pixels
+    pub fn control_register_sprite_size(&self) -> u8 {
+        self.ctrl_register.sprite_size()
+    }
+
+    /// Address of the low bit plane of a row (0-7, or 0-15 for 8x16 sprites) of the sprite,
+    /// counted from its top and taking vertical flipping into account. 8x16 sprites take
+    /// their pattern table from bit 0 of the tile index, and the bottom half from the next tile.
+    pub fn sprite_pattern_row_address(&self, sprite: &Sprite, row: usize) -> u16 {
+        let height = self.ctrl_register.sprite_size() as usize;
+        let row = if sprite.flip_v { height - 1 - row } else { row };
+        let tile_addr = if height == 16 {
+            let bank = (sprite.tile_index as u16 & 1) * 0x1000;
+            let tile = (sprite.tile_index & 0xFE) as u16 + (row / 8) as u16;
+            bank + tile * 16
+        } else {
+            self.ctrl_register.sprite_pattern_address() + sprite.tile_index as u16 * 16
+        };
+        tile_addr + (row % 8) as u16
+    }
+
+    pub fn control_register_bits(&self) -> u8 {
+        self.ctrl_register.bits()
+    }
+
+    pub fn mask_register_bits(&self) -> u8 {
+        self.mask_register.bits()
+    }
+
+    /// Set when the mask register turns the picture greyscale
+    pub fn is_greyscale(&self) -> bool {
+        self.mask_register.is_grayscale()
+    }
+
+    /// The colors the mask register emphasizes, as BGR bits (0-7) like `palette::emphasize` takes
+    pub fn color_emphasis(&self) -> u8 {
+        self.mask_register
+            .emphasize()
+            .iter()
+            .fold(0, |bits, color| match color {
+                Color::Red => bits | 0b001,
+                Color::Green => bits | 0b010,
+                Color::Blue => bits | 0b100,
+            })
+    }
+
+    pub fn status_register_bits(&self) -> u8 {
+        self.status_register.snapshot()
+    }
+
     pub fn write_to_mask_register(&mut self, value: u8) {
+        self.last_bus_value = value;
         self.mask_register.update(value);
     }
 
+    /// Rendering is enabled as soon as either the background or the sprites are shown
+    pub fn rendering_enabled(&self) -> bool {
+        self.mask_register.show_background() || self.mask_register.show_sprites()
+    }
+
+    /// The (x, y) scroll written through 0x2005, e.g. for a debugger overlay
+    pub fn current_scroll(&self) -> (u8, u8) {
+        (
+            self.vram_addr_register.scroll_x(),
+            self.vram_addr_register.scroll_y(),
+        )
+    }
+
+    /// The nametable (0-3) the scroll is relative to, selected through 0x2000
+    pub fn current_nametable(&self) -> u8 {
+        self.vram_addr_register.nametable_select()
+    }
+
+    /// The row (0-479) of the 512x480 area of the four nametables shown on the given visible
+    /// scanline. Like on hardware, the vertical scroll only takes effect at the pre-render
+    /// scanline, or at the scanline after a 0x2006 write during rendering.
+    pub fn scanline_scroll_y(&self, scanline: u16) -> u16 {
+        (self.scroll_origin_y + scanline) % 480
+    }
+
+    /// The column (0-511) of the 512x480 area of the four nametables shown at the left edge
+    /// of the current scanline. Like on hardware, the horizontal scroll is copied at dot 257
+    /// of the scanline before, so 0x2005 writes show up from the next scanline.
+    pub fn scanline_scroll_x(&self) -> u16 {
+        self.scroll_x
+    }
+
+    // Column of the four nametables that t points at
+    fn scroll_x_from_t(&self) -> u16 {
+        let nametable = self.vram_addr_register.nametable_select() as u16;
+        (nametable & 1) * 256 + self.vram_addr_register.scroll_x() as u16
+    }
+
+    // Row of the four nametables that t points at
+    fn scroll_y_from_t(&self) -> u16 {
+        let nametable = self.vram_addr_register.nametable_select() as u16;
+        ((nametable >> 1) * 240 + self.vram_addr_register.scroll_y() as u16) % 480
+    }
+
+    /// The last completed frame. It is rendered one scanline at a time as the PPU reaches
+    /// the end of each of them, so mid-frame scroll, palette and mask changes show up.
+    pub fn frame(&self) -> &Frame {
+        &self.frame
+    }
+
+    pub fn tv_system(&self) -> TvSystem {
+        self.tv_system
+    }
+
+    /// The nametable mirroring in effect, which bank-switching mappers can change at any time
+    pub fn mirroring(&self) -> MirroringMode {
+        self.mirroring_override
+            .unwrap_or_else(|| self.mapper.borrow().mirroring())
+    }
+
+    /// Forces the nametable mirroring, ignoring the mapper's from then on. Mappers that
+    /// switch mirroring don't need it, the PPU asks them on every nametable access, but
+    /// a PPU without a real cart behind it (e.g. in tools or tests) can be switched this way.
+    pub fn set_mirroring(&mut self, mode: MirroringMode) {
+        self.mirroring_override = Some(mode);
+    }
+
+    pub fn scanline(&self) -> u16 {
+        self.scanline
+    }
+
+    /// Whether the background was shown on the given visible scanline of the last frame.
+    /// Games can toggle the mask register mid-frame, so it has to be checked per scanline.
+    pub fn scanline_shows_background(&self, scanline: usize) -> bool {
+        self.scanline_masks[scanline].show_background()
+    }
+
+    /// Whether the sprites were shown on the given visible scanline of the last frame.
+    pub fn scanline_shows_sprites(&self, scanline: usize) -> bool {
+        self.scanline_masks[scanline].show_sprites()
+    }
+
+    /// Whether the background was shown in the leftmost 8 pixels of the given visible scanline.
+    pub fn scanline_shows_leftmost_background(&self, scanline: usize) -> bool {
+        self.scanline_masks[scanline].is_leftmost_8_pixels_background()
+    }
+
+    /// Whether the sprites were shown in the leftmost 8 pixels of the given visible scanline.
+    pub fn scanline_shows_leftmost_sprites(&self, scanline: usize) -> bool {
+        self.scanline_masks[scanline].is_leftmost_8_pixels_sprites()
+    }
+
     pub fn write_to_scroll_register(&mut self, value: u8) {
-        self.scroll_register.write(value);
+        self.last_bus_value = value;
+        self.vram_addr_register.write_scroll(value);
     }
 
+    /// Reading the status register races with the start of vblank (ppu_vbl_nmi):
+    /// - one dot before the flag is set, it reads clear and neither the flag nor the NMI
+    ///   happen for that frame
+    /// - on the dot it is set or the next one, it reads set but the NMI is cancelled
     pub fn read_status_register(&mut self) -> u8 {
-        let stat_reg_snapshot = self.status_register.snapshot();
+        if self.scanline == VBLANK_SCANLINE {
+            match self.cycles {
+                0 => self.vblank_suppressed = true,
+                1 | 2 => self.nmi_interrupt = None,
+                _ => {}
+            }
+        }
+        let stat_reg_snapshot = self.status_register_value();
         self.status_register.reset_vblank_status_flag();
-        self.addr_register.reset_latch();
-        self.scroll_register.reset_latch();
+        self.vram_addr_register.reset_latch();
+        self.last_bus_value = stat_reg_snapshot;
         stat_reg_snapshot
     }
 
     pub fn write_to_oam_address_register(&mut self, value: u8) {
+        self.last_bus_value = value;
         self.oam_addr_register = value;
     }
 
     pub fn write_to_oam_data_register(&mut self, value: u8) {
+        self.last_bus_value = value;
         self.oam_data_register[self.oam_addr_register as usize] = value;
         self.oam_addr_register = self.oam_addr_register.wrapping_add(1);
     }
 
-    pub fn read_oam_data_register(&self) -> u8 {
-        self.oam_data_register[self.oam_addr_register as usize]
+    pub fn read_oam_data_register(&mut self) -> u8 {
+        let value = self.oam_data_register_value();
+        self.last_bus_value = value;
+        value
+    }
+
+    /// The value of the register at the given address (0x2000-0x2007), without the side
+    /// effects of reading it. Write-only registers give the last value written to them,
+    /// except for the scroll and address registers that read as 0.
+    pub fn peek_register(&self, addr: u16) -> u8 {
+        match addr {
+            0x2000 => self.ctrl_register.bits(),
+            0x2001 => self.mask_register.bits(),
+            0x2002 => self.status_register_value(),
+            0x2003 => self.oam_addr_register,
+            0x2004 => self.oam_data_register_value(),
+            0x2007 => {
+                // What a read would return: palette reads aren't buffered
+                let addr = self.vram_addr_register.get_address();
+                if addr >= 0x3F00 {
+                    self.palette_table[Self::mirror_palette_address(addr)]
+                } else {
+                    self.internal_data_buffer
+                }
+            }
+            _ => 0,
+        }
+    }
+
+    /// The value left on the PPU data bus by the last register access, which is what
+    /// reading a write-only register returns.
+    pub fn open_bus(&self) -> u8 {
+        self.last_bus_value
+    }
+
+    fn status_register_value(&self) -> u8 {
+        // Only the top 3 bits are driven, the low 5 bits are open bus
+        (self.status_register.snapshot() & 0b1110_0000) | (self.last_bus_value & 0b0001_1111)
+    }
+
+    fn oam_data_register_value(&self) -> u8 {
+        let value = self.oam_data_register[self.oam_addr_register as usize];
+        // Bits 2-4 of the sprite attributes byte don't exist, they read back as 0
+        if self.oam_addr_register % 4 == 2 {
+            value & 0b1110_0011
+        } else {
+            value
+        }
     }
 
     pub fn write_to_oam_dma_register(&mut self, data: &[u8; 256]) {
@@ -213,8 +805,37 @@ impl Ppu {
         self.oam_data_register[index]
     }
 
+    /// OAM indices of the sprites on the given scanline, in OAM order and before the
+    /// 8 sprites per scanline limit drops any of them, e.g. to debug flicker
+    pub fn sprites_on_scanline(&self, scanline: u16) -> Vec<u8> {
+        let sprite_height = self.ctrl_register.sprite_size() as u16;
+        self.oam_data_register
+            .chunks(4)
+            .enumerate()
+            .filter(|(_, sprite)| {
+                let sprite_y = sprite[0] as u16;
+                scanline >= sprite_y && scanline < sprite_y + sprite_height
+            })
+            .map(|(index, _)| index as u8)
+            .collect()
+    }
+
+    /// The 64 OAM entries decoded, in OAM order (sprite 0 first)
+    pub fn sprites(&self) -> Vec<Sprite> {
+        (0..self.oam_data_register.len() / 4)
+            .map(|i| self.sprite(i))
+            .collect()
+    }
+
+    /// The OAM entry (0-63) decoded, for callers that would rather not allocate `sprites`
+    pub(crate) fn sprite(&self, index: usize) -> Sprite {
+        let mut entry = [0; 4];
+        entry.copy_from_slice(&self.oam_data_register[index * 4..index * 4 + 4]);
+        Sprite::from_oam_entry(entry)
+    }
+
     fn increment_vram_address(&mut self) {
-        self.addr_register
+        self.vram_addr_register
             .increment(self.ctrl_register.vram_address_increment());
     }
 
@@ -223,12 +844,16 @@ impl Ppu {
         let mirrored_vram = addr & 0b0010111111111111;
         let vram_index = mirrored_vram - 0x2000;
         let name_table = vram_index / 0x0400;
-        match (&self.mirroring_mode, name_table) {
+        match (self.mirroring(), name_table) {
             (MirroringMode::Horizontal, 2) | (MirroringMode::Horizontal, 1) => vram_index - 0x0400,
             (MirroringMode::Vertical, 2)
             | (MirroringMode::Vertical, 3)
             | (MirroringMode::Horizontal, 3) => vram_index - 0x0800,
-            _ => vram_index,
+            (MirroringMode::Horizontal, _) | (MirroringMode::Vertical, _) => vram_index,
+            // Every nametable has its own memory, using the extra VRAM on the cart
+            (MirroringMode::FourScreen, _) => vram_index,
+            (MirroringMode::SingleScreenLower, _) => vram_index % 0x0400,
+            (MirroringMode::SingleScreenUpper, _) => vram_index % 0x0400 + 0x0400,
         }
     }
 }
@@ -236,6 +861,10 @@ impl Ppu {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::nes::mapper::{Mapper, Mapper7};
+    use crate::nes::render::palette::SYSTEM_PALETTE;
+    use std::cell::Cell;
+    use std::rc::Rc;
 
     impl Ppu {
         fn new_with_empty_rom_hor() -> Self {
@@ -266,7 +895,7 @@ mod tests {
         ppu.write_to_address_register(0x23);
         ppu.write_to_address_register(0x05);
         ppu.read_data_register(); // get data into buffer
-        assert_eq!(ppu.addr_register.get_address(), 0x2306);
+        assert_eq!(ppu.vram_addr_register.get_address(), 0x2306);
         assert_eq!(ppu.read_data_register(), 0x66);
     }
 
@@ -302,6 +931,134 @@ mod tests {
         assert_eq!(ppu.read_data_register(), 0xCD);
     }
 
+    #[test]
+    fn test_ppu_vram_increment_into_nametable_mirrors() {
+        let mut ppu = Ppu::new_with_empty_rom_hor();
+        ppu.write_to_control_register(0b0100); // addr increments of 32
+        ppu.vram[0x07E0] = 0xAB; // 0x2FE0
+        ppu.vram[0x0000] = 0xCD; // 0x3000 mirrors 0x2000
+        ppu.vram[0x0020] = 0xEF; // 0x3020 mirrors 0x2020
+
+        ppu.write_to_address_register(0x2F);
+        ppu.write_to_address_register(0xE0);
+
+        ppu.read_data_register(); // get data into buffer
+        assert_eq!(ppu.read_data_register(), 0xAB);
+        assert_eq!(ppu.read_data_register(), 0xCD);
+        assert_eq!(ppu.vram_addr_register.get_address(), 0x3040);
+        assert_eq!(ppu.read_data_register(), 0xEF);
+    }
+
+    #[test]
+    fn test_ppu_vram_increment_into_palette() {
+        let mut ppu = Ppu::new_with_empty_rom_hor();
+        ppu.write_to_control_register(0b0100); // addr increments of 32
+        ppu.vram[0x06E0] = 0xAB; // 0x3EE0 mirrors 0x2EE0
+        ppu.vram[0x0700] = 0xCD; // 0x3F00 sits on top of 0x2F00
+        ppu.palette_table[0x00] = 0x12;
+
+        ppu.write_to_address_register(0x3E);
+        ppu.write_to_address_register(0xE0);
+
+        ppu.read_data_register(); // get data into buffer
+        assert_eq!(ppu.internal_data_buffer, 0xAB);
+        assert_eq!(ppu.vram_addr_register.get_address(), 0x3F00);
+
+        assert_eq!(ppu.read_data_register(), 0x12); // palette is read immediately
+        assert_eq!(ppu.internal_data_buffer, 0xCD);
+        assert_eq!(ppu.vram_addr_register.get_address(), 0x3F20);
+
+        assert_eq!(ppu.read_data_register(), 0x12); // 0x3F20 mirrors 0x3F00
+    }
+
+    #[test]
+    fn test_ppu_read_chr() {
+        let mut chr_rom = vec![0; 0x2000];
+        chr_rom[0x0000] = 0xAB;
+        chr_rom[0x1FFF] = 0xCD;
+        let ppu = Ppu::new(chr_rom, MirroringMode::Horizontal);
+
+        assert_eq!(ppu.read_chr(0x0000), 0xAB);
+        assert_eq!(ppu.read_chr(0x0001), 0x00);
+        assert_eq!(ppu.read_chr(0x1FFF), 0xCD);
+    }
+
+    #[test]
+    fn test_ppu_data_read_buffer_chr() {
+        let mut chr_rom = vec![0; 0x2000];
+        chr_rom[0x0000] = 0xAB;
+        chr_rom[0x0001] = 0xCD;
+        let mut ppu = Ppu::new(chr_rom, MirroringMode::Horizontal);
+
+        ppu.write_to_address_register(0x00);
+        ppu.write_to_address_register(0x00);
+
+        assert_eq!(ppu.read_data_register(), 0x00); // stale buffer
+        assert_eq!(ppu.read_data_register(), 0xAB);
+        assert_eq!(ppu.read_data_register(), 0xCD);
+    }
+
+    #[test]
+    fn test_ppu_chr_ram_write_and_read_back() {
+        let mut ppu = Ppu::new(Vec::new(), MirroringMode::Horizontal); // no CHR-ROM banks
+        let tile = [0x18, 0x3C, 0x7E, 0xFF, 0xFF, 0x7E, 0x3C, 0x18];
+
+        ppu.write_to_address_register(0x1F);
+        ppu.write_to_address_register(0xF0);
+        for byte in tile.iter() {
+            ppu.write_to_data_register(*byte);
+        }
+
+        ppu.write_to_address_register(0x1F);
+        ppu.write_to_address_register(0xF0);
+        ppu.read_data_register(); // stale buffer
+        for byte in tile.iter() {
+            assert_eq!(ppu.read_data_register(), *byte);
+        }
+    }
+
+    #[test]
+    fn test_ppu_data_read_buffer_chr_to_nametable() {
+        let mut chr_rom = vec![0; 0x2000];
+        chr_rom[0x1FFE] = 0xAB;
+        chr_rom[0x1FFF] = 0xCD;
+        let mut ppu = Ppu::new(chr_rom, MirroringMode::Horizontal);
+        ppu.vram[0x0000] = 0xEF;
+        ppu.vram[0x0001] = 0x12;
+
+        ppu.write_to_address_register(0x1F);
+        ppu.write_to_address_register(0xFE);
+
+        assert_eq!(ppu.read_data_register(), 0x00); // stale buffer
+        assert_eq!(ppu.read_data_register(), 0xAB);
+        assert_eq!(ppu.read_data_register(), 0xCD);
+        assert_eq!(ppu.read_data_register(), 0xEF);
+        assert_eq!(ppu.read_data_register(), 0x12);
+    }
+
+    #[test]
+    fn test_ppu_data_read_buffer_after_address_change() {
+        let mut chr_rom = vec![0; 0x2000];
+        chr_rom[0x0010] = 0xAB;
+        let mut ppu = Ppu::new(chr_rom, MirroringMode::Horizontal);
+        ppu.vram[0x0305] = 0xCD;
+
+        ppu.write_to_address_register(0x00);
+        ppu.write_to_address_register(0x10);
+        ppu.read_data_register(); // get data into buffer
+
+        // The first read after setting a new address still returns the previous buffer
+        ppu.write_to_address_register(0x23);
+        ppu.write_to_address_register(0x05);
+        assert_eq!(ppu.read_data_register(), 0xAB);
+        assert_eq!(ppu.read_data_register(), 0xCD);
+
+        ppu.write_to_address_register(0x00);
+        ppu.write_to_address_register(0x10);
+        assert_eq!(ppu.read_data_register(), 0x00); // 0x2306 was buffered
+        assert_eq!(ppu.read_data_register(), 0xAB);
+    }
+
     // Horizontal: https://wiki.nesdev.com/w/index.php/Mirroring
     //   [0x2000 A ] [0x2400 a ]
     //   [0x2800 B ] [0x2C00 b ]
@@ -352,6 +1109,64 @@ mod tests {
         assert_eq!(ppu.read_data_register(), 0xCD);
     }
 
+    // Four-screen:
+    //   [0x2000 A ] [0x2400 B ]
+    //   [0x2800 C ] [0x2C00 D ]
+    #[test]
+    fn test_vram_four_screen() {
+        let mut ppu = Ppu::new(vec![0; 2048], MirroringMode::FourScreen);
+        for (i, hi) in [0x20, 0x24, 0x28, 0x2C].iter().enumerate() {
+            ppu.write_to_address_register(*hi);
+            ppu.write_to_address_register(0x05);
+            ppu.write_to_data_register(0xA0 + i as u8);
+        }
+
+        for (i, hi) in [0x20, 0x24, 0x28, 0x2C].iter().enumerate() {
+            ppu.write_to_address_register(*hi);
+            ppu.write_to_address_register(0x05);
+            ppu.read_data_register(); // get data into buffer
+            assert_eq!(ppu.read_data_register(), 0xA0 + i as u8);
+        }
+    }
+
+    // Single-screen (mapper 7 selects the screen):
+    //   [0x2000 A ] [0x2400 a ]
+    //   [0x2800 a ] [0x2C00 a ]
+    #[test]
+    fn test_vram_single_screen_switched_by_mapper() {
+        let mapper = mapper::share(Box::new(Mapper7::new(vec![0; 0x8000], Vec::new())));
+        let mut ppu = Ppu::with_mapper(mapper.clone(), TvSystem::Ntsc);
+
+        mapper.borrow_mut().cpu_write(0x8000, 0b0001_0000); // upper screen
+        ppu.write_to_address_register(0x2C);
+        ppu.write_to_address_register(0x05);
+        ppu.write_to_data_register(0xAB);
+        assert_eq!(ppu.vram[0x0405], 0xAB);
+        assert_eq!(ppu.vram[0x0005], 0x00);
+        for hi in [0x20, 0x24, 0x28] {
+            assert_eq!(ppu.read_nametable_at((hi as u16) << 8 | 0x05), 0xAB);
+        }
+
+        mapper.borrow_mut().cpu_write(0x8000, 0); // lower screen
+        ppu.write_to_address_register(0x24);
+        ppu.write_to_address_register(0x05);
+        ppu.write_to_data_register(0xCD);
+        assert_eq!(ppu.vram[0x0005], 0xCD);
+        assert_eq!(ppu.read_nametable_at(0x2005), 0xCD);
+    }
+
+    #[test]
+    fn test_ppu_set_mirroring() {
+        let mut ppu = Ppu::new_with_empty_rom_hor();
+        assert_eq!(ppu.mirror_vram_address(0x2405), 0x0005);
+        assert_eq!(ppu.mirror_vram_address(0x2805), 0x0405);
+
+        ppu.set_mirroring(MirroringMode::Vertical);
+        assert_eq!(ppu.mirroring(), MirroringMode::Vertical);
+        assert_eq!(ppu.mirror_vram_address(0x2405), 0x0405);
+        assert_eq!(ppu.mirror_vram_address(0x2805), 0x0005);
+    }
+
     #[test]
     fn test_ppu_status_register_reset_latch() {
         let mut ppu = Ppu::new_with_empty_rom_hor();
@@ -384,6 +1199,29 @@ mod tests {
         assert_eq!(ppu.status_register.snapshot() >> 7, 0);
     }
 
+    #[test]
+    fn test_ppu_current_scroll() {
+        let mut ppu = Ppu::new_with_empty_rom_hor();
+        ppu.write_to_scroll_register(0x12);
+        ppu.write_to_scroll_register(0xE7);
+        assert_eq!(ppu.current_scroll(), (0x12, 0xE7));
+
+        // The control register's nametable bits don't change it
+        ppu.write_to_control_register(0b11);
+        assert_eq!(ppu.current_scroll(), (0x12, 0xE7));
+    }
+
+    #[test]
+    fn test_ppu_status_low_bits_are_open_bus() {
+        let mut ppu = Ppu::new_with_empty_rom_hor();
+        ppu.status_register.set_vblank_started_flag(true);
+        ppu.write_to_scroll_register(0b0101_0101);
+
+        assert_eq!(ppu.read_status_register(), 0b1001_0101);
+        // The status read itself is now the last value on the bus
+        assert_eq!(ppu.read_status_register(), 0b0001_0101);
+    }
+
     #[test]
     fn test_ppu_oam_data_register_read_write() {
         let mut ppu = Ppu::new_with_empty_rom_hor();
@@ -397,6 +1235,39 @@ mod tests {
         assert_eq!(ppu.read_oam_data_register(), 0xCD);
     }
 
+    #[test]
+    fn test_ppu_sprites() {
+        let mut ppu = Ppu::new_with_empty_rom_hor();
+        let mut oam = [0; 256];
+        oam[4..8].copy_from_slice(&[0x20, 0x05, 0b0110_0001, 0x30]);
+        ppu.write_to_oam_dma_register(&oam);
+
+        let sprites = ppu.sprites();
+        assert_eq!(sprites.len(), 64);
+        assert_eq!(sprites[0], Sprite::from_oam_entry([0; 4]));
+        let sprite = sprites[1];
+        assert_eq!((sprite.x, sprite.y, sprite.tile_index), (0x30, 0x20, 0x05));
+        assert!(sprite.flip_h && !sprite.flip_v && sprite.priority);
+        assert_eq!(sprite.palette, 1);
+    }
+
+    #[test]
+    fn test_ppu_oam_attributes_unimplemented_bits_read_as_zero() {
+        let mut ppu = Ppu::new_with_empty_rom_hor();
+        ppu.write_to_oam_address_register(0x10);
+        ppu.write_to_oam_data_register(0xFF);
+        ppu.write_to_oam_data_register(0xFF);
+        ppu.write_to_oam_data_register(0xFF);
+        ppu.write_to_oam_data_register(0xFF);
+
+        ppu.write_to_oam_address_register(0x12);
+        assert_eq!(ppu.read_oam_data_register(), 0xE3);
+        for addr in [0x10, 0x11, 0x13].iter() {
+            ppu.write_to_oam_address_register(*addr);
+            assert_eq!(ppu.read_oam_data_register(), 0xFF);
+        }
+    }
+
     #[test]
     fn test_ppu_oam_dma_register() {
         let mut ppu = Ppu::new_with_empty_rom_hor();
@@ -415,6 +1286,25 @@ mod tests {
         assert_eq!(ppu.read_oam_data_register(), 0xAB);
     }
 
+    #[test]
+    fn test_ppu_sprites_on_scanline() {
+        let mut ppu = Ppu::new_with_empty_rom_hor();
+        let mut data = [0xFF; 256];
+        data[0] = 10;
+        data[3 * 4] = 15;
+        data[5 * 4] = 20;
+        ppu.write_to_oam_dma_register(&data);
+
+        assert_eq!(ppu.sprites_on_scanline(17), vec![0, 3]);
+        assert_eq!(ppu.sprites_on_scanline(22), vec![3, 5]);
+        assert_eq!(ppu.sprites_on_scanline(30), vec![]);
+
+        // 8x16 sprites
+        ppu.write_to_control_register(0b00100000);
+        assert_eq!(ppu.sprites_on_scanline(22), vec![0, 3, 5]);
+        assert_eq!(ppu.sprites_on_scanline(30), vec![3, 5]);
+    }
+
     #[test]
     fn test_ppu_write_to_ctrl_register_gen_interrupt() {
         let mut ppu = Ppu::new_with_empty_rom_hor();
@@ -423,6 +1313,119 @@ mod tests {
         assert_eq!(ppu.nmi_interrupt, Some(1));
     }
 
+    #[test]
+    fn test_ppu_soft_reset_keeps_memory() {
+        let mut ppu = Ppu::new_with_empty_rom_hor();
+        ppu.write_to_address_register(0x3F);
+        ppu.write_to_address_register(0x01);
+        ppu.write_to_data_register(0x2A);
+        ppu.write_to_data_register(0x16);
+        ppu.vram[0x0010] = 0xAB;
+        ppu.write_to_oam_data_register(0xCD);
+        ppu.write_to_control_register(0b10000000);
+
+        ppu.reset();
+        assert_eq!(ppu.read_palette_table_at(1), 0x2A);
+        assert_eq!(ppu.read_palette_table_at(2), 0x16);
+        assert_eq!(ppu.read_vram_at(0x0010), 0xAB);
+        assert_eq!(ppu.read_oam_data_at(0), 0xCD);
+        assert_eq!(ppu.control_register_bits(), 0);
+        assert_eq!(ppu.vram_addr_register.get_address(), 0);
+
+        ppu.power_on();
+        assert_eq!(ppu.read_palette_table_at(1), 0);
+        assert_eq!(ppu.read_palette_table_at(2), 0);
+        assert_eq!(ppu.read_vram_at(0x0010), 0);
+        assert_eq!(ppu.read_oam_data_at(0), 0);
+    }
+
+    #[test]
+    fn test_ppu_rendering_enabled() {
+        let mut ppu = Ppu::new_with_empty_rom_hor();
+        assert!(!ppu.rendering_enabled());
+
+        ppu.write_to_mask_register(0b00001000); // show background
+        assert!(ppu.rendering_enabled());
+
+        ppu.write_to_mask_register(0b00010000); // show sprites
+        assert!(ppu.rendering_enabled());
+
+        ppu.write_to_mask_register(0b00011000);
+        assert!(ppu.rendering_enabled());
+
+        ppu.write_to_mask_register(0b11100111); // everything but background and sprites
+        assert!(!ppu.rendering_enabled());
+    }
+
+    #[test]
+    fn test_ppu_raw_register_bits() {
+        let mut ppu = Ppu::new_with_empty_rom_hor();
+        ppu.write_to_control_register(0b10010100);
+        ppu.write_to_mask_register(0b00011110);
+        ppu.status_register.set_vblank_started_flag(true);
+
+        assert_eq!(ppu.control_register_bits(), 0b10010100);
+        assert_eq!(ppu.mask_register_bits(), 0b00011110);
+        assert_eq!(ppu.status_register_bits(), 0b10000000);
+    }
+
+    #[test]
+    fn test_ppu_greyscale_and_color_emphasis() {
+        let mut ppu = Ppu::new_with_empty_rom_hor();
+        assert!(!ppu.is_greyscale());
+        assert_eq!(ppu.color_emphasis(), 0);
+
+        ppu.write_to_mask_register(0b10100001); // greyscale, emphasize red and blue
+        assert!(ppu.is_greyscale());
+        assert_eq!(ppu.color_emphasis(), 0b101);
+    }
+
+    #[test]
+    fn test_ppu_palette_mirroring() {
+        let mut ppu = Ppu::new_with_empty_rom_hor();
+        ppu.write_to_address_register(0x3F);
+        ppu.write_to_address_register(0x10);
+        ppu.write_to_data_register(0x2C);
+
+        ppu.write_to_address_register(0x3F);
+        ppu.write_to_address_register(0x00);
+        assert_eq!(ppu.read_data_register(), 0x2C);
+        assert_eq!(ppu.read_palette_table_at(0x00), 0x2C);
+        assert_eq!(ppu.read_palette_table_at(0x10), 0x2C);
+
+        // 0x3F04/0x3F08/0x3F0C have their own entries, but 0x3F14/0x3F18/0x3F1C mirror them
+        ppu.write_to_address_register(0x3F);
+        ppu.write_to_address_register(0x04);
+        ppu.write_to_data_register(0x11);
+        ppu.write_to_address_register(0x3F);
+        ppu.write_to_address_register(0x1C);
+        ppu.write_to_data_register(0x22);
+        assert_eq!(ppu.read_palette_table_at(0x14), 0x11);
+        assert_eq!(ppu.read_palette_table_at(0x0C), 0x22);
+        assert_eq!(ppu.read_palette_table_at(0x00), 0x2C);
+
+        // 0x3F20-0x3FFF repeat the 32 entries
+        ppu.write_to_address_register(0x3F);
+        ppu.write_to_address_register(0xE1);
+        ppu.write_to_data_register(0x33);
+        assert_eq!(ppu.read_palette_table_at(0x01), 0x33);
+    }
+
+    #[test]
+    fn test_ppu_palette_rgb() {
+        let mut ppu = Ppu::new_with_empty_rom_hor();
+        ppu.palette_table[0x00] = 0x0F;
+        ppu.palette_table[0x01] = 0x21;
+        ppu.palette_table[0x11] = 0x30;
+
+        let rgb = ppu.palette_rgb(&SYSTEM_PALETTE);
+        assert_eq!(rgb[0x00], SYSTEM_PALETTE[0x0F]);
+        assert_eq!(rgb[0x01], SYSTEM_PALETTE[0x21]);
+        assert_eq!(rgb[0x11], SYSTEM_PALETTE[0x30]);
+        assert_eq!(rgb[0x10], SYSTEM_PALETTE[0x0F]); // backdrop mirror
+        assert_eq!(rgb[0x02], SYSTEM_PALETTE[0x00]);
+    }
+
     #[test]
     fn test_ppu_tick_gen_interrupt() {
         let mut ppu = Ppu::new_with_empty_rom_hor();
@@ -430,6 +1433,426 @@ mod tests {
         ppu.cycles = 340;
         ppu.write_to_control_register(0b10000000);
         ppu.tick(1);
+        assert_eq!(ppu.nmi_interrupt, None); // dot 0 of scanline 241
+        ppu.tick(1);
         assert_eq!(ppu.nmi_interrupt, Some(1));
     }
+
+    // Positions the PPU 1 or 2 dots before the vblank flag gets set (scanline 241, dot 1)
+    fn new_before_vblank(dots: u8) -> Ppu {
+        let mut ppu = Ppu::new_with_empty_rom_hor();
+        ppu.write_to_control_register(0b10000000);
+        ppu.scanline = 240;
+        ppu.cycles = 340;
+        ppu.tick(2 - dots);
+        ppu
+    }
+
+    #[test]
+    fn test_ppu_status_read_before_vblank_suppresses_it() {
+        // Two dots before: normal read, vblank and NMI happen
+        let mut ppu = new_before_vblank(2);
+        assert_eq!(ppu.read_status_register() >> 7, 0);
+        ppu.tick(2);
+        assert_eq!(ppu.status_register.snapshot() >> 7, 1);
+        assert_eq!(ppu.poll_nmi_interrupt(), Some(1));
+
+        // One dot before: reads clear, and the flag and NMI never happen for this frame
+        let mut ppu = new_before_vblank(1);
+        assert_eq!(ppu.read_status_register() >> 7, 0);
+        ppu.tick(1);
+        assert_eq!(ppu.status_register.snapshot() >> 7, 0);
+        assert_eq!(ppu.poll_nmi_interrupt(), None);
+    }
+
+    #[test]
+    fn test_ppu_status_read_at_vblank_start_cancels_nmi() {
+        for dots_after in 0..=2 {
+            let mut ppu = new_before_vblank(1);
+            ppu.tick(1 + dots_after);
+            let status = ppu.read_status_register();
+            assert_eq!(status >> 7, 1);
+            assert_eq!(ppu.status_register.snapshot() >> 7, 0);
+            let expected_nmi = if dots_after < 2 { None } else { Some(1) };
+            assert_eq!(ppu.poll_nmi_interrupt(), expected_nmi);
+        }
+    }
+
+    #[test]
+    fn test_ppu_nmi_enable_toggled_during_vblank() {
+        let mut ppu = new_before_vblank(1);
+        ppu.tick(10);
+        // Disabling before the CPU takes it cancels the NMI
+        ppu.write_to_control_register(0);
+        assert_eq!(ppu.poll_nmi_interrupt(), None);
+
+        // Each 0 -> 1 transition while the flag is set generates a new one
+        ppu.write_to_control_register(0b10000000);
+        assert_eq!(ppu.poll_nmi_interrupt(), Some(1));
+        ppu.write_to_control_register(0b10000000);
+        assert_eq!(ppu.poll_nmi_interrupt(), None);
+        ppu.write_to_control_register(0);
+        ppu.write_to_control_register(0b10000000);
+        assert_eq!(ppu.poll_nmi_interrupt(), Some(1));
+
+        // But not once the flag has been read
+        ppu.read_status_register();
+        ppu.write_to_control_register(0);
+        ppu.write_to_control_register(0b10000000);
+        assert_eq!(ppu.poll_nmi_interrupt(), None);
+    }
+
+    #[test]
+    fn test_ppu_flags_cleared_on_pre_render_scanline() {
+        let mut ppu = Ppu::new_with_empty_rom_hor();
+        ppu.scanline = 260;
+        ppu.cycles = 340;
+        ppu.status_register.set_vblank_started_flag(true);
+        ppu.status_register.set_sprite_zero_hit_flag(true);
+        ppu.status_register.set_sprite_overflow_flag(true);
+
+        assert!(!ppu.tick(1));
+        assert_eq!(ppu.scanline, 261);
+        assert_eq!(ppu.status_register_bits(), 0);
+
+        ppu.status_register.set_sprite_zero_hit_flag(true); // set again on the pre-render line
+        ppu.cycles = 340;
+        assert!(ppu.tick(1));
+        assert_eq!(ppu.scanline, 0);
+        assert_eq!(ppu.status_register_bits(), 0b01000000);
+    }
+
+    fn new_with_sprite_zero_test_rom() -> Ppu {
+        let mut chr_rom = vec![0; 0x2000];
+        chr_rom[16..24].copy_from_slice(&[0xFF; 8]); // tile 1 is opaque, tile 0 transparent
+        let mut ppu = Ppu::new(chr_rom, MirroringMode::Horizontal);
+        ppu.write_to_oam_dma_register(&[0xFF; 256]);
+        ppu
+    }
+
+    // Stops right at the first dot of the scanline
+    fn run_until_scanline(ppu: &mut Ppu, scanline: u16) {
+        while ppu.scanline != scanline {
+            ppu.tick(1);
+        }
+    }
+
+    #[test]
+    fn test_ppu_sprite_zero_hit() {
+        let mut ppu = new_with_sprite_zero_test_rom();
+        ppu.oam_data_register[0..4].copy_from_slice(&[10, 1, 0, 20]);
+        ppu.vram[32 + 2] = 1; // opaque background tile under the sprite
+        ppu.write_to_mask_register(0b00011110);
+
+        run_until_scanline(&mut ppu, 10);
+        assert_eq!(ppu.status_register_bits() & 0b01000000, 0);
+        run_until_scanline(&mut ppu, 11);
+        assert_eq!(ppu.status_register_bits() & 0b01000000, 0b01000000);
+    }
+
+    #[test]
+    fn test_ppu_no_sprite_zero_hit_over_transparent_background() {
+        let mut ppu = new_with_sprite_zero_test_rom();
+        ppu.oam_data_register[0..4].copy_from_slice(&[10, 1, 0, 20]);
+        ppu.write_to_mask_register(0b00011110);
+
+        run_until_scanline(&mut ppu, 240);
+        assert_eq!(ppu.status_register_bits() & 0b01000000, 0);
+    }
+
+    #[test]
+    fn test_ppu_no_sprite_zero_hit_in_hidden_left_column() {
+        let mut ppu = new_with_sprite_zero_test_rom();
+        ppu.oam_data_register[0..4].copy_from_slice(&[10, 1, 0, 0]);
+        ppu.vram[32] = 1;
+        ppu.write_to_mask_register(0b00011000); // left column hidden
+
+        run_until_scanline(&mut ppu, 240);
+        assert_eq!(ppu.status_register_bits() & 0b01000000, 0);
+
+        ppu.write_to_mask_register(0b00011110);
+        run_until_scanline(&mut ppu, 20);
+        assert_eq!(ppu.status_register_bits() & 0b01000000, 0b01000000);
+    }
+
+    #[test]
+    fn test_ppu_sprite_zero_hit_with_scroll() {
+        let mut ppu = new_with_sprite_zero_test_rom();
+        ppu.oam_data_register[0..4].copy_from_slice(&[10, 1, 0, 20]);
+        ppu.vram[32 + 4] = 1; // opaque background tile 2 tiles right of the sprite
+        ppu.write_to_mask_register(0b00011110);
+
+        run_until_scanline(&mut ppu, 240);
+        assert_eq!(ppu.status_register_bits() & 0b01000000, 0);
+
+        // Scrolling 16 pixels right brings it under the sprite
+        ppu.write_to_scroll_register(16);
+        ppu.write_to_scroll_register(0);
+        run_until_scanline(&mut ppu, 10);
+        assert_eq!(ppu.status_register_bits() & 0b01000000, 0);
+        run_until_scanline(&mut ppu, 11);
+        assert_eq!(ppu.status_register_bits() & 0b01000000, 0b01000000);
+    }
+
+    #[test]
+    fn test_ppu_sprite_zero_hit_8x16() {
+        let mut ppu = new_with_sprite_zero_test_rom();
+        // Tiles 0 (transparent) and 1 (opaque) as the top and bottom halves
+        ppu.oam_data_register[0..4].copy_from_slice(&[10, 0, 0, 20]);
+        ppu.vram[2 * 32 + 2] = 1; // opaque background under the bottom half
+        ppu.write_to_mask_register(0b00011110);
+
+        run_until_scanline(&mut ppu, 240);
+        assert_eq!(ppu.status_register_bits() & 0b01000000, 0);
+
+        ppu.write_to_control_register(0b0010_0000);
+        run_until_scanline(&mut ppu, 18);
+        assert_eq!(ppu.status_register_bits() & 0b01000000, 0);
+        run_until_scanline(&mut ppu, 19);
+        assert_eq!(ppu.status_register_bits() & 0b01000000, 0b01000000);
+    }
+
+    #[test]
+    fn test_ppu_sprite_zero_hit_timing() {
+        let mut ppu = new_with_sprite_zero_test_rom();
+        ppu.oam_data_register[0..4].copy_from_slice(&[10, 1, 0, 20]);
+        ppu.vram[32 + 2] = 1; // opaque background tile under the sprite
+        ppu.write_to_mask_register(0b00011110);
+        ppu.scanline = 10;
+        ppu.cycles = 0;
+
+        ppu.tick(20);
+        assert!(!ppu.status_register.has_sprite_zero_hit());
+        ppu.tick(1); // x=20 is output at dot 21
+        assert!(ppu.status_register.has_sprite_zero_hit());
+
+        // The flag survives vblank and is only cleared on the pre-render scanline
+        run_until_scanline(&mut ppu, 241);
+        assert!(ppu.status_register.has_sprite_zero_hit());
+        run_until_scanline(&mut ppu, 261);
+        assert!(!ppu.status_register.has_sprite_zero_hit());
+    }
+
+    #[test]
+    fn test_ppu_sprite_overflow() {
+        let mut ppu = new_with_sprite_zero_test_rom();
+        for sprite in 0..9 {
+            ppu.oam_data_register[sprite * 4] = 50;
+        }
+        ppu.write_to_mask_register(0b00010000); // show sprites
+        ppu.scanline = 49;
+        ppu.cycles = 0;
+
+        ppu.tick(255);
+        ppu.tick(2);
+        assert_eq!(ppu.status_register_bits() & 0b00100000, 0);
+
+        ppu.scanline = 50;
+        ppu.cycles = 0;
+        ppu.tick(255);
+        ppu.tick(1);
+        assert_eq!(ppu.status_register_bits() & 0b00100000, 0);
+        ppu.tick(1); // evaluation is over at dot 257
+        assert_eq!(ppu.status_register_bits() & 0b00100000, 0b00100000);
+    }
+
+    #[test]
+    fn test_ppu_no_sprite_overflow_with_8_sprites() {
+        let mut ppu = new_with_sprite_zero_test_rom();
+        for sprite in 0..8 {
+            ppu.oam_data_register[sprite * 4] = 50;
+        }
+        ppu.write_to_mask_register(0b00010000);
+
+        run_until_scanline(&mut ppu, 60);
+        assert_eq!(ppu.status_register_bits() & 0b00100000, 0);
+    }
+
+    struct A12CountingMapper {
+        chr_ram: Vec<u8>,
+        a12_rises: Rc<Cell<usize>>,
+    }
+
+    impl Mapper for A12CountingMapper {
+        fn cpu_read(&self, _addr: u16) -> u8 {
+            0
+        }
+
+        fn cpu_write(&mut self, _addr: u16, _data: u8) {}
+
+        fn ppu_read(&self, addr: u16) -> u8 {
+            self.chr_ram[addr as usize]
+        }
+
+        fn ppu_write(&mut self, addr: u16, data: u8) {
+            self.chr_ram[addr as usize] = data;
+        }
+
+        fn mirroring(&self) -> MirroringMode {
+            MirroringMode::Horizontal
+        }
+
+        fn notify_a12_rise(&mut self) {
+            self.a12_rises.set(self.a12_rises.get() + 1);
+        }
+    }
+
+    #[test]
+    fn test_ppu_a12_rises_once_per_rendered_scanline() {
+        let a12_rises = Rc::new(Cell::new(0));
+        let mapper = A12CountingMapper {
+            chr_ram: vec![0; 0x2000],
+            a12_rises: a12_rises.clone(),
+        };
+        let mut ppu = Ppu::with_mapper(mapper::share(Box::new(mapper)), TvSystem::Ntsc);
+
+        // Rendering disabled
+        while !ppu.tick(255) {}
+        assert_eq!(a12_rises.get(), 0);
+
+        // Background at 0x0000, sprites at 0x1000
+        ppu.write_to_control_register(0b00001000);
+        ppu.write_to_mask_register(0b00011000);
+        ppu.scanline = 0;
+        ppu.cycles = 0;
+        ppu.tick(255);
+        assert_eq!(a12_rises.get(), 0);
+        ppu.tick(5); // dot 260
+        assert_eq!(a12_rises.get(), 1);
+
+        // The 240 visible scanlines and the pre-render one
+        while !ppu.tick(255) {}
+        assert_eq!(a12_rises.get(), 241);
+    }
+
+    fn new_with_a12_counting_mapper() -> (Ppu, Rc<Cell<usize>>) {
+        let a12_rises = Rc::new(Cell::new(0));
+        let mapper = A12CountingMapper {
+            chr_ram: vec![0; 0x2000],
+            a12_rises: a12_rises.clone(),
+        };
+        let mut ppu = Ppu::with_mapper(mapper::share(Box::new(mapper)), TvSystem::Ntsc);
+        // Every sprite off screen
+        ppu.oam_data_register = [0xFF; 64 * 4];
+        (ppu, a12_rises)
+    }
+
+    #[test]
+    fn test_ppu_a12_rises_with_8x16_sprites() {
+        // Background at 0x0000: the unused sprite slots fetch tile 0xFF from the high table
+        let (mut ppu, a12_rises) = new_with_a12_counting_mapper();
+        ppu.write_to_control_register(0b00100000);
+        ppu.write_to_mask_register(0b00011000);
+        while !ppu.tick(255) {}
+        a12_rises.set(0);
+        while !ppu.tick(255) {}
+        assert_eq!(a12_rises.get(), 241);
+
+        // Background at 0x1000 and no sprites: A12 never goes low
+        let (mut ppu, a12_rises) = new_with_a12_counting_mapper();
+        ppu.write_to_control_register(0b00110000);
+        ppu.write_to_mask_register(0b00011000);
+        while !ppu.tick(255) {}
+        while !ppu.tick(255) {}
+        assert_eq!(a12_rises.get(), 0);
+
+        // 8 sprites with even tiles fill every slot with low fetches on their 16 scanlines,
+        // so A12 rises when the background fetches start again
+        for sprite in 0..8 {
+            ppu.oam_data_register[sprite * 4] = 100;
+            ppu.oam_data_register[sprite * 4 + 1] = 0x02;
+        }
+        run_until_scanline(&mut ppu, 100);
+        ppu.tick(255);
+        ppu.tick(68); // dot 323
+        assert_eq!(a12_rises.get(), 0);
+        ppu.tick(1); // dot 324
+        assert_eq!(a12_rises.get(), 1);
+        while !ppu.tick(255) {}
+        assert_eq!(a12_rises.get(), 16);
+
+        // An odd tile among them makes A12 rise during its slot as well
+        ppu.oam_data_register[3 * 4 + 1] = 0x03;
+        run_until_scanline(&mut ppu, 100);
+        ppu.tick(255);
+        ppu.tick(29); // dot 284, the 4th sprite slot
+        assert_eq!(a12_rises.get(), 17);
+        ppu.tick(40); // dot 324
+        assert_eq!(a12_rises.get(), 18);
+
+        // But not when A12 was low for a single slot before it
+        while !ppu.tick(255) {}
+        ppu.oam_data_register[1] = 0x01;
+        ppu.oam_data_register[2 * 4 + 1] = 0x01;
+        run_until_scanline(&mut ppu, 100);
+        ppu.tick(255);
+        ppu.tick(29);
+        assert_eq!(a12_rises.get(), 48);
+        ppu.tick(40);
+        assert_eq!(a12_rises.get(), 49);
+    }
+
+    /// The scanline counter of the MMC3: reloaded from the latch when it's 0, decremented
+    /// otherwise, raising the IRQ when it reaches 0
+    struct Mmc3ScanlineCounterMapper {
+        chr_ram: Vec<u8>,
+        latch: u8,
+        counter: u8,
+        irq: Rc<Cell<bool>>,
+    }
+
+    impl Mapper for Mmc3ScanlineCounterMapper {
+        fn cpu_read(&self, _addr: u16) -> u8 {
+            0
+        }
+
+        fn cpu_write(&mut self, _addr: u16, _data: u8) {}
+
+        fn ppu_read(&self, addr: u16) -> u8 {
+            self.chr_ram[addr as usize]
+        }
+
+        fn ppu_write(&mut self, addr: u16, data: u8) {
+            self.chr_ram[addr as usize] = data;
+        }
+
+        fn mirroring(&self) -> MirroringMode {
+            MirroringMode::Vertical
+        }
+
+        fn notify_a12_rise(&mut self) {
+            if self.counter == 0 {
+                self.counter = self.latch;
+            } else {
+                self.counter -= 1;
+            }
+            if self.counter == 0 {
+                self.irq.set(true);
+            }
+        }
+    }
+
+    #[test]
+    fn test_ppu_mmc3_irq_scanline_with_8x16_sprites() {
+        // Status bar split: the latch is set during vblank, so the pre-render scanline reloads
+        // the counter and the IRQ fires at the sprite fetches of the scanline before the split
+        let irq = Rc::new(Cell::new(false));
+        let mapper = Mmc3ScanlineCounterMapper {
+            chr_ram: vec![0; 0x2000],
+            latch: 192,
+            counter: 0,
+            irq: irq.clone(),
+        };
+        let mut ppu = Ppu::with_mapper(mapper::share(Box::new(mapper)), TvSystem::Ntsc);
+        ppu.oam_data_register = [0xFF; 64 * 4];
+        run_until_scanline(&mut ppu, VBLANK_SCANLINE);
+        ppu.write_to_control_register(0b00100000);
+        ppu.write_to_mask_register(0b00011000);
+
+        while !irq.get() {
+            ppu.tick(1);
+        }
+        assert_eq!(ppu.scanline, 191);
+        assert_eq!(ppu.cycles, 260);
+    }
 }
@@ -1,5 +1,4 @@
-pub mod address;
 pub mod control;
 pub mod mask;
 pub mod status;
-pub mod scroll;
\ No newline at end of file
+pub mod vram_address;
\ No newline at end of file
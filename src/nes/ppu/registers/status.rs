@@ -63,6 +63,10 @@ impl StatusRegister {
         self.contains(StatusRegister::VBLANK_STARTED)
     }
 
+    pub fn has_sprite_zero_hit(&self) -> bool {
+        self.contains(StatusRegister::SPRITE_ZERO_HIT)
+    }
+
     pub fn snapshot(&self) -> u8 {
         self.bits
     }
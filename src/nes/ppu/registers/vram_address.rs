@@ -0,0 +1,190 @@
+use crate::nes::state::{StateError, StateReader, StateWriter};
+
+// The internal registers, as nesdev names them:
+//   v: current VRAM address (15 bits)
+//   t: temporary VRAM address (15 bits), the top left onscreen tile
+//   x: fine X scroll (3 bits)
+//   w: write toggle, shared by 0x2005 and 0x2006
+//
+// v and t are laid out as:
+//   yyy NN YYYYY XXXXX
+//   ||| || ||||| +++++-- coarse X scroll
+//   ||| || +++++-------- coarse Y scroll
+//   ||| ++-------------- nametable select
+//   +++----------------- fine Y scroll
+const COARSE_X_MASK: u16 = 0x001F;
+const COARSE_Y_MASK: u16 = 0x03E0;
+const NAMETABLE_MASK: u16 = 0x0C00;
+const FINE_Y_MASK: u16 = 0x7000;
+const ADDRESS_MASK: u16 = 0x3FFF;
+
+/// PPU scroll (0x2005) and address (0x2006) registers. On hardware they are not separate:
+/// both are written through the same internal registers and write toggle, which is why
+/// writes to one affect the other.
+/// RAM addresses: 0x2005 - Bits: xxxx xxxx, 0x2006 - Bits: aaaa aaaa.
+pub struct VramAddressRegister {
+    v: u16,
+    t: u16,
+    fine_x: u8,
+    write_toggle: bool,
+}
+
+impl VramAddressRegister {
+    pub fn new() -> Self {
+        VramAddressRegister {
+            v: 0,
+            t: 0,
+            fine_x: 0,
+            write_toggle: false,
+        }
+    }
+
+    /// The address that 0x2007 accesses
+    pub fn get_address(&self) -> u16 {
+        self.v & ADDRESS_MASK
+    }
+
+    /// First write: high 6 bits of the address. Second write: low byte, then t is copied to v.
+    /// Returns true on the second write.
+    pub fn write_address(&mut self, data: u8) -> bool {
+        if !self.write_toggle {
+            // Bit 14 of t is cleared as well
+            self.t = (self.t & 0x00FF) | ((data as u16 & 0x3F) << 8);
+        } else {
+            self.t = (self.t & 0xFF00) | data as u16;
+            self.v = self.t;
+        }
+        self.write_toggle = !self.write_toggle;
+        !self.write_toggle
+    }
+
+    /// First write: X scroll. Second write: Y scroll.
+    /// Both go into t, and only reach v through a second address write or rendering.
+    pub fn write_scroll(&mut self, data: u8) {
+        if !self.write_toggle {
+            self.t = (self.t & !COARSE_X_MASK) | (data as u16 >> 3);
+            self.fine_x = data & 0b111;
+        } else {
+            self.t = (self.t & !(COARSE_Y_MASK | FINE_Y_MASK))
+                | ((data as u16 & 0b1111_1000) << 2)
+                | ((data as u16 & 0b111) << 12);
+        }
+        self.write_toggle = !self.write_toggle;
+    }
+
+    /// X scroll the next frame starts from, as written to 0x2005
+    pub fn scroll_x(&self) -> u8 {
+        ((self.t & COARSE_X_MASK) << 3) as u8 | self.fine_x
+    }
+
+    /// Y scroll the next frame starts from, as written to 0x2005
+    pub fn scroll_y(&self) -> u8 {
+        (((self.t & COARSE_Y_MASK) >> 2) | ((self.t & FINE_Y_MASK) >> 12)) as u8
+    }
+
+    /// Nametable (0-3) the next frame starts from
+    pub fn nametable_select(&self) -> u8 {
+        ((self.t & NAMETABLE_MASK) >> 10) as u8
+    }
+
+    /// The nametable select bits of the control register (0x2000) are also kept in t
+    pub fn write_nametable_select(&mut self, data: u8) {
+        self.t = (self.t & !NAMETABLE_MASK) | ((data as u16 & 0b11) << 10);
+    }
+
+    /// Increment after a 0x2007 access, by 1 or 32 depending on the control register
+    pub fn increment(&mut self, inc: u8) {
+        self.v = self.v.wrapping_add(inc as u16) & 0x7FFF;
+    }
+
+    /// Reading the status register (0x2002) resets the write toggle
+    pub fn reset_latch(&mut self) {
+        self.write_toggle = false;
+    }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.write_u16(self.v);
+        state.write_u16(self.t);
+        state.write_u8(self.fine_x);
+        state.write_bool(self.write_toggle);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.v = state.read_u16()? & 0x7FFF;
+        self.t = state.read_u16()? & 0x7FFF;
+        self.fine_x = state.read_u8()? & 0b111;
+        self.write_toggle = state.read_bool()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ppu_vram_address_write() {
+        let mut reg = VramAddressRegister::new();
+        reg.write_address(0x23);
+        assert_eq!(reg.get_address(), 0x0000); // only t changes on the first write
+        reg.write_address(0x05);
+        assert_eq!(reg.get_address(), 0x2305);
+
+        // Only the low 14 bits are addressable
+        reg.write_address(0xFF);
+        reg.write_address(0xAB);
+        assert_eq!(reg.get_address(), 0x3FAB);
+    }
+
+    #[test]
+    fn test_ppu_vram_address_increment() {
+        let mut reg = VramAddressRegister::new();
+        reg.write_address(0x02);
+        reg.write_address(0xFF);
+        reg.increment(1);
+        assert_eq!(reg.get_address(), 0x0300);
+
+        reg.write_address(0x3F);
+        reg.write_address(0xF0);
+        reg.increment(32);
+        assert_eq!(reg.get_address(), 0x0010);
+    }
+
+    #[test]
+    fn test_ppu_vram_address_scroll_write() {
+        let mut reg = VramAddressRegister::new();
+        reg.write_nametable_select(0b10);
+        reg.write_scroll(0x7D); // coarse X 15, fine X 5
+        reg.write_scroll(0xAB); // coarse Y 21, fine Y 3
+        assert_eq!(reg.t, (3 << 12) | (0b10 << 10) | (21 << 5) | 15);
+        assert_eq!(reg.nametable_select(), 0b10);
+        assert_eq!(reg.fine_x, 0b101);
+        assert_eq!(reg.v, 0);
+    }
+
+    #[test]
+    fn test_ppu_vram_address_scroll_read_back() {
+        let mut reg = VramAddressRegister::new();
+        reg.write_scroll(0x7D);
+        reg.write_scroll(0xAB);
+        assert_eq!((reg.scroll_x(), reg.scroll_y()), (0x7D, 0xAB));
+    }
+
+    #[test]
+    fn test_ppu_vram_address_shares_the_write_toggle() {
+        // The usual mid-frame split: 0x2006, 0x2005, 0x2005, 0x2006
+        let mut reg = VramAddressRegister::new();
+        reg.write_address(0x04);
+        reg.write_scroll(0x40); // second write: Y scroll, coarse Y 8
+        reg.write_scroll(0x17); // first write: X scroll, coarse X 2 and fine X 7
+        reg.write_address(0x42); // overwrites the low 3 bits of coarse Y
+        assert_eq!(reg.v, (0b01 << 10) | (10 << 5) | 2);
+        assert_eq!(reg.fine_x, 0b111);
+
+        reg.write_address(0x21);
+        reg.reset_latch();
+        reg.write_address(0x3F);
+        reg.write_address(0x00);
+        assert_eq!(reg.get_address(), 0x3F00);
+    }
+}
@@ -0,0 +1,59 @@
+const FLIP_VERTICAL_FLAG: u8 = 0b1000_0000;
+const FLIP_HORIZONTAL_FLAG: u8 = 0b0100_0000;
+const PRIORITY_FLAG: u8 = 0b0010_0000;
+const PALETTE_MASK: u8 = 0b0000_0011;
+
+/// An OAM entry, decoded from its 4 bytes: Y, tile index, attributes and X.
+/// The attributes byte is kept as is besides the fields decoded from it:
+///   76543210
+///   ||||||++- palette (4 to 7) of the sprite
+///   |||+++--- unimplemented
+///   ||+------ priority (0: in front of background; 1: behind background)
+///   |+------- flip sprite horizontally
+///   +-------- flip sprite vertically
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Sprite {
+    /// Top of the sprite. Sprites are drawn one scanline lower than this on hardware.
+    pub y: u8,
+    pub tile_index: u8,
+    pub attributes: u8,
+    pub x: u8,
+    pub flip_h: bool,
+    pub flip_v: bool,
+    /// Sprite palette, 0 to 3 (palettes 4 to 7 of the palette table)
+    pub palette: u8,
+    /// Set when the sprite goes behind opaque background pixels
+    pub priority: bool,
+}
+
+impl Sprite {
+    pub fn from_oam_entry(entry: [u8; 4]) -> Self {
+        let attributes = entry[2];
+        Sprite {
+            y: entry[0],
+            tile_index: entry[1],
+            attributes,
+            x: entry[3],
+            flip_h: attributes & FLIP_HORIZONTAL_FLAG != 0,
+            flip_v: attributes & FLIP_VERTICAL_FLAG != 0,
+            palette: attributes & PALETTE_MASK,
+            priority: attributes & PRIORITY_FLAG != 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sprite_from_oam_entry() {
+        let sprite = Sprite::from_oam_entry([0x10, 0x42, 0b1010_0010, 0x80]);
+        assert_eq!((sprite.y, sprite.tile_index, sprite.x), (0x10, 0x42, 0x80));
+        assert_eq!(sprite.attributes, 0b1010_0010);
+        assert!(sprite.flip_v);
+        assert!(!sprite.flip_h);
+        assert!(sprite.priority);
+        assert_eq!(sprite.palette, 2);
+    }
+}
@@ -1,5 +1,13 @@
+use crate::nes::render::PixelSink;
+use std::fs::File;
+use std::io;
+use std::io::BufWriter;
+use std::path::Path;
+
 pub struct Frame {
     data: Vec<u8>,
+    width: usize,
+    height: usize,
 }
 
 impl Frame {
@@ -7,21 +15,177 @@ impl Frame {
     const HEIGHT: usize = 240;
 
     pub fn new() -> Self {
+        Frame::with_size(Frame::WIDTH, Frame::HEIGHT)
+    }
+
+    /// Creates a frame with custom dimensions, e.g. for debug views larger than the screen
+    pub fn with_size(width: usize, height: usize) -> Self {
         Frame {
-            data: vec![0; (Frame::WIDTH) * (Frame::HEIGHT) * 3],
+            data: vec![0; width * height * 3],
+            width,
+            height,
         }
     }
 
+    /// Out of range coordinates are ignored, e.g. sprites partially off the right edge
     pub fn set_pixel(&mut self, x: usize, y: usize, rgb: (u8, u8, u8)) {
-        let base = y * 3 * Frame::WIDTH + x * 3;
-        if base + 2 < self.data.len() {
-            self.data[base] = rgb.0;
-            self.data[base + 1] = rgb.1;
-            self.data[base + 2] = rgb.2;
+        if x >= self.width || y >= self.height {
+            return;
+        }
+        let base = y * 3 * self.width + x * 3;
+        self.data[base] = rgb.0;
+        self.data[base + 1] = rgb.1;
+        self.data[base + 2] = rgb.2;
+    }
+
+    /// Reads back a pixel. Out of range coordinates read as black, like `set_pixel` ignores them.
+    pub fn get_pixel(&self, x: usize, y: usize) -> (u8, u8, u8) {
+        if x >= self.width || y >= self.height {
+            return (0, 0, 0);
         }
+        let base = y * 3 * self.width + x * 3;
+        (self.data[base], self.data[base + 1], self.data[base + 2])
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
     }
 
     pub fn data(&self) -> &Vec<u8> {
         &self.data
     }
-}
\ No newline at end of file
+
+    /// The RGB data without the given number of pixels on each edge, e.g. `cropped(8, 8, 0, 0)`
+    /// for the 256x224 area that TVs usually show. Games often leave garbage in the overscan.
+    pub fn cropped(&self, top: usize, bottom: usize, left: usize, right: usize) -> Vec<u8> {
+        let width = self.width.saturating_sub(left + right);
+        let height = self.height.saturating_sub(top + bottom);
+        if width == 0 || height == 0 {
+            return Vec::new();
+        }
+        let mut data = Vec::with_capacity(width * height * 3);
+        for y in top..top + height {
+            let base = (y * self.width + left) * 3;
+            data.extend_from_slice(&self.data[base..base + width * 3]);
+        }
+        data
+    }
+
+    /// The frame expanded to RGBA with an opaque alpha, 4 bytes per pixel, as expected
+    /// by e.g. a canvas' `putImageData` or most GPU textures
+    pub fn data_rgba(&self) -> Vec<u8> {
+        let mut rgba = Vec::with_capacity(self.width * self.height * 4);
+        self.write_rgba(&mut rgba);
+        rgba
+    }
+
+    /// Same as `data_rgba`, but reuses the given buffer's allocation
+    pub fn write_rgba(&self, rgba: &mut Vec<u8>) {
+        rgba.clear();
+        for rgb in self.data.chunks_exact(3) {
+            rgba.extend_from_slice(rgb);
+            rgba.push(0xFF);
+        }
+    }
+
+    /// Saves the frame as an 8-bit RGB PNG, e.g. for screenshots or golden-image tests
+    pub fn save_png<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        self.write_png(BufWriter::new(File::create(path)?))
+    }
+
+    /// Same as `save_png`, but keeps the PNG in memory, e.g. to attach it to a bug report
+    pub fn png_bytes(&self) -> Vec<u8> {
+        let mut png = Vec::new();
+        self.write_png(&mut png)
+            .expect("Writing a PNG to memory can't fail");
+        png
+    }
+
+    fn write_png<W: io::Write>(&self, writer: W) -> io::Result<()> {
+        let mut encoder = png::Encoder::new(writer, self.width as u32, self.height as u32);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&self.data)?;
+        Ok(())
+    }
+}
+
+impl PixelSink for Frame {
+    fn set_pixel(&mut self, x: usize, y: usize, rgb: (u8, u8, u8)) {
+        Frame::set_pixel(self, x, y, rgb)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frame_get_pixel() {
+        let mut frame = Frame::new();
+        frame.set_pixel(255, 239, (0x12, 0x34, 0x56));
+        assert_eq!(frame.get_pixel(255, 239), (0x12, 0x34, 0x56));
+        assert_eq!(frame.get_pixel(254, 239), (0, 0, 0));
+
+        frame.set_pixel(256, 0, (0xFF, 0xFF, 0xFF)); // ignored instead of wrapping to (0, 1)
+        assert_eq!(frame.get_pixel(256, 0), (0, 0, 0));
+        assert_eq!(frame.get_pixel(0, 1), (0, 0, 0));
+        assert_eq!(frame.get_pixel(0, 240), (0, 0, 0));
+    }
+
+    #[test]
+    fn test_frame_cropped() {
+        let mut frame = Frame::new();
+        frame.set_pixel(8, 8, (0x12, 0x34, 0x56));
+        frame.set_pixel(247, 231, (0xAB, 0xCD, 0xEF));
+
+        let data = frame.cropped(8, 8, 0, 0);
+        assert_eq!(data.len(), 256 * 224 * 3);
+        assert_eq!(&data[8 * 3..9 * 3], &[0x12, 0x34, 0x56]);
+
+        let data = frame.cropped(8, 8, 8, 8);
+        assert_eq!(data.len(), 240 * 224 * 3);
+        assert_eq!(&data[0..3], &[0x12, 0x34, 0x56]);
+        assert_eq!(&data[data.len() - 3..], &[0xAB, 0xCD, 0xEF]);
+
+        assert!(frame.cropped(200, 200, 0, 0).is_empty());
+        assert!(frame.cropped(0, 0, 300, 0).is_empty());
+    }
+
+    #[test]
+    fn test_frame_data_rgba() {
+        let mut frame = Frame::new();
+        frame.set_pixel(1, 0, (0x12, 0x34, 0x56));
+        frame.set_pixel(255, 239, (0xAB, 0xCD, 0xEF));
+
+        let rgba = frame.data_rgba();
+        assert_eq!(rgba.len(), 256 * 240 * 4);
+        assert_eq!(&rgba[0..8], &[0, 0, 0, 0xFF, 0x12, 0x34, 0x56, 0xFF]);
+        assert_eq!(&rgba[rgba.len() - 4..], &[0xAB, 0xCD, 0xEF, 0xFF]);
+    }
+
+    #[test]
+    fn test_frame_save_png() {
+        let mut frame = Frame::new();
+        frame.set_pixel(1, 0, (0x12, 0x34, 0x56));
+        let path = std::env::temp_dir().join("phantom_test_frame_save_png.png");
+        frame.save_png(&path).unwrap();
+
+        let decoder = png::Decoder::new(File::open(&path).unwrap());
+        let mut reader = decoder.read_info().unwrap();
+        let mut data = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut data).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!((info.width, info.height), (256, 240));
+        assert_eq!(info.color_type, png::ColorType::Rgb);
+        assert_eq!(info.bit_depth, png::BitDepth::Eight);
+        assert_eq!(&data[..], &frame.data()[..]);
+    }
+}
@@ -2,68 +2,176 @@ pub mod frame;
 pub mod palette;
 
 use crate::nes::ppu::Ppu;
-use crate::nes::render::frame::Frame;
 
-pub fn render(ppu: &Ppu, frame: &mut Frame) {
-    let bank = ppu.control_register_background_pattern_address();
+const NAMETABLE_ADDRESSES: [u16; 4] = [0x2000, 0x2400, 0x2800, 0x2C00];
 
-    // Background
-    for i in 0..0x03C0 {
-        let tile = ppu.read_vram_at(i) as u16;
-        let tile_column = i % 32;
-        let tile_row = i / 32;
-        let tile = ppu.chr_rom_slice(
-            (bank + tile * 16) as usize,
-            (bank + tile * 16 + 15) as usize,
-        );
-        let palette = background_pallet(ppu, tile_column, tile_row);
+/// Where the renderers draw their RGB pixels. `Frame` is the usual one, but anything can be,
+/// e.g. a downsampled thumbnail, a terminal or a color histogram.
+pub trait PixelSink {
+    /// Coordinates can be out of range (e.g. sprites partially off the right edge),
+    /// sinks are expected to ignore them
+    fn set_pixel(&mut self, x: usize, y: usize, rgb: (u8, u8, u8));
+}
 
-        for y in 0..=7 {
-            let mut upper = tile[y];
-            let mut lower = tile[y + 8];
+pub fn render<S: PixelSink + ?Sized>(ppu: &Ppu, frame: &mut S) {
+    let greyscale_mask = greyscale_mask(ppu);
+    let emphasis = ppu.color_emphasis();
+    render_color_indices(ppu, |x, y, color_index| {
+        // Like on hardware, greyscale is applied to the color index and emphasis to the result
+        let rgb = palette::SYSTEM_PALETTE[(color_index & greyscale_mask) as usize];
+        frame.set_pixel(x, y, palette::emphasize(rgb, emphasis))
+    });
+}
 
-            for x in (0..=7).rev() {
-                let value = (1 & lower) << 1 | (1 & upper);
-                upper = upper >> 1;
-                lower = lower >> 1;
-                let rgb = match value {
-                    0 => palette::SYSTEM_PALETTE[palette[0] as usize],
-                    1 => palette::SYSTEM_PALETTE[palette[1] as usize],
-                    2 => palette::SYSTEM_PALETTE[palette[2] as usize],
-                    3 => palette::SYSTEM_PALETTE[palette[3] as usize],
-                    _ => panic!("RGB system palette for background could not be calculated"),
-                };
-                frame.set_pixel(tile_column * 8 + x, tile_row * 8 + y, rgb)
+/// Renders a single visible scanline (0-239) with the PPU state as it is at the time of the
+/// call. The PPU calls it as each scanline completes, which honors mid-frame scroll, palette
+/// and mask changes (e.g. status bars). Unlike `render`, the background is scrolled.
+pub fn render_scanline<S: PixelSink + ?Sized>(ppu: &Ppu, frame: &mut S, scanline: u16) {
+    let y = scanline as usize;
+    if y >= 240 {
+        return;
+    }
+    let mut line = [ppu.read_palette_table_at(0); 256];
+    let mut background_opaque = [false; 256];
+
+    if ppu.scanline_shows_background(y) {
+        // The row of the current tile, shared by its 8 pixels
+        let mut tile_row: Option<([u8; 2], [u8; 4])> = None;
+        for (x, color_index) in line.iter_mut().enumerate() {
+            if x < 8 && !ppu.scanline_shows_leftmost_background(y) {
+                continue;
+            }
+            let (world_x, world_y) = background_world_position(ppu, x, scanline);
+            let bit = 7 - world_x % 8;
+            if bit == 7 || tile_row.is_none() {
+                tile_row = Some(background_tile_row(ppu, world_x, world_y));
+            }
+            let ([lo, hi], palette) = tile_row.expect("The tile row was just fetched");
+            let value = (hi >> bit & 1) << 1 | (lo >> bit & 1);
+            if value != 0 {
+                *color_index = palette[value as usize];
+                background_opaque[x] = true;
             }
         }
     }
 
-    // Sprites
-    for i in (0..ppu.oam_data_size()).step_by(4).rev() {
-        let tile_idx = ppu.read_oam_data_at(i + 1) as u16;
-        let tile_x = ppu.read_oam_data_at(i + 3) as usize;
-        let tile_y = ppu.read_oam_data_at(i) as usize;
+    if ppu.scanline_shows_sprites(y) {
+        let height = ppu.control_register_sprite_size() as usize;
+        // The lower indices on top
+        for index in (0..64).rev() {
+            let sprite = ppu.sprite(index);
+            let top = sprite.y as usize;
+            if y < top || y >= top + height {
+                continue;
+            }
+            let addr = ppu.sprite_pattern_row_address(&sprite, y - top);
+            let (lo, hi) = (ppu.read_chr(addr), ppu.read_chr(addr + 8));
+            let palette = sprite_palette(ppu, sprite.palette);
+            for column in 0..8 {
+                let x = sprite.x as usize + column;
+                if x >= 256 || (x < 8 && !ppu.scanline_shows_leftmost_sprites(y)) {
+                    continue;
+                }
+                let bit = if sprite.flip_h { column } else { 7 - column };
+                let value = (hi >> bit & 1) << 1 | (lo >> bit & 1);
+                if value == 0 || (sprite.priority && background_opaque[x]) {
+                    continue;
+                }
+                line[x] = palette[value as usize];
+            }
+        }
+    }
 
-        let flip_vertical = if ppu.read_oam_data_at(i + 2) >> 7 & 1 == 1 {
-            true
-        } else {
-            false
-        };
+    let greyscale_mask = greyscale_mask(ppu);
+    let emphasis = ppu.color_emphasis();
+    for (x, color_index) in line.iter().enumerate() {
+        let rgb = palette::SYSTEM_PALETTE[(color_index & greyscale_mask) as usize];
+        frame.set_pixel(x, y, palette::emphasize(rgb, emphasis));
+    }
+}
+
+/// Renders the 6-bit NES color index of every pixel instead of its RGB value, with the
+/// mask register's color emphasis bits on bits 6-8, so front-ends can apply the palette
+/// themselves (e.g. in a shader).
+pub fn render_indexed(ppu: &Ppu, output: &mut [u16; 256 * 240]) {
+    let greyscale_mask = greyscale_mask(ppu);
+    let emphasis = (ppu.color_emphasis() as u16) << 6;
+    render_color_indices(ppu, |x, y, color_index| {
+        if x < 256 && y < 240 {
+            output[y * 256 + x] = (color_index & greyscale_mask) as u16 | emphasis;
+        }
+    });
+}
+
+/// Renders the four logical nametables as a 2x2 composite, to see the whole scrollable area.
+/// The frame is expected to be 512x480, e.g. `Frame::with_size(512, 480)`.
+pub fn render_nametables<S: PixelSink + ?Sized>(ppu: &Ppu, frame: &mut S) {
+    for (i, nametable_addr) in NAMETABLE_ADDRESSES.iter().enumerate() {
+        let x_offset = (i % 2) * 256;
+        let y_offset = (i / 2) * 240;
+        render_nametable(ppu, *nametable_addr, |x, y, color_index, _value| {
+            let rgb = palette::SYSTEM_PALETTE[(color_index & 0x3F) as usize];
+            frame.set_pixel(x_offset + x, y_offset + y, rgb)
+        });
+    }
+}
+
+/// Renders the 256 tiles of a pattern table (0 for 0x0000, 1 for 0x1000) as a 16x16 tile sheet,
+/// coloring them with the given 4 system palette indices. The frame is expected to be 128x128,
+/// e.g. `Frame::with_size(128, 128)`.
+pub fn render_pattern_table<S: PixelSink + ?Sized>(
+    ppu: &Ppu,
+    table: u8,
+    palette: [u8; 4],
+    frame: &mut S,
+) {
+    let bank = (table as u16 & 1) * 0x1000;
+    for tile_idx in 0..256 {
+        let tile = read_tile(ppu, bank + tile_idx as u16 * 16);
+        let tile_x = (tile_idx % 16) * 8;
+        let tile_y = (tile_idx / 16) * 8;
+        for_each_tile_pixel(&tile, |x, y, value| {
+            let rgb = palette::SYSTEM_PALETTE[(palette[value as usize] & 0x3F) as usize];
+            frame.set_pixel(tile_x + x, tile_y + y, rgb)
+        });
+    }
+}
 
-        let flip_horizontal = if ppu.read_oam_data_at(i + 2) >> 6 & 1 == 1 {
-            true
+// Greyscale mode keeps only the brightness (upper) bits of the color index
+fn greyscale_mask(ppu: &Ppu) -> u8 {
+    if ppu.is_greyscale() {
+        0x30
+    } else {
+        0x3F
+    }
+}
+
+fn render_color_indices<F>(ppu: &Ppu, mut set_pixel: F)
+where
+    F: FnMut(usize, usize, u8),
+{
+    // Background, with the backdrop color where it is hidden. Where it is opaque is kept
+    // for the sprites that go behind it.
+    let mut background_opaque = vec![false; 256 * 240];
+    render_nametable(ppu, NAMETABLE_ADDRESSES[0], |x, y, color_index, value| {
+        let shown = ppu.scanline_shows_background(y)
+            && (x >= 8 || ppu.scanline_shows_leftmost_background(y));
+        if shown {
+            background_opaque[y * 256 + x] = value != 0;
+            set_pixel(x, y, color_index)
         } else {
-            false
-        };
+            set_pixel(x, y, ppu.read_palette_table_at(0))
+        }
+    });
 
-        let palette_idx = ppu.read_oam_data_at(i + 2) & 0b11;
-        let sprite_palette = sprite_palette(ppu, palette_idx);
+    // Sprites, the lower indices on top
+    for sprite in ppu.sprites().iter().rev() {
+        let tile_x = sprite.x as usize;
+        let tile_y = sprite.y as usize;
+        let sprite_palette = sprite_palette(ppu, sprite.palette);
         let bank = ppu.control_register_sprite_pattern_address();
 
-        let tile = ppu.chr_rom_slice(
-            (bank + tile_idx * 16) as usize,
-            (bank + tile_idx * 16 + 15) as usize,
-        );
+        let tile = read_tile(ppu, bank + sprite.tile_index as u16 * 16);
 
         for y in 0..=7 {
             let mut upper = tile[y];
@@ -73,28 +181,129 @@ pub fn render(ppu: &Ppu, frame: &mut Frame) {
                 let value = (1 & lower) << 1 | (1 & upper);
                 upper = upper >> 1;
                 lower = lower >> 1;
-                let rgb = match value {
+                let color_index = match value {
                     0 => continue, // Transparent pixel - Skip coloring
-                    1 => palette::SYSTEM_PALETTE[sprite_palette[1] as usize],
-                    2 => palette::SYSTEM_PALETTE[sprite_palette[2] as usize],
-                    3 => palette::SYSTEM_PALETTE[sprite_palette[3] as usize],
+                    1 => sprite_palette[1],
+                    2 => sprite_palette[2],
+                    3 => sprite_palette[3],
                     _ => panic!("RGB system palette for sprite could not be calculated"),
                 };
 
-                match (flip_horizontal, flip_vertical) {
-                    (false, false) => frame.set_pixel(tile_x + x, tile_y + y, rgb),
-                    (true, false) => frame.set_pixel(tile_x + 7 - x, tile_y + y, rgb),
-                    (false, true) => frame.set_pixel(tile_x + x, tile_y + 7 - y, rgb),
-                    (true, true) => frame.set_pixel(tile_x + 7 - x, tile_y + 7 - y, rgb),
+                let (pixel_x, pixel_y) = match (sprite.flip_h, sprite.flip_v) {
+                    (false, false) => (tile_x + x, tile_y + y),
+                    (true, false) => (tile_x + 7 - x, tile_y + y),
+                    (false, true) => (tile_x + x, tile_y + 7 - y),
+                    (true, true) => (tile_x + 7 - x, tile_y + 7 - y),
+                };
+                if pixel_y < 240 {
+                    let shown = ppu.scanline_shows_sprites(pixel_y)
+                        && (pixel_x >= 8 || ppu.scanline_shows_leftmost_sprites(pixel_y));
+                    if !shown {
+                        continue;
+                    }
+                    if sprite.priority
+                        && pixel_x < 256
+                        && background_opaque[pixel_y * 256 + pixel_x]
+                    {
+                        continue;
+                    }
                 }
+                set_pixel(pixel_x, pixel_y, color_index)
             }
         }
     }
 }
 
-fn background_pallet(ppu: &Ppu, tile_column: usize, tile_row: usize) -> [u8; 4] {
+// Gives the color index of every pixel, along with its 2-bit color value (0 is transparent)
+fn render_nametable<F>(ppu: &Ppu, nametable_addr: u16, mut set_pixel: F)
+where
+    F: FnMut(usize, usize, u8, u8),
+{
+    let bank = ppu.control_register_background_pattern_address();
+
+    for i in 0..0x03C0 {
+        let tile = ppu.read_nametable_at(nametable_addr + i as u16) as u16;
+        let tile_column = i % 32;
+        let tile_row = i / 32;
+        let tile = read_tile(ppu, bank + tile * 16);
+        let palette = background_pallet(ppu, nametable_addr, tile_column, tile_row);
+
+        for_each_tile_pixel(&tile, |x, y, value| {
+            set_pixel(
+                tile_column * 8 + x,
+                tile_row * 8 + y,
+                palette[value as usize],
+                value,
+            )
+        });
+    }
+}
+
+/// Whether the background pixel at x of the given scanline is opaque, scrolled like
+/// `render_scanline` does it. The PPU uses it for sprite 0 hits.
+pub(crate) fn is_background_pixel_opaque(ppu: &Ppu, x: usize, scanline: u16) -> bool {
+    let (world_x, world_y) = background_world_position(ppu, x, scanline);
+    let ([lo, hi], _) = background_tile_row(ppu, world_x, world_y);
+    let bit = 7 - world_x % 8;
+    (lo | hi) >> bit & 1 == 1
+}
+
+// Position of a pixel of the given scanline in the 512x480 area of the four nametables,
+// wrapping around
+fn background_world_position(ppu: &Ppu, x: usize, scanline: u16) -> (usize, usize) {
+    let world_x = (ppu.scanline_scroll_x() as usize + x) % 512;
+    (world_x, ppu.scanline_scroll_y(scanline) as usize)
+}
+
+// The two bit planes and the palette of the background tile row at the given position
+// of the 512x480 area of the four nametables
+fn background_tile_row(ppu: &Ppu, world_x: usize, world_y: usize) -> ([u8; 2], [u8; 4]) {
+    let nametable_addr = NAMETABLE_ADDRESSES[world_x / 256 + world_y / 240 * 2];
+    let tile_column = world_x % 256 / 8;
+    let tile_row = world_y % 240 / 8;
+    let tile = ppu.read_nametable_at(nametable_addr + (tile_row * 32 + tile_column) as u16);
+    let addr =
+        ppu.control_register_background_pattern_address() + tile as u16 * 16 + (world_y % 8) as u16;
+    (
+        [ppu.read_chr(addr), ppu.read_chr(addr + 8)],
+        background_pallet(ppu, nametable_addr, tile_column, tile_row),
+    )
+}
+
+// Decodes the two bit planes of a tile, giving the 2-bit color value of each of its pixels
+fn for_each_tile_pixel<F>(tile: &[u8; 16], mut pixel: F)
+where
+    F: FnMut(usize, usize, u8),
+{
+    for y in 0..=7 {
+        let mut upper = tile[y];
+        let mut lower = tile[y + 8];
+
+        for x in (0..=7).rev() {
+            let value = (1 & lower) << 1 | (1 & upper);
+            upper >>= 1;
+            lower >>= 1;
+            pixel(x, y, value)
+        }
+    }
+}
+
+fn read_tile(ppu: &Ppu, addr: u16) -> [u8; 16] {
+    let mut tile = [0; 16];
+    for (i, byte) in tile.iter_mut().enumerate() {
+        *byte = ppu.read_chr(addr + i as u16);
+    }
+    tile
+}
+
+fn background_pallet(
+    ppu: &Ppu,
+    nametable_addr: u16,
+    tile_column: usize,
+    tile_row: usize,
+) -> [u8; 4] {
     let attr_table_idx = tile_row / 4 * 8 + tile_column / 4;
-    let attr_byte = ppu.read_vram_at(0x3C0 + attr_table_idx);
+    let attr_byte = ppu.read_nametable_at(nametable_addr + 0x3C0 + attr_table_idx as u16);
 
     let pallet_idx = match (tile_column % 4 / 2, tile_row % 4 / 2) {
         (0, 0) => attr_byte & 0b11,
@@ -122,3 +331,428 @@ fn sprite_palette(ppu: &Ppu, palette_idx: u8) -> [u8; 4] {
         ppu.read_palette_table_at(start + 2),
     ]
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nes::cartridge::MirroringMode;
+    use crate::nes::render::frame::Frame;
+    use std::collections::HashMap;
+
+    fn write_ppu_memory(ppu: &mut Ppu, addr: u16, data: &[u8]) {
+        ppu.write_to_address_register((addr >> 8) as u8);
+        ppu.write_to_address_register((addr & 0xFF) as u8);
+        for byte in data {
+            ppu.write_to_data_register(*byte);
+        }
+    }
+
+    fn run_frame(ppu: &mut Ppu) {
+        while !ppu.tick(255) {}
+    }
+
+    #[test]
+    fn test_render_indexed() {
+        // Tile 0: the first pixel of the first row uses color 1, the rest color 0
+        let mut chr_rom = vec![0; 0x2000];
+        chr_rom[0] = 0b10000000;
+        let mut ppu = Ppu::new(chr_rom, MirroringMode::Horizontal);
+
+        write_ppu_memory(&mut ppu, 0x3F00, &[0x0F, 0x21]);
+        write_ppu_memory(&mut ppu, 0x3F11, &[0x30]);
+
+        let mut oam = [0xFF; 256]; // every sprite off-screen...
+        oam[0..4].copy_from_slice(&[16, 0, 0, 16]); // ...but sprite 0 at (16, 16)
+        ppu.write_to_oam_dma_register(&oam);
+        ppu.write_to_mask_register(0b00011110); // show background and sprites
+        run_frame(&mut ppu);
+
+        let mut output = Box::new([0; 256 * 240]);
+        render_indexed(&ppu, &mut output);
+        assert_eq!(output[0], 0x21);
+        assert_eq!(output[1], 0x0F);
+        assert_eq!(output[8], 0x21); // next tile
+        assert_eq!(output[16 * 256 + 16], 0x30);
+        assert_eq!(output[16 * 256 + 17], 0x0F); // transparent sprite pixel
+
+        ppu.write_to_mask_register(0b10111110); // emphasize red and blue
+        run_frame(&mut ppu);
+        render_indexed(&ppu, &mut output);
+        assert_eq!(output[0], 0b101_000000 | 0x21);
+        assert_eq!(output[1], 0b101_000000 | 0x0F);
+    }
+
+    #[test]
+    fn test_render_sprite_behind_background() {
+        let mut chr_rom = vec![0; 0x2000];
+        chr_rom[0..8].copy_from_slice(&[0xFF; 8]); // tile 0 is fully color 1, tile 1 is empty
+        let mut ppu = Ppu::new(chr_rom, MirroringMode::Horizontal);
+        write_ppu_memory(&mut ppu, 0x3F00, &[0x0F, 0x21]);
+        write_ppu_memory(&mut ppu, 0x3F11, &[0x30]);
+        write_ppu_memory(&mut ppu, 0x2000 + 4 * 32 + 4, &[1]); // transparent tile at (32, 32)
+
+        let mut oam = [0xFF; 256];
+        oam[0..4].copy_from_slice(&[16, 0, 0b0010_0000, 16]); // behind the background
+        oam[4..8].copy_from_slice(&[16, 0, 0, 48]); // in front of it
+        oam[8..12].copy_from_slice(&[32, 0, 0b0010_0000, 32]); // behind, on the empty tile
+        ppu.write_to_oam_dma_register(&oam);
+        ppu.write_to_mask_register(0b00011110); // show background and sprites
+        run_frame(&mut ppu);
+
+        let mut output = Box::new([0; 256 * 240]);
+        render_indexed(&ppu, &mut output);
+        assert_eq!(output[16 * 256 + 16], 0x21);
+        assert_eq!(output[16 * 256 + 48], 0x30);
+        assert_eq!(output[32 * 256 + 32], 0x30);
+
+        // Nothing to hide behind when the background is not shown
+        ppu.write_to_mask_register(0b00010110);
+        run_frame(&mut ppu);
+        render_indexed(&ppu, &mut output);
+        assert_eq!(output[16 * 256 + 16], 0x30);
+    }
+
+    #[test]
+    fn test_render_scanline_honors_mid_frame_scroll() {
+        let mut chr_rom = vec![0; 0x2000];
+        chr_rom[16..24].copy_from_slice(&[0xFF; 8]); // tile 1 is fully color 1, tile 0 empty
+        let mut ppu = Ppu::new(chr_rom, MirroringMode::Vertical);
+        write_ppu_memory(&mut ppu, 0x3F00, &[0x0F, 0x21]);
+        write_ppu_memory(&mut ppu, 0x2400, &[1; 0x3C0]); // the second nametable is all tile 1
+        ppu.write_to_control_register(0); // the address writes selected the second nametable
+        ppu.write_to_mask_register(0b00001010); // show background
+
+        while ppu.scanline() != 120 {
+            ppu.tick(1);
+        }
+        // Scrolling half a screen right brings in the left half of the second nametable
+        ppu.write_to_scroll_register(128);
+        ppu.write_to_scroll_register(0);
+        run_frame(&mut ppu);
+
+        let frame = ppu.frame();
+        let backdrop = palette::SYSTEM_PALETTE[0x0F];
+        let color = palette::SYSTEM_PALETTE[0x21];
+        // Scanline 120 got its horizontal scroll at dot 257 of scanline 119, before the write
+        assert_eq!(frame.get_pixel(0, 120), backdrop);
+        assert_eq!(frame.get_pixel(255, 120), backdrop);
+        assert_eq!(frame.get_pixel(127, 121), backdrop);
+        assert_eq!(frame.get_pixel(128, 121), color);
+        assert_eq!(frame.get_pixel(255, 239), color);
+
+        // The full-frame renderer only sees the scroll at the end of the frame
+        let mut full_frame = Frame::new();
+        render(&ppu, &mut full_frame);
+        assert_ne!(full_frame.data(), frame.data());
+    }
+
+    #[test]
+    fn test_render_scanline_latches_vertical_scroll() {
+        let mut chr_rom = vec![0; 0x2000];
+        chr_rom[16..24].copy_from_slice(&[0xFF; 8]); // tile 1 is fully color 1, tile 0 empty
+        let mut ppu = Ppu::new(chr_rom, MirroringMode::Horizontal);
+        write_ppu_memory(&mut ppu, 0x3F00, &[0x0F, 0x21]);
+        write_ppu_memory(&mut ppu, 0x2000 + 14 * 32, &[1; 64]); // rows 112-127 are tile 1
+        ppu.write_to_control_register(0);
+        ppu.write_to_scroll_register(0);
+        ppu.write_to_scroll_register(0);
+        ppu.write_to_mask_register(0b00001010); // show background
+        run_frame(&mut ppu);
+
+        // 0x2005 only writes t, the new vertical scroll waits for the next frame
+        while ppu.scanline() != 100 {
+            ppu.tick(1);
+        }
+        ppu.write_to_scroll_register(0);
+        ppu.write_to_scroll_register(16);
+        run_frame(&mut ppu);
+        let backdrop = palette::SYSTEM_PALETTE[0x0F];
+        let color = palette::SYSTEM_PALETTE[0x21];
+        assert_eq!(ppu.frame().get_pixel(0, 100), backdrop);
+        assert_eq!(ppu.frame().get_pixel(0, 112), color);
+
+        // A 0x2006 write copies t to v, the scanlines after it start from the new row
+        while ppu.scanline() != 50 {
+            ppu.tick(1);
+        }
+        ppu.write_to_address_register(0x01);
+        ppu.write_to_address_register(0xC0); // coarse Y 14, row 112
+        run_frame(&mut ppu);
+        let frame = ppu.frame();
+        assert_eq!(frame.get_pixel(0, 49), backdrop);
+        assert_eq!(frame.get_pixel(0, 50), backdrop);
+        assert_eq!(frame.get_pixel(0, 51), color);
+        assert_eq!(frame.get_pixel(0, 66), color);
+        assert_eq!(frame.get_pixel(0, 67), backdrop);
+
+        // The pre-render scanline reloads the vertical scroll from t
+        run_frame(&mut ppu);
+        let frame = ppu.frame();
+        assert_eq!(frame.get_pixel(0, 0), color);
+        assert_eq!(frame.get_pixel(0, 15), color);
+        assert_eq!(frame.get_pixel(0, 16), backdrop);
+    }
+
+    #[test]
+    fn test_render_scanline_8x16_sprites() {
+        let mut chr_rom = vec![0; 0x2000];
+        chr_rom[48..56].copy_from_slice(&[0xFF; 8]); // tile 3 is fully color 1, tile 2 empty
+        let mut ppu = Ppu::new(chr_rom, MirroringMode::Horizontal);
+        write_ppu_memory(&mut ppu, 0x3F00, &[0x0F]);
+        write_ppu_memory(&mut ppu, 0x3F11, &[0x30]);
+
+        let mut oam = [0xFF; 256];
+        oam[0..4].copy_from_slice(&[16, 2, 0, 16]); // tiles 2 and 3
+        oam[4..8].copy_from_slice(&[16, 2, 0b1000_0000, 32]); // flipped vertically
+        ppu.write_to_oam_dma_register(&oam);
+        ppu.write_to_control_register(0b0010_0000); // 8x16 sprites
+        ppu.write_to_mask_register(0b00010100); // show sprites
+        run_frame(&mut ppu);
+
+        let frame = ppu.frame();
+        let backdrop = palette::SYSTEM_PALETTE[0x0F];
+        let color = palette::SYSTEM_PALETTE[0x30];
+        assert_eq!(frame.get_pixel(16, 16), backdrop);
+        assert_eq!(frame.get_pixel(16, 24), color);
+        assert_eq!(frame.get_pixel(16, 31), color);
+        assert_eq!(frame.get_pixel(32, 16), color);
+        assert_eq!(frame.get_pixel(32, 23), color);
+        assert_eq!(frame.get_pixel(32, 24), backdrop);
+        assert_eq!(frame.get_pixel(16, 32), backdrop);
+    }
+
+    #[test]
+    fn test_render_background_disabled_mid_frame() {
+        let mut chr_rom = vec![0; 0x2000];
+        chr_rom[0..8].copy_from_slice(&[0xFF; 8]); // tile 0 is fully color 1
+        let mut ppu = Ppu::new(chr_rom, MirroringMode::Horizontal);
+        write_ppu_memory(&mut ppu, 0x3F00, &[0x0F, 0x21]);
+
+        ppu.write_to_mask_register(0b00001010); // show background
+        while ppu.scanline() != 100 {
+            ppu.tick(255);
+        }
+        ppu.write_to_mask_register(0); // disable rendering at scanline 100
+        run_frame(&mut ppu);
+
+        let mut output = Box::new([0; 256 * 240]);
+        render_indexed(&ppu, &mut output);
+        assert!(output[..100 * 256].iter().all(|pixel| *pixel == 0x21));
+        assert!(output[100 * 256..].iter().all(|pixel| *pixel == 0x0F));
+    }
+
+    #[test]
+    fn test_render_left_column_clipping() {
+        let mut chr_rom = vec![0; 0x2000];
+        chr_rom[0..8].copy_from_slice(&[0xFF; 8]); // tile 0 is fully color 1
+        let mut ppu = Ppu::new(chr_rom, MirroringMode::Horizontal);
+        write_ppu_memory(&mut ppu, 0x3F00, &[0x0F, 0x21]);
+        write_ppu_memory(&mut ppu, 0x3F11, &[0x30]);
+
+        let mut oam = [0xFF; 256];
+        oam[0..4].copy_from_slice(&[16, 0, 0, 4]); // sprite 0 straddles the left column
+        ppu.write_to_oam_dma_register(&oam);
+        ppu.write_to_mask_register(0b00011000); // show both, but not in the left column
+        run_frame(&mut ppu);
+
+        let mut output = Box::new([0; 256 * 240]);
+        render_indexed(&ppu, &mut output);
+        assert_eq!(output[7], 0x0F);
+        assert_eq!(output[8], 0x21);
+        assert_eq!(output[16 * 256 + 7], 0x0F);
+        assert_eq!(output[16 * 256 + 8], 0x30);
+
+        ppu.write_to_mask_register(0b00000110); // left column only, but both layers hidden
+        run_frame(&mut ppu);
+        render_indexed(&ppu, &mut output);
+        assert!(output.iter().all(|pixel| *pixel == 0x0F));
+    }
+
+    #[test]
+    fn test_render_greyscale_then_emphasis() {
+        let mut ppu = Ppu::new(vec![0; 0x2000], MirroringMode::Horizontal);
+        write_ppu_memory(&mut ppu, 0x3F00, &[0x21]);
+        ppu.write_to_mask_register(0b00101001); // greyscale, emphasize red, show background
+        run_frame(&mut ppu);
+
+        let mut frame = Frame::new();
+        render(&ppu, &mut frame);
+        // 0x21 turns into the 0x20 white, then green and blue are attenuated
+        let white = palette::SYSTEM_PALETTE[0x20];
+        assert_eq!(white, (0xFF, 0xFF, 0xFF));
+        assert_eq!(&frame.data()[0..3], &[0xFF, 0xD0, 0xD0]);
+
+        let mut output = Box::new([0; 256 * 240]);
+        render_indexed(&ppu, &mut output);
+        assert_eq!(output[0], 0b001_000000 | 0x20);
+    }
+
+    #[test]
+    fn test_render_to_custom_pixel_sink() {
+        struct Histogram(HashMap<(u8, u8, u8), usize>);
+
+        impl PixelSink for Histogram {
+            fn set_pixel(&mut self, x: usize, y: usize, rgb: (u8, u8, u8)) {
+                if x < 256 && y < 240 {
+                    *self.0.entry(rgb).or_insert(0) += 1;
+                }
+            }
+        }
+
+        // Tile 0: the first row is color 1, the rest color 0
+        let mut chr_rom = vec![0; 0x2000];
+        chr_rom[0] = 0xFF;
+        let mut ppu = Ppu::new(chr_rom, MirroringMode::Horizontal);
+        write_ppu_memory(&mut ppu, 0x3F00, &[0x0F, 0x30]);
+        ppu.write_to_mask_register(0b00001010); // show background
+        run_frame(&mut ppu);
+
+        let mut histogram = Histogram(HashMap::new());
+        render(&ppu, &mut histogram);
+        let white = palette::SYSTEM_PALETTE[0x30];
+        let black = palette::SYSTEM_PALETTE[0x0F];
+        // One row of pixels per row of tiles
+        assert_eq!(histogram.0[&white], 256 * 30);
+        assert_eq!(histogram.0[&black], 256 * 240 - 256 * 30);
+    }
+
+    #[test]
+    fn test_render_greyscale_and_emphasis_apply_to_sprites() {
+        // Tile 0: the first pixel of the first row uses color 1
+        let mut chr_rom = vec![0; 0x2000];
+        chr_rom[0] = 0b10000000;
+        let mut ppu = Ppu::new(chr_rom, MirroringMode::Horizontal);
+        write_ppu_memory(&mut ppu, 0x3F00, &[0x0F]);
+        write_ppu_memory(&mut ppu, 0x3F11, &[0x31]);
+
+        let mut oam = [0xFF; 256];
+        oam[0..4].copy_from_slice(&[16, 0, 0, 16]);
+        ppu.write_to_oam_dma_register(&oam);
+        ppu.write_to_mask_register(0b10011111); // greyscale, emphasize blue, show everything
+        run_frame(&mut ppu);
+
+        let mut frame = Frame::new();
+        render(&ppu, &mut frame);
+        // 0x31 turns into the 0x30 white, then red and green are attenuated
+        assert_eq!(frame.get_pixel(16, 16), (0xD0, 0xD0, 0xFF));
+    }
+
+    #[test]
+    fn test_emphasize() {
+        let color = (100, 100, 100);
+        assert_eq!(palette::emphasize(color, 0), color);
+        assert_eq!(palette::emphasize(color, 0b001), (100, 81, 81));
+        assert_eq!(palette::emphasize(color, 0b011), (81, 81, 66));
+        assert_eq!(palette::emphasize(color, 0b111), (66, 66, 66));
+    }
+
+    #[test]
+    fn test_render_pattern_table() {
+        let mut chr_rom = vec![0; 0x2000];
+        chr_rom[16] = 0b10000000; // tile 1, first pixel: color 1
+        chr_rom[0x1000 + 17 * 16 + 8] = 0b00000001; // table 1, tile 17, last pixel: color 2
+        let ppu = Ppu::new(chr_rom, MirroringMode::Horizontal);
+        let colors = [0x0F, 0x21, 0x16, 0x30];
+
+        let mut frame = Frame::with_size(128, 128);
+        render_pattern_table(&ppu, 0, colors, &mut frame);
+        assert_eq!(frame.get_pixel(8, 0), palette::SYSTEM_PALETTE[0x21]);
+        assert_eq!(frame.get_pixel(9, 0), palette::SYSTEM_PALETTE[0x0F]);
+        assert_eq!(frame.get_pixel(15, 8), palette::SYSTEM_PALETTE[0x0F]);
+
+        render_pattern_table(&ppu, 1, colors, &mut frame);
+        assert_eq!(frame.get_pixel(8, 0), palette::SYSTEM_PALETTE[0x0F]);
+        assert_eq!(frame.get_pixel(15, 8), palette::SYSTEM_PALETTE[0x16]);
+    }
+
+    #[test]
+    fn test_render_nametables() {
+        let mut chr_rom = vec![0; 0x2000];
+        chr_rom[16..24].copy_from_slice(&[0xFF; 8]); // tile 1 is fully color 1
+        let mut ppu = Ppu::new(chr_rom, MirroringMode::Horizontal);
+        write_ppu_memory(&mut ppu, 0x3F00, &[0x0F, 0x21]);
+        write_ppu_memory(&mut ppu, 0x2000, &[1]); // top nametables (mirrored)
+        write_ppu_memory(&mut ppu, 0x2801, &[1]); // bottom nametables (mirrored)
+
+        let mut frame = Frame::with_size(512, 480);
+        render_nametables(&ppu, &mut frame);
+        assert_eq!(frame.width(), 512);
+        assert_eq!(frame.height(), 480);
+        assert_eq!(frame.data().len(), 512 * 480 * 3);
+
+        let pixel = |x: usize, y: usize| {
+            let base = (y * 512 + x) * 3;
+            let data = frame.data();
+            (data[base], data[base + 1], data[base + 2])
+        };
+        let backdrop = palette::SYSTEM_PALETTE[0x0F];
+        let color = palette::SYSTEM_PALETTE[0x21];
+        // Top-left tile of every quadrant, then the tile next to it
+        assert_eq!((pixel(0, 0), pixel(8, 0)), (color, backdrop));
+        assert_eq!((pixel(256, 0), pixel(264, 0)), (color, backdrop));
+        assert_eq!((pixel(0, 240), pixel(8, 240)), (backdrop, color));
+        assert_eq!((pixel(256, 240), pixel(264, 240)), (backdrop, color));
+    }
+
+    #[test]
+    fn test_render_nametables_follows_mirroring() {
+        let mut chr_rom = vec![0; 0x2000];
+        chr_rom[16..24].copy_from_slice(&[0xFF; 8]); // tile 1 is fully color 1
+        let backdrop = palette::SYSTEM_PALETTE[0x0F];
+        let color = palette::SYSTEM_PALETTE[0x21];
+
+        // Vertical mirroring: the left nametables are the same, and so are the right ones
+        let mut ppu = Ppu::new(chr_rom.clone(), MirroringMode::Vertical);
+        write_ppu_memory(&mut ppu, 0x3F00, &[0x0F, 0x21]);
+        write_ppu_memory(&mut ppu, 0x2000, &[1]);
+        write_ppu_memory(&mut ppu, 0x2401, &[1]);
+        let mut frame = Frame::with_size(512, 480);
+        render_nametables(&ppu, &mut frame);
+        assert_eq!(
+            (frame.get_pixel(0, 0), frame.get_pixel(8, 0)),
+            (color, backdrop)
+        );
+        assert_eq!(
+            (frame.get_pixel(0, 240), frame.get_pixel(8, 240)),
+            (color, backdrop)
+        );
+        assert_eq!(
+            (frame.get_pixel(256, 0), frame.get_pixel(264, 0)),
+            (backdrop, color)
+        );
+        assert_eq!(
+            (frame.get_pixel(256, 240), frame.get_pixel(264, 240)),
+            (backdrop, color)
+        );
+
+        // Four-screen: only the nametable that was written to has the tile
+        let mut ppu = Ppu::new(chr_rom, MirroringMode::FourScreen);
+        write_ppu_memory(&mut ppu, 0x3F00, &[0x0F, 0x21]);
+        write_ppu_memory(&mut ppu, 0x2C00, &[1]);
+        render_nametables(&ppu, &mut frame);
+        assert_eq!(frame.get_pixel(256, 240), color);
+        for (x, y) in [(0, 0), (256, 0), (0, 240)].iter() {
+            assert_eq!(frame.get_pixel(*x, *y), backdrop);
+        }
+    }
+
+    #[test]
+    fn test_render_out_of_range_palette_entries() {
+        // Palette RAM written by a corrupted ROM can hold values past the 64 system colors
+        let mut chr_rom = vec![0; 0x2000];
+        chr_rom[0..16].copy_from_slice(&[0xFF; 16]); // tile 0 is fully color 3
+        let mut ppu = Ppu::new(chr_rom, MirroringMode::Horizontal);
+        write_ppu_memory(&mut ppu, 0x3F00, &[0xFF; 32]);
+        ppu.write_to_mask_register(0b00011110); // show background and sprites
+        run_frame(&mut ppu);
+
+        let mut frame = Frame::new();
+        render(&ppu, &mut frame);
+        assert_eq!(frame.get_pixel(0, 0), palette::SYSTEM_PALETTE[0x3F]);
+        let mut output = Box::new([0; 256 * 240]);
+        render_indexed(&ppu, &mut output);
+        assert_eq!(output[0], 0x3F);
+        render_nametables(&ppu, &mut Frame::with_size(512, 480));
+        render_pattern_table(&ppu, 0, [0xFF; 4], &mut Frame::with_size(128, 128));
+    }
+}
@@ -16,3 +16,19 @@ pub static SYSTEM_PALETTE: [(u8, u8, u8); 64] = [
     (0xFF, 0xEF, 0xA6), (0xFF, 0xF7, 0x9C), (0xD7, 0xE8, 0x95), (0xA6, 0xED, 0xAF), (0xA2, 0xF2, 0xDA),
     (0x99, 0xFF, 0xFC), (0xDD, 0xDD, 0xDD), (0x11, 0x11, 0x11), (0x11, 0x11, 0x11)
 ];
+
+// Each emphasis bit darkens the two other color channels
+const EMPHASIS_ATTENUATION: f32 = 0.816;
+
+/// Applies the mask register's color emphasis bits (shifted down to bits 0-2, as BGR) to a color
+pub fn emphasize(rgb: (u8, u8, u8), emphasis: u8) -> (u8, u8, u8) {
+    let attenuate = |channel: u8, channel_bit: u8| {
+        let attenuations = (emphasis & 0b111 & !channel_bit).count_ones() as i32;
+        (channel as f32 * EMPHASIS_ATTENUATION.powi(attenuations)) as u8
+    };
+    (
+        attenuate(rgb.0, 0b001),
+        attenuate(rgb.1, 0b010),
+        attenuate(rgb.2, 0b100),
+    )
+}
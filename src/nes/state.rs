@@ -0,0 +1,213 @@
+//! Save states: the CPU, RAM, PPU and mapper state flattened to bytes, e.g. for bug reports.
+//! The APU and the controllers aren't part of it, a loaded state keeps the ones of the
+//! console it is loaded into.
+use std::convert::TryInto;
+use std::error::Error;
+use std::fmt;
+
+const STATE_SIGNATURE: [u8; 4] = [b'P', b'H', b'S', 0x1A];
+const STATE_VERSION: u8 = 1;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum StateError {
+    InvalidSignature,
+    UnsupportedVersion(u8),
+    /// The data ends before all of the state was read
+    TruncatedData,
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StateError::InvalidSignature => write!(f, "Data is not a save state"),
+            StateError::UnsupportedVersion(version) => {
+                write!(f, "Save state version {} not supported", version)
+            }
+            StateError::TruncatedData => write!(f, "Save state data is truncated"),
+        }
+    }
+}
+
+impl Error for StateError {}
+
+/// A snapshot of the machine, taken with `Console::save_state`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MachineState {
+    data: Vec<u8>,
+}
+
+impl MachineState {
+    pub(crate) fn new(data: Vec<u8>) -> Self {
+        MachineState { data }
+    }
+
+    pub(crate) fn reader(&self) -> StateReader<'_> {
+        StateReader::new(&self.data)
+    }
+
+    /// The state as a file: a signature and version, then the state itself
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(STATE_SIGNATURE.len() + 1 + self.data.len());
+        bytes.extend_from_slice(&STATE_SIGNATURE);
+        bytes.push(STATE_VERSION);
+        bytes.extend_from_slice(&self.data);
+        bytes
+    }
+
+    /// Reads back a state saved with `to_bytes`
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, StateError> {
+        if bytes.len() < STATE_SIGNATURE.len() + 1 {
+            return Err(StateError::TruncatedData);
+        }
+        if bytes[0..4] != STATE_SIGNATURE {
+            return Err(StateError::InvalidSignature);
+        }
+        if bytes[4] != STATE_VERSION {
+            return Err(StateError::UnsupportedVersion(bytes[4]));
+        }
+        Ok(MachineState::new(bytes[5..].to_vec()))
+    }
+}
+
+/// Appends the state of each component, read back in the same order by `StateReader`.
+/// Numbers are little endian.
+#[derive(Default)]
+pub struct StateWriter {
+    data: Vec<u8>,
+}
+
+impl StateWriter {
+    pub fn new() -> Self {
+        StateWriter { data: Vec::new() }
+    }
+
+    pub fn write_u8(&mut self, value: u8) {
+        self.data.push(value);
+    }
+
+    pub fn write_bool(&mut self, value: bool) {
+        self.write_u8(value as u8);
+    }
+
+    pub fn write_u16(&mut self, value: u16) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_u32(&mut self, value: u32) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_u64(&mut self, value: u64) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    /// Writes the length first, so `read_bytes` doesn't need to know it
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        self.write_u32(bytes.len() as u32);
+        self.data.extend_from_slice(bytes);
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.data
+    }
+
+    pub fn into_state(self) -> MachineState {
+        MachineState::new(self.into_bytes())
+    }
+}
+
+pub struct StateReader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> StateReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        StateReader { data }
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], StateError> {
+        if self.data.len() < len {
+            return Err(StateError::TruncatedData);
+        }
+        let (taken, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(taken)
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8, StateError> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn read_bool(&mut self) -> Result<bool, StateError> {
+        Ok(self.read_u8()? != 0)
+    }
+
+    pub fn read_u16(&mut self) -> Result<u16, StateError> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    pub fn read_u32(&mut self) -> Result<u32, StateError> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    pub fn read_u64(&mut self) -> Result<u64, StateError> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    pub fn read_bytes(&mut self) -> Result<&'a [u8], StateError> {
+        let len = self.read_u32()? as usize;
+        self.take(len)
+    }
+
+    /// Same as `read_bytes`, but into a buffer of a known size, e.g. the 2KB of CPU RAM
+    pub fn read_into(&mut self, buffer: &mut [u8]) -> Result<(), StateError> {
+        let bytes = self.read_bytes()?;
+        if bytes.len() != buffer.len() {
+            return Err(StateError::TruncatedData);
+        }
+        buffer.copy_from_slice(bytes);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_state_round_trip() {
+        let mut writer = StateWriter::new();
+        writer.write_u8(0x12);
+        writer.write_bool(true);
+        writer.write_u16(0x3456);
+        writer.write_u64(0x0123_4567_89AB_CDEF);
+        writer.write_bytes(&[1, 2, 3]);
+        let state = MachineState::from_bytes(&writer.into_state().to_bytes()).unwrap();
+
+        let mut reader = state.reader();
+        assert_eq!(reader.read_u8(), Ok(0x12));
+        assert_eq!(reader.read_bool(), Ok(true));
+        assert_eq!(reader.read_u16(), Ok(0x3456));
+        assert_eq!(reader.read_u64(), Ok(0x0123_4567_89AB_CDEF));
+        let mut buffer = [0; 3];
+        assert_eq!(reader.read_into(&mut buffer), Ok(()));
+        assert_eq!(buffer, [1, 2, 3]);
+        assert_eq!(reader.read_u8(), Err(StateError::TruncatedData));
+    }
+
+    #[test]
+    fn test_state_rejects_invalid_data() {
+        assert_eq!(
+            MachineState::from_bytes(&[0; 2]),
+            Err(StateError::TruncatedData)
+        );
+        assert_eq!(
+            MachineState::from_bytes(&[0; 8]),
+            Err(StateError::InvalidSignature)
+        );
+        assert_eq!(
+            MachineState::from_bytes(&[b'P', b'H', b'S', 0x1A, 9]),
+            Err(StateError::UnsupportedVersion(9))
+        );
+    }
+}